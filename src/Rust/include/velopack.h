@@ -0,0 +1,120 @@
+/*
+ * Velopack C API
+ *
+ * Embeds the Velopack update logic in-process. All functions are exported from the velopack
+ * shared library (velopack.dll / libvelopack.so / libvelopack.dylib).
+ *
+ * Conventions:
+ * - Functions return a vpkc_error_t. When it is not VPKC_OK, vpkc_get_last_error() returns a
+ *   description of the failure for the calling thread.
+ * - Handles are opaque and must be released with the matching vpkc_free_* function.
+ * - Strings returned through out parameters are UTF-8, owned by the caller, and must be released
+ *   with vpkc_free_string(). Strings passed in must be UTF-8 and are never retained.
+ */
+
+#ifndef VELOPACK_H
+#define VELOPACK_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef enum vpkc_error_t {
+    VPKC_OK = 0,
+    /* A required argument was NULL or was not valid UTF-8. */
+    VPKC_ERROR_INVALID_ARGUMENT = 1,
+    /* The application is not installed, so the updater could not be located. */
+    VPKC_ERROR_NOT_INSTALLED = 2,
+    /* A file system operation failed. */
+    VPKC_ERROR_IO = 3,
+    /* A network request failed. */
+    VPKC_ERROR_NETWORK = 4,
+    /* The operation failed for another reason, see vpkc_get_last_error(). */
+    VPKC_ERROR_FAILED = 5,
+    /* An unexpected internal error occurred. */
+    VPKC_ERROR_PANIC = 6,
+} vpkc_error_t;
+
+/* Checks for, downloads and applies updates for one application. */
+typedef struct vpkc_update_manager_t vpkc_update_manager_t;
+
+/* A release which is available to be downloaded and applied. */
+typedef struct vpkc_update_info_t vpkc_update_info_t;
+
+typedef struct vpkc_update_options_t {
+    /* The channel to check for updates, or NULL for the channel the application was installed from. */
+    const char *explicit_channel;
+    /* Allow updating to a version which is older than the installed version. */
+    bool allow_version_downgrade;
+    /* If not zero, the restarted application must create the file named by the VELOPACK_HEALTH_FILE environment
+       variable within this many seconds after an update is applied, or the previous version is restored. */
+    uint32_t health_check_seconds;
+} vpkc_update_options_t;
+
+/* Explicit application locations, for hosts which do not run from a standard installation. */
+typedef struct vpkc_locator_config_t {
+    const char *root_app_dir;
+    const char *update_exe_path;
+    const char *packages_dir;
+    /* The path of the sq.version manifest of the installed version. */
+    const char *manifest_path;
+} vpkc_locator_config_t;
+
+/* Receives download progress from 0 to 100. */
+typedef void (*vpkc_progress_callback_t)(void *user_data, int16_t progress);
+
+/* Returns the last error which occurred on the calling thread, or an empty string. The pointer is
+ * valid until the next call into this library on the same thread. */
+const char *vpkc_get_last_error(void);
+
+void vpkc_free_string(char *str);
+
+/* Creates an update manager for the currently running application. url_or_path is a http(s) url
+ * or a local directory containing a release feed. options may be NULL. */
+vpkc_error_t vpkc_new_update_manager(const char *url_or_path, const vpkc_update_options_t *options, vpkc_update_manager_t **out_manager);
+
+/* Like vpkc_new_update_manager, but with explicit application locations. */
+vpkc_error_t vpkc_new_update_manager_with_locator(const char *url_or_path,
+                                                  const vpkc_update_options_t *options,
+                                                  const vpkc_locator_config_t *locator,
+                                                  vpkc_update_manager_t **out_manager);
+
+void vpkc_free_update_manager(vpkc_update_manager_t *manager);
+
+vpkc_error_t vpkc_get_app_id(const vpkc_update_manager_t *manager, char **out_app_id);
+
+vpkc_error_t vpkc_get_current_version(const vpkc_update_manager_t *manager, char **out_version);
+
+/* Checks for updates. If there is no update available, VPKC_OK is returned and *out_update is NULL. */
+vpkc_error_t vpkc_check_for_updates(const vpkc_update_manager_t *manager, vpkc_update_info_t **out_update);
+
+vpkc_error_t vpkc_update_info_get_version(const vpkc_update_info_t *update, char **out_version);
+
+bool vpkc_update_info_is_downgrade(const vpkc_update_info_t *update);
+
+void vpkc_free_update_info(vpkc_update_info_t *update);
+
+/* Downloads and verifies the update. progress may be NULL. */
+vpkc_error_t vpkc_download_updates(const vpkc_update_manager_t *manager,
+                                   const vpkc_update_info_t *update,
+                                   vpkc_progress_callback_t progress,
+                                   void *user_data);
+
+/* Launches the updater, which waits for this process to exit and then applies the downloaded update.
+ * The host should exit soon after this returns. restart_args may be NULL if restart_args_count is 0. */
+vpkc_error_t vpkc_wait_exit_then_apply_updates(const vpkc_update_manager_t *manager,
+                                               const vpkc_update_info_t *update,
+                                               bool silent,
+                                               bool restart,
+                                               const char *const *restart_args,
+                                               size_t restart_args_count);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* VELOPACK_H */
@@ -0,0 +1,288 @@
+//! C ABI bindings for the UpdateManager, see include/velopack.h.
+// the safety contract for every pointer argument is documented in the header.
+#![allow(clippy::missing_safety_doc, non_camel_case_types)]
+
+use crate::{
+    bundle,
+    manager::{self, UpdateInfo, UpdateManager, UpdateOptions, VelopackLocator},
+};
+use anyhow::{anyhow, Result};
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_void, CStr, CString},
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    ptr,
+};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum vpkc_error_t {
+    VPKC_OK = 0,
+    VPKC_ERROR_INVALID_ARGUMENT = 1,
+    VPKC_ERROR_NOT_INSTALLED = 2,
+    VPKC_ERROR_IO = 3,
+    VPKC_ERROR_NETWORK = 4,
+    VPKC_ERROR_FAILED = 5,
+    VPKC_ERROR_PANIC = 6,
+}
+
+pub struct vpkc_update_manager_t(UpdateManager);
+
+pub struct vpkc_update_info_t(UpdateInfo);
+
+#[repr(C)]
+pub struct vpkc_update_options_t {
+    pub explicit_channel: *const c_char,
+    pub allow_version_downgrade: bool,
+    pub health_check_seconds: u32,
+}
+
+#[repr(C)]
+pub struct vpkc_locator_config_t {
+    pub root_app_dir: *const c_char,
+    pub update_exe_path: *const c_char,
+    pub packages_dir: *const c_char,
+    pub manifest_path: *const c_char,
+}
+
+pub type vpkc_progress_callback_t = Option<extern "C" fn(user_data: *mut c_void, progress: i16)>;
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+struct CallError(vpkc_error_t, anyhow::Error);
+
+impl From<anyhow::Error> for CallError {
+    fn from(e: anyhow::Error) -> Self {
+        let code = if e.chain().any(|c| c.downcast_ref::<std::io::Error>().is_some()) {
+            vpkc_error_t::VPKC_ERROR_IO
+        } else if e.chain().any(|c| c.downcast_ref::<ureq::Error>().is_some()) {
+            vpkc_error_t::VPKC_ERROR_NETWORK
+        } else {
+            vpkc_error_t::VPKC_ERROR_FAILED
+        };
+        CallError(code, e)
+    }
+}
+
+fn set_last_error(message: &str) {
+    let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = message);
+}
+
+/// Runs the body of an exported function, converting errors and panics into error codes.
+fn call<F: FnOnce() -> Result<(), CallError>>(f: F) -> vpkc_error_t {
+    set_last_error("");
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => vpkc_error_t::VPKC_OK,
+        Ok(Err(CallError(code, e))) => {
+            set_last_error(&format!("{}", e));
+            code
+        }
+        Err(_) => {
+            set_last_error("An unexpected internal error occurred.");
+            vpkc_error_t::VPKC_ERROR_PANIC
+        }
+    }
+}
+
+fn invalid_argument(name: &str) -> CallError {
+    CallError(vpkc_error_t::VPKC_ERROR_INVALID_ARGUMENT, anyhow!("Argument '{}' is null or not valid UTF-8.", name))
+}
+
+unsafe fn to_str<'a>(ptr: *const c_char, name: &str) -> Result<&'a str, CallError> {
+    if ptr.is_null() {
+        return Err(invalid_argument(name));
+    }
+    CStr::from_ptr(ptr).to_str().map_err(|_| invalid_argument(name))
+}
+
+unsafe fn to_ref<'a, T>(ptr: *const T, name: &str) -> Result<&'a T, CallError> {
+    ptr.as_ref().ok_or_else(|| invalid_argument(name))
+}
+
+unsafe fn write_string(out: *mut *mut c_char, value: &str, name: &str) -> Result<(), CallError> {
+    if out.is_null() {
+        return Err(invalid_argument(name));
+    }
+    *out = CString::new(value).map_err(|e| CallError::from(anyhow!(e)))?.into_raw();
+    Ok(())
+}
+
+unsafe fn read_options(options: *const vpkc_update_options_t) -> Result<Option<UpdateOptions>, CallError> {
+    match options.as_ref() {
+        None => Ok(None),
+        Some(o) => {
+            let explicit_channel = if o.explicit_channel.is_null() { None } else { Some(to_str(o.explicit_channel, "explicit_channel")?.to_owned()) };
+            let health_check_timeout = if o.health_check_seconds > 0 { Some(std::time::Duration::from_secs(o.health_check_seconds as u64)) } else { None };
+            Ok(Some(UpdateOptions { explicit_channel, allow_version_downgrade: o.allow_version_downgrade, health_check_timeout }))
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn vpkc_get_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ptr())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vpkc_free_string(str: *mut c_char) {
+    if !str.is_null() {
+        drop(CString::from_raw(str));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vpkc_new_update_manager(
+    url_or_path: *const c_char,
+    options: *const vpkc_update_options_t,
+    out_manager: *mut *mut vpkc_update_manager_t,
+) -> vpkc_error_t {
+    call(|| {
+        let url_or_path = to_str(url_or_path, "url_or_path")?;
+        let options = read_options(options)?;
+        if out_manager.is_null() {
+            return Err(invalid_argument("out_manager"));
+        }
+        let locator = VelopackLocator::auto_locate().map_err(|e| CallError(vpkc_error_t::VPKC_ERROR_NOT_INSTALLED, e))?;
+        let um = UpdateManager::new_with_boxed_source(manager::create_source(url_or_path), options, locator)?;
+        *out_manager = Box::into_raw(Box::new(vpkc_update_manager_t(um)));
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vpkc_new_update_manager_with_locator(
+    url_or_path: *const c_char,
+    options: *const vpkc_update_options_t,
+    locator: *const vpkc_locator_config_t,
+    out_manager: *mut *mut vpkc_update_manager_t,
+) -> vpkc_error_t {
+    call(|| {
+        let url_or_path = to_str(url_or_path, "url_or_path")?;
+        let options = read_options(options)?;
+        let config = to_ref(locator, "locator")?;
+        if out_manager.is_null() {
+            return Err(invalid_argument("out_manager"));
+        }
+        let manifest_path = to_str(config.manifest_path, "manifest_path")?;
+        let nuspec = std::fs::read_to_string(manifest_path).map_err(|e| anyhow!("Unable to read manifest '{}' ({}).", manifest_path, e))?;
+        let root_app_dir = PathBuf::from(to_str(config.root_app_dir, "root_app_dir")?);
+        let manifest = bundle::read_manifest_from_string(&nuspec)?;
+        let locator = VelopackLocator {
+            update_exe_path: PathBuf::from(to_str(config.update_exe_path, "update_exe_path")?),
+            packages_dir: PathBuf::from(to_str(config.packages_dir, "packages_dir")?),
+            state_dir: crate::shared::get_app_state_dir(&manifest.id, &root_app_dir),
+            root_app_dir,
+            manifest,
+        };
+        let um = UpdateManager::new_with_boxed_source(manager::create_source(url_or_path), options, locator)?;
+        *out_manager = Box::into_raw(Box::new(vpkc_update_manager_t(um)));
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vpkc_free_update_manager(manager: *mut vpkc_update_manager_t) {
+    if !manager.is_null() {
+        drop(Box::from_raw(manager));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vpkc_get_app_id(manager: *const vpkc_update_manager_t, out_app_id: *mut *mut c_char) -> vpkc_error_t {
+    call(|| {
+        let um = to_ref(manager, "manager")?;
+        write_string(out_app_id, &um.0.get_app_id(), "out_app_id")
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vpkc_get_current_version(manager: *const vpkc_update_manager_t, out_version: *mut *mut c_char) -> vpkc_error_t {
+    call(|| {
+        let um = to_ref(manager, "manager")?;
+        write_string(out_version, &um.0.get_current_version().to_string(), "out_version")
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vpkc_check_for_updates(manager: *const vpkc_update_manager_t, out_update: *mut *mut vpkc_update_info_t) -> vpkc_error_t {
+    call(|| {
+        let um = to_ref(manager, "manager")?;
+        if out_update.is_null() {
+            return Err(invalid_argument("out_update"));
+        }
+        *out_update = match um.0.check_for_updates()? {
+            Some(update) => Box::into_raw(Box::new(vpkc_update_info_t(update))),
+            None => ptr::null_mut(),
+        };
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vpkc_update_info_get_version(update: *const vpkc_update_info_t, out_version: *mut *mut c_char) -> vpkc_error_t {
+    call(|| {
+        let update = to_ref(update, "update")?;
+        write_string(out_version, &update.0.target_full_release.version.to_string(), "out_version")
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vpkc_update_info_is_downgrade(update: *const vpkc_update_info_t) -> bool {
+    update.as_ref().map(|u| u.0.is_downgrade).unwrap_or(false)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vpkc_free_update_info(update: *mut vpkc_update_info_t) {
+    if !update.is_null() {
+        drop(Box::from_raw(update));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vpkc_download_updates(
+    manager: *const vpkc_update_manager_t,
+    update: *const vpkc_update_info_t,
+    progress: vpkc_progress_callback_t,
+    user_data: *mut c_void,
+) -> vpkc_error_t {
+    call(|| {
+        let um = to_ref(manager, "manager")?;
+        let update = to_ref(update, "update")?;
+        um.0.download_updates(&update.0, |p| {
+            if let Some(cb) = progress {
+                cb(user_data, p);
+            }
+        })?;
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vpkc_wait_exit_then_apply_updates(
+    manager: *const vpkc_update_manager_t,
+    update: *const vpkc_update_info_t,
+    silent: bool,
+    restart: bool,
+    restart_args: *const *const c_char,
+    restart_args_count: usize,
+) -> vpkc_error_t {
+    call(|| {
+        let um = to_ref(manager, "manager")?;
+        let update = to_ref(update, "update")?;
+        let mut args = Vec::new();
+        if restart_args_count > 0 {
+            if restart_args.is_null() {
+                return Err(invalid_argument("restart_args"));
+            }
+            for i in 0..restart_args_count {
+                args.push(to_str(*restart_args.add(i), "restart_args")?);
+            }
+        }
+        um.0.wait_exit_then_apply_updates(&update.0.target_full_release, silent, restart, Some(args))?;
+        Ok(())
+    })
+}
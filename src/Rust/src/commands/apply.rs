@@ -1,9 +1,12 @@
 use crate::{
     bundle,
-    shared::{self, bundle::Manifest, OperationWait},
+    shared::{self, bundle::Manifest, events, events::ApplyPhase, hooks::HookRetryLaterError, OperationWait},
 };
 use anyhow::{bail, Result};
-use std::path::PathBuf;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 #[cfg(target_os = "linux")]
 use super::apply_linux_impl::apply_package_impl;
@@ -19,50 +22,115 @@ pub fn apply<'a>(
     wait: OperationWait,
     package: Option<&PathBuf>,
     exe_args: Option<Vec<&str>>,
+    health_check: Option<Duration>,
+    allow_downgrade: bool,
     runhooks: bool,
 ) -> Result<()> {
     shared::operation_wait(wait);
 
+    events::phase(ApplyPhase::Locating);
     let package = package.cloned().map_or_else(|| auto_locate_package(&app, &root_path), Ok);
-    match package {
+    let error_message = match package {
         Ok(package) => {
             info!("Getting ready to apply package to {} ver {}: {}", app.id, app.version, package.to_string_lossy());
-            match apply_package_impl(&root_path, &app, &package, runhooks) {
+            match apply_package_impl(&root_path, &app, &package, allow_downgrade, runhooks) {
                 Ok(applied_app) => {
                     info!("Package version {} applied successfully.", applied_app.version);
+                    let packages_dir = get_packages_dir(&applied_app, &root_path);
+                    if let Err(e) = super::prune_packages(&packages_dir, &applied_app.version, super::PACKAGES_RETAIN_COUNT, false) {
+                        warn!("Failed to clean up packages directory ({}).", e);
+                    }
                     // if successful, we want to restart the new version of the app, which could have different metadata
                     if restart {
-                        shared::start_package(&applied_app, &root_path, exe_args, Some("VELOPACK_RESTART"))?;
+                        events::phase(ApplyPhase::Restarting);
+                        if let Some(timeout) = health_check {
+                            if let Err(e) = super::start_and_wait_until_healthy(&applied_app, root_path, exe_args.clone(), timeout) {
+                                error!("Health check failed: {}", e);
+                                events::result_failure(&recover_from_failed_health_check(root_path, app, &applied_app, exe_args));
+                                bail!("Apply failed, see logs for details.");
+                            }
+                        } else if let Err(e) = shared::start_package(&applied_app, &root_path, exe_args, Some("VELOPACK_RESTART")) {
+                            events::result_failure(&format!("Package applied, but failed to restart the application ({}).", e));
+                            return Err(e);
+                        }
                     }
+                    events::result_success(&applied_app.version);
                     return Ok(());
                 }
                 Err(e) => {
+                    if e.downcast_ref::<HookRetryLaterError>().is_some() {
+                        if let Err(e) = super::record_pending_apply(get_packages_dir(app, root_path), &package) {
+                            warn!("Failed to record the apply to be retried later ({}).", e);
+                        }
+                    }
                     error!("Error applying package: {}", e);
+                    format!("Error applying package: {}", e)
                 }
             }
         }
         Err(e) => {
             error!("Failed to locate package ({}).", e);
+            format!("Failed to locate package ({}).", e)
         }
-    }
+    };
 
     // an error occurred if we're here, but we still want to restart the old version of the app if it was requested
     if restart {
-        shared::start_package(&app, &root_path, exe_args, Some("VELOPACK_RESTART"))?;
+        events::phase(ApplyPhase::Restarting);
+        if let Err(e) = shared::start_package(&app, &root_path, exe_args, Some("VELOPACK_RESTART")) {
+            warn!("Failed to restart the application ({}).", e);
+        }
     }
 
+    events::result_failure(&error_message);
     bail!("Apply failed, see logs for details.");
 }
 
-fn auto_locate_package(app: &Manifest, _root_path: &PathBuf) -> Result<PathBuf> {
-    #[cfg(target_os = "windows")]
-    let packages_dir = app.get_packages_path(_root_path);
-    #[cfg(target_os = "linux")]
-    let packages_dir = format!("/var/tmp/velopack/{}/packages", &app.id);
-    #[cfg(target_os = "macos")]
-    let packages_dir = format!("/tmp/velopack/{}/packages", &app.id);
+/// Stops the version which failed its health check, makes sure it is never applied again, and restores
+/// and restarts the previous version. Returns a description of the outcome.
+fn recover_from_failed_health_check(root_path: &PathBuf, previous: &Manifest, applied: &Manifest, exe_args: Option<Vec<&str>>) -> String {
+    let _ = shared::force_stop_package(root_path);
 
-    info!("Attempting to auto-detect package in: {}", packages_dir);
+    let packages_dir = get_packages_dir(applied, root_path);
+    if let Err(e) = super::record_failed_version(&packages_dir, &applied.version) {
+        warn!("Failed to record version {} as failed ({}).", applied.version, e);
+    }
+
+    match super::restore_previous_version(root_path, previous, applied) {
+        Ok(restored) => {
+            if let Err(e) = shared::start_package(&restored, root_path, exe_args, Some("VELOPACK_RESTART")) {
+                warn!("Failed to restart the application ({}).", e);
+            }
+            format!("Version {} failed its health check, version {} has been restored.", applied.version, restored.version)
+        }
+        Err(e) => {
+            error!("Failed to restore version {} ({}).", previous.version, e);
+            format!("Version {} failed its health check, and restoring version {} failed ({}).", applied.version, previous.version, e)
+        }
+    }
+}
+
+pub fn get_packages_dir(app: &Manifest, root_path: &Path) -> String {
+    shared::get_location(shared::LocationKind::Packages, &app.id, root_path).to_string_lossy().to_string()
+}
+
+fn auto_locate_package(app: &Manifest, root_path: &PathBuf) -> Result<PathBuf> {
+    locate_latest_package(app, root_path, &app.channel).map(|(path, _)| path)
+}
+
+/// Finds the newest package in the packages directory which belongs to the specified channel,
+/// and which this installation is allowed to see given any staged rollout percentage.
+pub fn locate_latest_package(app: &Manifest, root_path: &PathBuf, channel: &str) -> Result<(PathBuf, Manifest)> {
+    let packages_dir = get_packages_dir(app, root_path);
+    // packages are about to be trusted and applied, so make sure nobody else could have put them there
+    if Path::new(&packages_dir).exists() {
+        shared::verify_location(Path::new(&packages_dir))?;
+    }
+    let staged_user_id = shared::get_or_create_staged_user_id(shared::get_app_state_dir(&app.id, root_path), &packages_dir);
+    // packages are checked against the requested channel, which differs from the installed one when switching channels
+    let channel_app = Manifest { channel: channel.to_owned(), ..app.clone() };
+
+    info!("Attempting to auto-detect package in: {} (channel: '{}')", packages_dir, channel);
     let mut package_path: Option<PathBuf> = None;
     let mut package_manifest: Option<Manifest> = None;
 
@@ -72,6 +140,19 @@ fn auto_locate_package(app: &Manifest, _root_path: &PathBuf) -> Result<PathBuf>
                 trace!("Checking package: '{}'", path.to_string_lossy());
                 if let Ok(bun) = bundle::load_bundle_from_file(&path) {
                     if let Ok(mani) = bun.read_manifest() {
+                        // downgrades are rejected when the package is applied, so an older stray package is reported rather than skipped
+                        if let Err(e) = super::verify_package_compatibility(&channel_app, &mani, true) {
+                            info!("Skipping '{}' ({})", path.to_string_lossy(), e);
+                            continue;
+                        }
+                        if super::is_failed_version(&packages_dir, &mani.version) {
+                            info!("Skipping {} (it previously failed its health check).", mani.version);
+                            continue;
+                        }
+                        if !shared::is_staging_match(mani.staging_percentage, staged_user_id.as_deref()) {
+                            info!("Skipping {} (staged to {}%, this install is not in the rollout group yet).", mani.version, mani.staging_percentage.unwrap_or(100.0));
+                            continue;
+                        }
                         if package_manifest.is_none() || mani.version > package_manifest.clone().unwrap().version {
                             info!("Found {}: '{}'", mani.version, path.to_string_lossy());
                             package_manifest = Some(mani);
@@ -83,9 +164,25 @@ fn auto_locate_package(app: &Manifest, _root_path: &PathBuf) -> Result<PathBuf>
         }
     }
 
-    if let Some(p) = package_path {
-        return Ok(p);
+    if let (Some(p), Some(m)) = (package_path, package_manifest) {
+        return Ok((p, m));
     } else {
         bail!("Unable to find/load suitable package. Provide via the --package argument.");
     }
 }
+
+pub(crate) fn is_channel_match(channel: &str, package_channel: &str) -> bool {
+    // an installation which predates channels accepts any package, but a package without a channel is never assumed to be on one
+    channel.is_empty() || channel.eq_ignore_ascii_case(package_channel)
+}
+
+#[test]
+fn test_is_channel_match() {
+    assert!(is_channel_match("", "beta"));
+    assert!(is_channel_match("", ""));
+    assert!(!is_channel_match("beta", ""));
+    assert!(is_channel_match("beta", "beta"));
+    assert!(is_channel_match("Beta", "beta"));
+    assert!(!is_channel_match("stable", "beta"));
+    assert!(!is_channel_match("win", "linux"));
+}
@@ -1,28 +1,195 @@
+use super::{ApplyJournal, JournalKind};
 use crate::shared::{
     self,
     bundle::{self, Manifest},
     dialogs,
+    elevation,
+    events,
+    events::ApplyPhase,
+    hooks::{HookPayload, HookResult},
 };
-use anyhow::{bail, Result};
-use std::{fs, path::PathBuf, process::Command};
+use anyhow::{anyhow, bail, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+const RETAINED_APPIMAGE_NAME: &str = "app.AppImage";
 
-pub fn apply_package_impl<'a>(root_path: &PathBuf, _app: &Manifest, pkg: &PathBuf, _runhooks: bool) -> Result<Manifest> {
+pub fn apply_package_impl<'a>(root_path: &PathBuf, app: &Manifest, pkg: &PathBuf, allow_downgrade: bool, runhooks: bool) -> Result<Manifest> {
     // on linux, the current "dir" is actually an AppImage file which we need to replace.
     info!("Loading bundle from {}", pkg.to_string_lossy());
     let bundle = bundle::load_bundle_from_file(pkg)?;
     let manifest = bundle.read_manifest()?;
-    let temp_path = format!("/var/tmp/velopack_{}", shared::random_string(8));
-    let script_path = format!("/var/tmp/velopack_update_{}.sh", manifest.id);
+    events::phase(ApplyPhase::Verifying);
+    super::verify_package_compatibility(app, &manifest, allow_downgrade)?;
+    crate::logging::set_log_field("to_version", &manifest.version.to_string());
+    let temp_path = get_temp_appimage_path(app, root_path)?;
+    let journal_path = super::get_journal_path(&app.id, root_path)?;
+
+    if runhooks {
+        events::phase_detail(ApplyPhase::Hooks, "--veloapp-obsolete");
+        let payload = HookPayload::new("--veloapp-obsolete", Some(&app.version), &manifest, root_path, false);
+        shared::run_hook(app, root_path, &payload, 15).ensure_continue("--veloapp-obsolete")?;
+    } else {
+        info!("Skipping --veloapp-obsolete hook.");
+    }
 
     let action: Result<()> = (|| {
         info!("Extracting bundle to temp file: {}", temp_path);
-        bundle.extract_zip_predicate_to_path(|z| z.ends_with(".AppImage"), &temp_path)?;
+        events::phase(ApplyPhase::Extracting);
+        let appimage_idx = bundle.find_zip_file(|z| z.ends_with(".AppImage")).ok_or_else(|| anyhow!("Could not find an AppImage in the bundle."))?;
+        bundle.extract_zip_idx_to_path_with_progress(appimage_idx, &temp_path, |p| events::phase_progress(ApplyPhase::Extracting, p))?;
 
         info!("Chmod as executable");
         std::fs::set_permissions(&temp_path, <std::fs::Permissions as std::os::unix::fs::PermissionsExt>::from_mode(0o755))?;
 
+        // nothing is retained if the user cancels, so there is nothing to clean up
+        if !shared::close_processes_locking_path(&app.title, root_path) {
+            bail!("Update cancelled, programs using the application were not closed.");
+        }
+
+        if let Err(e) = retain_current_appimage(root_path, app) {
+            warn!("Not retaining version {} for rollback ({}).", app.version, e);
+        }
+
+        events::phase(ApplyPhase::Swapping);
+        replace_appimage(app, &manifest, &journal_path, &temp_path, root_path)?;
+        refresh_desktop_entry(root_path, &manifest);
+
+        // the hook runs inside the new AppImage, which is now at the root path
+        if runhooks {
+            events::phase_detail(ApplyPhase::Hooks, "--veloapp-updated");
+            let payload = HookPayload::new("--veloapp-updated", Some(&app.version), &manifest, root_path, false);
+            if shared::run_hook(&manifest, root_path, &payload, 15) == HookResult::Abort {
+                return Err(super::rollback_vetoed_update(root_path, app, &manifest));
+            }
+        } else {
+            info!("Skipping --veloapp-updated hook.");
+        }
+        Ok(())
+    })();
+    // if the AppImage was partially replaced, the temp file is left in place for the journal to recover
+    if !journal_path.exists() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    action?;
+    Ok(manifest)
+}
+
+/// Restores a retained version. If `retain` is set, the version it replaces is retained in turn, so the rollback can be undone.
+pub fn rollback_package_impl(root_path: &PathBuf, app: &Manifest, retained_path: &PathBuf, retain: bool, runhooks: bool) -> Result<Manifest> {
+    let nuspec = shared::retry_io(|| fs::read_to_string(retained_path.join("sq.version")))?;
+    let manifest = bundle::read_manifest_from_string(&nuspec)?;
+    let temp_path = get_temp_appimage_path(app, root_path)?;
+    let journal_path = super::get_journal_path(&app.id, root_path)?;
+
+    if runhooks {
+        let payload = HookPayload::new("--veloapp-obsolete", Some(&app.version), &manifest, root_path, false);
+        shared::run_hook(app, root_path, &payload, 15).ensure_continue("--veloapp-obsolete")?;
+    } else {
+        info!("Skipping --veloapp-obsolete hook.");
+    }
+
+    let action: Result<()> = (|| {
+        info!("Copying retained version {} to temp file: {}", manifest.version, temp_path);
+        fs::copy(retained_path.join(RETAINED_APPIMAGE_NAME), &temp_path)?;
+        std::fs::set_permissions(&temp_path, <std::fs::Permissions as std::os::unix::fs::PermissionsExt>::from_mode(0o755))?;
+
+        if !shared::close_processes_locking_path(&app.title, root_path) {
+            bail!("Rollback cancelled, programs using the application were not closed.");
+        }
+
+        if retain {
+            if let Err(e) = retain_current_appimage(root_path, app) {
+                warn!("Not retaining version {} for rollback ({}).", app.version, e);
+            }
+        }
+
+        replace_appimage(app, &manifest, &journal_path, &temp_path, root_path)?;
+        let _ = remove_dir_all::remove_dir_all(retained_path);
+        refresh_desktop_entry(root_path, &manifest);
+
+        if runhooks {
+            // a rollback is not itself rolled back, so the result is only logged
+            let payload = HookPayload::new("--veloapp-updated", Some(&app.version), &manifest, root_path, false);
+            let _ = shared::run_hook(&manifest, root_path, &payload, 15);
+        } else {
+            info!("Skipping --veloapp-updated hook.");
+        }
+        Ok(())
+    })();
+    // if the AppImage was partially replaced, the temp file is left in place for the journal to recover
+    if !journal_path.exists() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    action?;
+    Ok(manifest)
+}
+
+/// The new AppImage is staged in a private dir, so it can't be swapped out before it is moved into place.
+fn get_temp_appimage_path(app: &Manifest, root_path: &Path) -> Result<String> {
+    let staging_dir = shared::get_location_checked(shared::LocationKind::Staging, &app.id, root_path)?;
+    Ok(staging_dir.join(format!("velopack_{}", shared::random_string(8))).to_string_lossy().to_string())
+}
+
+fn refresh_desktop_entry(root_path: &Path, manifest: &Manifest) {
+    // the title or icon may have changed in this version, and the AppImage may have been moved since the entry was created
+    if let Err(e) = shared::refresh_desktop_entry(root_path, manifest) {
+        warn!("Failed to refresh the desktop entry ({}).", e);
+    }
+}
+
+fn retain_current_appimage(root_path: &PathBuf, app: &Manifest) -> Result<()> {
+    // the AppImage can't be read without mounting it, so the manifest next to the updater is used if it describes the same
+    // version. after a vetoed or unhealthy update the updater is still the old version, and the new one is not retained.
+    let nuspec = read_running_manifest()?;
+    let manifest = bundle::read_manifest_from_string(&nuspec)?;
+    if manifest.id != app.id || manifest.version != app.version {
+        bail!("the running updater belongs to {} {}, not the AppImage at {}", manifest.id, manifest.version, root_path.to_string_lossy());
+    }
+
+    let rollback_dir = super::get_rollback_dir(app, root_path)?;
+    let staging_path = rollback_dir.join(format!("tmp_{}", shared::random_string(8)));
+    let result: Result<PathBuf> = (|| {
+        fs::create_dir_all(&staging_path)?;
+        fs::copy(root_path, staging_path.join(RETAINED_APPIMAGE_NAME))?;
+        fs::write(staging_path.join("sq.version"), &nuspec)?;
+        super::retain_version(&rollback_dir, &app.version, &app.version.to_string(), &staging_path)
+    })();
+    if result.is_err() {
+        let _ = remove_dir_all::remove_dir_all(&staging_path);
+    }
+    result.map(|_| ())
+}
+
+/// The manifest of the running version lives next to the updater, inside the mounted AppImage.
+fn read_running_manifest() -> Result<String> {
+    let mut nuspec_path = std::env::current_exe()?;
+    nuspec_path.pop();
+    nuspec_path.push("sq.version");
+    if !nuspec_path.exists() {
+        bail!("Unable to find manifest at {}", nuspec_path.to_string_lossy());
+    }
+    Ok(shared::retry_io(|| fs::read_to_string(&nuspec_path))?)
+}
+
+fn replace_appimage(app: &Manifest, manifest: &Manifest, journal_path: &Path, temp_path: &str, root_path: &PathBuf) -> Result<()> {
+    let journal = ApplyJournal::begin(
+        journal_path,
+        JournalKind::FileReplace,
+        &app.version.to_string(),
+        &manifest.version.to_string(),
+        root_path,
+        None,
+        Path::new(temp_path),
+    )?;
+
+    // set if the target may have been partially overwritten, in which case the journal is kept to finish the job later
+    let mut partially_replaced = false;
+    let action: Result<()> = (|| {
         info!("Moving temp file to target: {}", &root_path.to_string_lossy());
-        let mut result: Result<(), std::io::Error> = std::fs::rename(&temp_path, &root_path);
+        let mut result: Result<(), std::io::Error> = super::fail_point("move_new").and_then(|_| std::fs::rename(&temp_path, &root_path));
         if result.is_ok() {
             info!("AppImage moved successfully to: {}", &root_path.to_string_lossy());
             return Ok(());
@@ -34,37 +201,92 @@ pub fn apply_package_impl<'a>(root_path: &PathBuf, _app: &Manifest, pkg: &PathBu
         // ideally we check against std::io::ErrorKind::CrossesDevices but that is unstable at the moment
         if Some(18) == result_err.raw_os_error() {
             info!("Move failed (cross-device), trying again with a copy.");
-            result = std::fs::copy(&temp_path, &root_path).map(|_| {});
+            result = super::fail_point("copy_new").and_then(|_| std::fs::copy(&temp_path, &root_path)).map(|_| {});
             if result.is_ok() {
                 info!("AppImage copied successfully to: {}", &root_path.to_string_lossy());
                 return Ok(());
             }
             result_err = result.unwrap_err();
+            partially_replaced = result_err.kind() != std::io::ErrorKind::PermissionDenied;
         }
 
-        // if the operation failed with permission denied, let's try again elevated with pkexec
+        // if the operation failed with permission denied, let's try again elevated
         if result_err.kind() == std::io::ErrorKind::PermissionDenied {
             error!("An error occurred {}, will attempt to elevate permissions and try again...", result_err);
             dialogs::ask_user_to_elevate(&manifest)?;
-            let script = format!("#!/bin/sh\nmv -f '{}' '{}'", temp_path, &root_path.to_string_lossy());
-            info!("Writing script for elevation: \n{}", script);
-            fs::write(&script_path, script)?;
-            std::fs::set_permissions(&script_path, <std::fs::Permissions as std::os::unix::fs::PermissionsExt>::from_mode(0o755))?;
-            let args = vec![&script_path];
-            info!("Attempting to elevate: pkexec {:?}", args);
-            let status = Command::new("pkexec").args(args).status()?;
-            if status.success() {
-                info!("AppImage moved (elevated) to {}", &root_path.to_string_lossy());
-                return Ok(());
-            } else {
-                bail!("pkexec exited with status: {}", status);
-            }
+            // mv is run directly rather than via a script, so nothing is left in a shared temp dir for others to tamper with
+            let mv = elevation::find_trusted_program("mv").ok_or_else(|| anyhow!("Unable to find mv in a system directory."))?;
+            elevation::run_elevated(&mv, &["-f", "--", temp_path, &root_path.to_string_lossy()])?;
+            info!("AppImage moved (elevated) to {}", &root_path.to_string_lossy());
+            return Ok(());
         }
 
         bail!("Failed to move the AppImage to target ({})", result_err);
     })();
-    let _ = fs::remove_file(&script_path);
-    let _ = fs::remove_file(&temp_path);
-    action?;
-    Ok(manifest)
+    if action.is_err() && partially_replaced {
+        return action;
+    }
+    journal.complete()?;
+    action
+}
+
+#[cfg(test)]
+fn run_test_replace_appimage(dir: &Path, failures: &[(&'static str, super::InjectedFailure)]) -> (PathBuf, PathBuf, Result<()>) {
+    let root_path = dir.join("MyApp.AppImage");
+    let staging_dir = dir.join("staging");
+    let temp_path = staging_dir.join("velopack_new");
+    let journal_path = staging_dir.join("apply.journal");
+    fs::create_dir_all(&staging_dir).unwrap();
+    fs::write(&root_path, "1.0.0").unwrap();
+    fs::write(&temp_path, "2.0.0").unwrap();
+
+    let mut app = Manifest::default();
+    app.id = "MyApp".to_owned();
+    app.version = semver::Version::new(1, 0, 0);
+    let mut manifest = app.clone();
+    manifest.version = semver::Version::new(2, 0, 0);
+
+    for (name, failure) in failures {
+        super::inject_failure(name, *failure);
+    }
+    let temp_path = temp_path.to_string_lossy().to_string();
+    let result = std::panic::catch_unwind(|| replace_appimage(&app, &manifest, &journal_path, &temp_path, &root_path))
+        .unwrap_or_else(|_| Err(anyhow!("crashed")));
+    (root_path, journal_path, result)
+}
+
+#[test]
+fn test_replace_appimage_recovers_from_crash() {
+    use super::InjectedFailure::*;
+    let cases: [&[(&'static str, super::InjectedFailure)]; 3] = [
+        &[("move_new", Crash)],
+        // a cross-device move falls back to a copy, which may be interrupted half way
+        &[("move_new", Error(18)), ("copy_new", Crash)],
+        &[("move_new", Error(18)), ("copy_new", Error(5))],
+    ];
+    for failures in cases {
+        let tmp = tempfile::tempdir().unwrap();
+        let (root_path, journal_path, result) = run_test_replace_appimage(tmp.path(), failures);
+        assert!(result.is_err());
+        assert!(journal_path.exists(), "journal not kept for {:?}", failures);
+        let outcome = super::recover_apply_journal(&journal_path, &root_path, &tmp.path().join("staging")).unwrap();
+        assert_eq!(outcome, Some(super::RecoveryOutcome::RolledForward));
+        assert_eq!(fs::read_to_string(&root_path).unwrap(), "2.0.0");
+        assert!(!journal_path.exists());
+    }
+}
+
+#[test]
+fn test_replace_appimage_leaves_install_untouched_on_error() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (root_path, journal_path, result) = run_test_replace_appimage(tmp.path(), &[("move_new", super::InjectedFailure::Error(5))]);
+    assert!(result.is_err());
+    assert_eq!(fs::read_to_string(&root_path).unwrap(), "1.0.0");
+    assert!(!journal_path.exists());
+
+    let tmp = tempfile::tempdir().unwrap();
+    let (root_path, journal_path, result) = run_test_replace_appimage(tmp.path(), &[]);
+    result.unwrap();
+    assert_eq!(fs::read_to_string(&root_path).unwrap(), "2.0.0");
+    assert!(!journal_path.exists());
 }
@@ -1,72 +1,224 @@
+use super::{ApplyJournal, JournalKind, JournalPhase};
 use crate::shared::{
     self,
     bundle::{self, Manifest},
-    dialogs,
+    dialogs, events,
+    events::ApplyPhase,
+    hooks::{HookPayload, HookResult},
 };
 use anyhow::{bail, Result};
-use std::{fs, path::PathBuf, process::Command};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
 
-pub fn apply_package_impl<'a>(root_path: &PathBuf, app: &Manifest, pkg: &PathBuf, _runhooks: bool) -> Result<Manifest> {
-    #[allow(deprecated)]
-    let mut cache_dir = std::env::home_dir().expect("Could not locate user home directory via $HOME or /etc/passwd");
-    cache_dir.push("Library");
-    cache_dir.push("Caches");
-    cache_dir.push("velopack");
-    cache_dir.push(&app.id);
+pub fn apply_package_impl<'a>(root_path: &PathBuf, app: &Manifest, pkg: &PathBuf, allow_downgrade: bool, runhooks: bool) -> Result<Manifest> {
+    let cache_dir = shared::get_location_checked(shared::LocationKind::Staging, &app.id, root_path)?;
 
     let tmp_path_new = cache_dir.join(shared::random_string(8) + ".tmp").to_string_lossy().to_string();
     let tmp_path_old = cache_dir.join(shared::random_string(8) + ".tmp").to_string_lossy().to_string();
+    let journal_path = super::get_journal_path(&app.id, root_path)?;
     let bundle = bundle::load_bundle_from_file(pkg)?;
     let manifest = bundle.read_manifest()?;
+    events::phase(ApplyPhase::Verifying);
+    super::verify_package_compatibility(app, &manifest, allow_downgrade)?;
+    crate::logging::set_log_field("to_version", &manifest.version.to_string());
+
+    if runhooks {
+        events::phase_detail(ApplyPhase::Hooks, "--veloapp-obsolete");
+        let payload = HookPayload::new("--veloapp-obsolete", Some(&app.version), &manifest, root_path, false);
+        shared::run_hook(app, root_path, &payload, 15).ensure_continue("--veloapp-obsolete")?;
+    } else {
+        info!("Skipping --veloapp-obsolete hook.");
+    }
 
     let action: Result<()> = (|| {
         // 1. extract the bundle to a temp dir
         fs::create_dir_all(&tmp_path_new)?;
         info!("Extracting bundle to {}", &tmp_path_new);
-        bundle.extract_lib_contents_to_path(&tmp_path_new, |_| {})?;
+        events::phase(ApplyPhase::Extracting);
+        bundle.extract_lib_contents_to_path(&tmp_path_new, |p| events::phase_progress(ApplyPhase::Extracting, p))?;
 
         // 2. attempt to replace the current bundle with the new one
-        let result: Result<()> = (|| {
-            info!("Replacing bundle at {}", &root_path.to_string_lossy());
-            fs::rename(&root_path, &tmp_path_old)?;
-            fs::rename(&tmp_path_new, &root_path)?;
-            Ok(())
-        })();
-
-        match result {
-            Ok(()) => {
-                info!("Bundle extracted successfully to {}", &root_path.to_string_lossy());
-                Ok(())
-            }
-            Err(e) => {
-                // 3. if fails for permission error, try again escallated via osascript
-                if shared::is_error_permission_denied(&e) {
-                    error!("A permissions error occurred ({}), will attempt to elevate permissions and try again...", e);
-                    dialogs::ask_user_to_elevate(&manifest)?;
-                    let script = format!(
-                        "do shell script \"mv -f '{}' '{}' && mv -f '{}' '{}' && rm -rf '{}'\" with administrator privileges",
-                        &root_path.to_string_lossy(),
-                        &tmp_path_old,
-                        &tmp_path_new,
-                        &root_path.to_string_lossy(),
-                        &tmp_path_old
-                    );
-                    info!("Running elevated process via osascript: {}", script);
-                    let output = Command::new("osascript").arg("-e").arg(&script).status()?;
-                    if output.success() {
-                        info!("Bundle applied successfully via osascript.");
-                        Ok(())
-                    } else {
-                        bail!("elevated process failed: exited with code: {}", output);
-                    }
-                } else {
-                    bail!("Failed to extract bundle ({})", e);
-                }
+        events::phase(ApplyPhase::Swapping);
+        swap_bundle(app, &manifest, &journal_path, root_path, &tmp_path_new, &tmp_path_old)?;
+
+        // 3. keep the previous bundle around so it can be rolled back to
+        retain_old_bundle(app, root_path, &tmp_path_old);
+
+        if runhooks {
+            events::phase_detail(ApplyPhase::Hooks, "--veloapp-updated");
+            let payload = HookPayload::new("--veloapp-updated", Some(&app.version), &manifest, root_path, false);
+            if shared::run_hook(&manifest, root_path, &payload, 15) == HookResult::Abort {
+                return Err(super::rollback_vetoed_update(root_path, app, &manifest));
             }
+        } else {
+            info!("Skipping --veloapp-updated hook.");
         }
+        Ok(())
     })();
-    let _ = fs::remove_dir_all(&tmp_path_new);
-    let _ = fs::remove_dir_all(&tmp_path_old);
+    // if the swap was interrupted half way, everything is left in place for the journal to recover
+    if !journal_path.exists() {
+        let _ = fs::remove_dir_all(&tmp_path_new);
+        let _ = fs::remove_dir_all(&tmp_path_old);
+    }
     action?;
     Ok(manifest)
 }
+
+/// Restores a retained version. If `retain` is set, the version it replaces is retained in turn, so the rollback can be undone.
+pub fn rollback_package_impl(root_path: &PathBuf, app: &Manifest, retained_path: &PathBuf, retain: bool, runhooks: bool) -> Result<Manifest> {
+    let nuspec_path = retained_path.join("Contents").join("MacOS").join("sq.version");
+    let nuspec = shared::retry_io(|| fs::read_to_string(&nuspec_path))?;
+    let manifest = bundle::read_manifest_from_string(&nuspec)?;
+
+    // the retained bundle is already on disk, so it is swapped in directly
+    let rollback_dir = super::get_rollback_dir(app, root_path)?;
+    let tmp_path_old = rollback_dir.join(shared::random_string(8) + ".tmp").to_string_lossy().to_string();
+    let retained_path = retained_path.to_string_lossy().to_string();
+    let journal_path = super::get_journal_path(&app.id, root_path)?;
+
+    if runhooks {
+        let payload = HookPayload::new("--veloapp-obsolete", Some(&app.version), &manifest, root_path, false);
+        shared::run_hook(app, root_path, &payload, 15).ensure_continue("--veloapp-obsolete")?;
+    } else {
+        info!("Skipping --veloapp-obsolete hook.");
+    }
+
+    let action: Result<()> = (|| {
+        swap_bundle(app, &manifest, &journal_path, root_path, &retained_path, &tmp_path_old)?;
+        if retain {
+            retain_old_bundle(app, root_path, &tmp_path_old);
+        }
+
+        if runhooks {
+            // a rollback is not itself rolled back, so the result is only logged
+            let payload = HookPayload::new("--veloapp-updated", Some(&app.version), &manifest, root_path, false);
+            let _ = shared::run_hook(&manifest, root_path, &payload, 15);
+        } else {
+            info!("Skipping --veloapp-updated hook.");
+        }
+        Ok(())
+    })();
+    if !journal_path.exists() {
+        let _ = fs::remove_dir_all(&tmp_path_old);
+    }
+    action?;
+    Ok(manifest)
+}
+
+fn retain_old_bundle(app: &Manifest, root_path: &PathBuf, tmp_path_old: &str) {
+    let slot_name = format!("{}.app", app.version);
+    let retained = super::get_rollback_dir(app, root_path).and_then(|dir| super::retain_version(&dir, &app.version, &slot_name, tmp_path_old));
+    if let Err(e) = retained {
+        warn!("Failed to retain version {} for rollback ({}).", app.version, e);
+    }
+}
+
+fn swap_bundle(app: &Manifest, manifest: &Manifest, journal_path: &Path, root_path: &PathBuf, tmp_path_new: &str, tmp_path_old: &str) -> Result<()> {
+    let mut journal = ApplyJournal::begin(
+        journal_path,
+        JournalKind::DirectorySwap,
+        &app.version.to_string(),
+        &manifest.version.to_string(),
+        root_path,
+        Some(Path::new(tmp_path_old)),
+        Path::new(tmp_path_new),
+    )?;
+
+    let result: Result<()> = (|| {
+        info!("Replacing bundle at {}", &root_path.to_string_lossy());
+        super::fail_point("move_old")?;
+        fs::rename(&root_path, tmp_path_old)?;
+        journal.set_phase(JournalPhase::OldMoved)?;
+        super::fail_point("move_new")?;
+        fs::rename(tmp_path_new, &root_path)?;
+        super::fail_point("new_moved")?;
+        journal.set_phase(JournalPhase::NewMoved)?;
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        // put the current bundle back if it was moved, so the install is left as it was
+        if journal.phase == JournalPhase::OldMoved {
+            fs::rename(tmp_path_old, &root_path)?;
+            journal.set_phase(JournalPhase::Prepared)?;
+        }
+
+        // 3. if fails for permission error, try again escallated via osascript
+        if !shared::is_error_permission_denied(&e) {
+            journal.complete()?;
+            bail!("Failed to extract bundle ({})", e);
+        }
+
+        error!("A permissions error occurred ({}), will attempt to elevate permissions and try again...", e);
+        dialogs::ask_user_to_elevate(&manifest)?;
+        let script = format!(
+            "do shell script \"mv -f '{}' '{}' && mv -f '{}' '{}'\" with administrator privileges",
+            &root_path.to_string_lossy(),
+            tmp_path_old,
+            tmp_path_new,
+            &root_path.to_string_lossy()
+        );
+        info!("Running elevated process via osascript: {}", script);
+        let output = Command::new("osascript").arg("-e").arg(&script).status()?;
+        if !output.success() {
+            bail!("elevated process failed: exited with code: {}", output);
+        }
+        info!("Bundle applied successfully via osascript.");
+        journal.set_phase(JournalPhase::NewMoved)?;
+    } else {
+        info!("Bundle extracted successfully to {}", &root_path.to_string_lossy());
+    }
+
+    journal.complete()
+}
+
+#[cfg(test)]
+fn run_test_swap_bundle(dir: &Path, failures: &[(&'static str, super::InjectedFailure)]) -> (PathBuf, PathBuf, Result<()>) {
+    let root_path = dir.join("MyApp.app");
+    let staging_dir = dir.join("staging");
+    for (path, version) in [(&root_path, "1.0.0"), (&staging_dir.join("new.tmp"), "2.0.0")] {
+        fs::create_dir_all(path).unwrap();
+        fs::write(path.join("version"), version).unwrap();
+    }
+
+    let mut app = Manifest::default();
+    app.id = "MyApp".to_owned();
+    app.version = semver::Version::new(1, 0, 0);
+    let mut manifest = app.clone();
+    manifest.version = semver::Version::new(2, 0, 0);
+
+    for (name, failure) in failures {
+        super::inject_failure(name, *failure);
+    }
+    let journal_path = staging_dir.join("apply.journal");
+    let new_path = staging_dir.join("new.tmp").to_string_lossy().to_string();
+    let old_path = staging_dir.join("old.tmp").to_string_lossy().to_string();
+    let result = std::panic::catch_unwind(|| swap_bundle(&app, &manifest, &journal_path, &root_path, &new_path, &old_path))
+        .unwrap_or_else(|_| Err(anyhow::anyhow!("crashed")));
+    (root_path, journal_path, result)
+}
+
+#[test]
+fn test_swap_bundle_restores_bundle_on_error() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (root_path, journal_path, result) = run_test_swap_bundle(tmp.path(), &[("move_new", super::InjectedFailure::Error(5))]);
+    assert!(result.is_err());
+    assert_eq!(fs::read_to_string(root_path.join("version")).unwrap(), "1.0.0");
+    assert!(!journal_path.exists());
+}
+
+#[test]
+fn test_swap_bundle_recovers_from_crash() {
+    use super::{InjectedFailure::Crash, RecoveryOutcome::*};
+    for (fail_point, expected, expected_version) in [("move_old", Consistent, "1.0.0"), ("move_new", RolledBack, "1.0.0"), ("new_moved", Consistent, "2.0.0")] {
+        let tmp = tempfile::tempdir().unwrap();
+        let (root_path, journal_path, result) = run_test_swap_bundle(tmp.path(), &[(fail_point, Crash)]);
+        assert!(result.is_err());
+        let outcome = super::recover_apply_journal(&journal_path, &root_path, &tmp.path().join("staging")).unwrap();
+        assert_eq!(outcome, Some(expected), "crash at {}", fail_point);
+        assert_eq!(fs::read_to_string(root_path.join("version")).unwrap(), expected_version);
+    }
+}
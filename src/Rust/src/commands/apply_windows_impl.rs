@@ -1,6 +1,13 @@
+use super::{ApplyJournal, JournalKind, JournalPhase};
 use crate::{
     dialogs,
-    shared::{self, bundle, bundle::Manifest},
+    shared::{
+        self, bundle,
+        bundle::Manifest,
+        events,
+        events::ApplyPhase,
+        hooks::{HookPayload, HookResult},
+    },
     windows::locksmith,
     windows::splash,
 };
@@ -10,9 +17,12 @@ use std::{
     path::{Path, PathBuf},
 };
 
-pub fn apply_package_impl<'a>(root_path: &PathBuf, app: &Manifest, package: &PathBuf, runhooks: bool) -> Result<Manifest> {
+pub fn apply_package_impl<'a>(root_path: &PathBuf, app: &Manifest, package: &PathBuf, allow_downgrade: bool, runhooks: bool) -> Result<Manifest> {
     let bundle = bundle::load_bundle_from_file(&package)?;
     let manifest = bundle.read_manifest()?;
+    events::phase(ApplyPhase::Verifying);
+    super::verify_package_compatibility(app, &manifest, allow_downgrade)?;
+    crate::logging::set_log_field("to_version", &manifest.version.to_string());
 
     let found_version = (&manifest.version).to_owned();
     info!("Applying package to current: {}", found_version);
@@ -22,7 +32,9 @@ pub fn apply_package_impl<'a>(root_path: &PathBuf, app: &Manifest, package: &Pat
     }
 
     if runhooks {
-        crate::windows::run_hook(&app, &root_path, "--veloapp-obsolete", 15);
+        events::phase_detail(ApplyPhase::Hooks, "--veloapp-obsolete");
+        let payload = HookPayload::new("--veloapp-obsolete", Some(&app.version), &manifest, root_path, false);
+        crate::windows::run_hook(&app, &root_path, &payload, 15).ensure_continue("--veloapp-obsolete")?;
     } else {
         info!("Skipping --veloapp-obsolete hook.");
     }
@@ -30,81 +42,62 @@ pub fn apply_package_impl<'a>(root_path: &PathBuf, app: &Manifest, package: &Pat
     // we are going to be replacing the current dir with temp_path_new
     let current_dir = app.get_current_path(&root_path);
 
-    // we extract to a temp directory inside $root/packages.tmp_XXXXX so that we know it's
+    // we extract to a temp directory inside the staging dir ($root/packages by default) so that we know it's
     // on the same volume as the current dir, and we can rename it quickly.
-    let packages_dir = app.get_packages_path(&root_path);
-    let packages_dir = Path::new(&packages_dir);
-    let temp_path_new = packages_dir.join(format!("tmp_{}", shared::random_string(8)));
-    let temp_path_old = packages_dir.join(format!("tmp_{}", shared::random_string(8)));
+    let staging_dir = shared::get_location_checked(shared::LocationKind::Staging, &app.id, root_path)?;
+    let temp_path_new = staging_dir.join(format!("tmp_{}", shared::random_string(8)));
+    let temp_path_old = staging_dir.join(format!("tmp_{}", shared::random_string(8)));
+    let journal_path = super::get_journal_path(&app.id, root_path)?;
+    let mut journal: Option<ApplyJournal> = None;
 
     let action: Result<()> = (|| {
         info!("Extracting bundle to {}", &temp_path_new.to_string_lossy());
         fs::create_dir_all(&temp_path_new)?;
+        events::phase(ApplyPhase::Extracting);
 
         if dialogs::get_silent() {
-            bundle.extract_lib_contents_to_path(&temp_path_new, |_| {})?;
+            bundle.extract_lib_contents_to_path(&temp_path_new, |p| events::phase_progress(ApplyPhase::Extracting, p))?;
         } else {
             let title = format!("{} Update", &manifest.title);
             let message = format!("Installing update {}...", &manifest.version);
             let tx = splash::show_progress_dialog(title, message);
             bundle.extract_lib_contents_to_path(&temp_path_new, |p| {
+                events::phase_progress(ApplyPhase::Extracting, p);
                 let _ = tx.send(p);
             })?;
             let _ = tx.send(splash::MSG_CLOSE);
         }
 
+        events::phase(ApplyPhase::Swapping);
         let _ = shared::force_stop_package(&root_path);
 
-        let mut has_retried = false;
-        loop {
-            let result: std::io::Result<()> = (|| {
-                info!("Replacing bundle at {}", &current_dir);
-                // so much stuff can lock folders on windows, so we retry a few times.
-                shared::retry_io(|| fs::rename(&current_dir, &temp_path_old))?;
-                shared::retry_io(|| fs::rename(&temp_path_new, &current_dir))?;
-                Ok(())
-            })();
-
-            match result {
-                Ok(()) => {
-                    info!("Bundle extracted successfully to {}", &current_dir);
-                    break;
-                }
-                Err(e) => {
-                    match e.raw_os_error() {
-                        Some(32) => {
-                            // this is usually because the folder is locked by something on Windows.
-                            // in the future, we also need to handle the case where this is a folder permissions issue
-                            // and request elevation, but for now we will ask the user to close the program.
-                            // it's also possible that an admin process is locking the dir, in which case we won't see
-                            // it here unless we are also elevated.
-                            if locksmith::close_processes_locking_dir(&app.title, &current_dir) {
-                                // the processes were closed successfully, so we can retry the operation (only once)
-                                if has_retried {
-                                    bail!("Failed to swap current dir: {}", e);
-                                }
-                                has_retried = true;
-                                continue;
-                            }
-                        }
-                        _ => {
-                            let title = format!("{} Update", &manifest.title);
-                            let header = format!("Failed to update");
-                            let body = format!("Failed to update {} to version {}. ({})", &manifest.title, &manifest.version, e);
-                            dialogs::show_error(&title, Some(&header), &body);
-                            return Ok(()); // so that a generic error dialog is not shown.
-                        }
-                    }
-                }
+        if let Err(e) = swap_current_dir(app, &manifest, &journal_path, &mut journal, Path::new(&current_dir), &temp_path_new, &temp_path_old) {
+            if journal.as_ref().is_some_and(|j| j.phase != JournalPhase::Prepared) {
+                bail!("Failed to swap current dir, it will be recovered on next start: {}", e);
             }
+            let title = format!("{} Update", &manifest.title);
+            let header = format!("Failed to update");
+            let body = format!("Failed to update {} to version {}. ({})", &manifest.title, &manifest.version, e);
+            dialogs::show_error(&title, Some(&header), &body);
+            return Ok(()); // so that a generic error dialog is not shown.
+        }
+
+        let retained = super::get_rollback_dir(&app, &root_path).and_then(|dir| super::retain_version(&dir, &app.version, &app.version.to_string(), &temp_path_old));
+        if let Err(e) = retained {
+            warn!("Failed to retain version {} for rollback ({}).", app.version, e);
         }
+        journal.take().unwrap().complete()?;
 
         if let Err(e) = manifest.write_uninstall_entry(root_path) {
             warn!("Failed to write uninstall entry ({}).", e);
         }
 
         if runhooks {
-            crate::windows::run_hook(&manifest, &root_path, "--veloapp-updated", 15);
+            events::phase_detail(ApplyPhase::Hooks, "--veloapp-updated");
+            let payload = HookPayload::new("--veloapp-updated", Some(&app.version), &manifest, root_path, false);
+            if crate::windows::run_hook(&manifest, &root_path, &payload, 15) == HookResult::Abort {
+                return Err(super::rollback_vetoed_update(root_path, app, &manifest));
+            }
         } else {
             info!("Skipping --veloapp-updated hook.");
         }
@@ -113,8 +106,185 @@ pub fn apply_package_impl<'a>(root_path: &PathBuf, app: &Manifest, package: &Pat
         Ok(())
     })();
 
-    let _ = remove_dir_all::remove_dir_all(&temp_path_new);
-    let _ = remove_dir_all::remove_dir_all(&temp_path_old);
+    // if the swap was interrupted half way, everything is left in place for the journal to recover
+    let interrupted = journal.as_ref().is_some_and(|j| j.phase != JournalPhase::Prepared);
+    if !interrupted {
+        let _ = remove_dir_all::remove_dir_all(&temp_path_new);
+        let _ = remove_dir_all::remove_dir_all(&temp_path_old);
+        if let Some(j) = journal {
+            let _ = j.complete();
+        }
+    }
     action?;
     Ok(manifest)
 }
+
+/// Moves the current dir aside and the new version into its place. If a move fails, the current version is put back and the
+/// journal returns to `Prepared`, so nothing needs to be recovered. If even that fails, the journal is left for the next start.
+fn swap_current_dir(
+    app: &Manifest,
+    manifest: &Manifest,
+    journal_path: &Path,
+    journal: &mut Option<ApplyJournal>,
+    current_dir: &Path,
+    temp_path_new: &Path,
+    temp_path_old: &Path,
+) -> Result<()> {
+    let from_version = app.version.to_string();
+    let to_version = manifest.version.to_string();
+    let j = journal.insert(ApplyJournal::begin(journal_path, JournalKind::DirectorySwap, &from_version, &to_version, current_dir, Some(temp_path_old), temp_path_new)?);
+
+    let mut has_retried = false;
+    loop {
+        let result: Result<()> = (|| {
+            info!("Replacing bundle at {}", current_dir.to_string_lossy());
+            // so much stuff can lock folders on windows, so we retry a few times.
+            super::fail_point("move_old")?;
+            shared::retry_io(|| fs::rename(current_dir, temp_path_old))?;
+            j.set_phase(JournalPhase::OldMoved)?;
+            super::fail_point("move_new")?;
+            shared::retry_io(|| fs::rename(temp_path_new, current_dir))?;
+            super::fail_point("new_moved")?;
+            j.set_phase(JournalPhase::NewMoved)?;
+            Ok(())
+        })();
+
+        let e = match result {
+            Ok(()) => {
+                info!("Bundle extracted successfully to {}", current_dir.to_string_lossy());
+                return Ok(());
+            }
+            Err(e) => e,
+        };
+        if j.phase == JournalPhase::OldMoved {
+            // put the current version back, so the swap can be retried and we are never left without a current dir
+            if shared::retry_io(|| fs::rename(temp_path_old, current_dir)).is_err() {
+                return Err(e);
+            }
+            j.set_phase(JournalPhase::Prepared)?;
+        }
+
+        // this is usually because the folder is locked by something on Windows.
+        // in the future, we also need to handle the case where this is a folder permissions issue
+        // and request elevation, but for now we will ask the user to close the program.
+        // it's also possible that an admin process is locking the dir, in which case we won't see
+        // it here unless we are also elevated.
+        let is_locked = e.downcast_ref::<std::io::Error>().and_then(|e| e.raw_os_error()) == Some(32);
+        if is_locked && !has_retried && locksmith::close_processes_locking_dir(&app.title, &current_dir.to_string_lossy()) {
+            // the processes were closed successfully, so we can retry the operation (only once)
+            has_retried = true;
+            continue;
+        }
+        return Err(e);
+    }
+}
+
+/// Restores a retained version. If `retain` is set, the version it replaces is retained in turn, so the rollback can be undone.
+pub fn rollback_package_impl(root_path: &PathBuf, app: &Manifest, retained_path: &PathBuf, retain: bool, runhooks: bool) -> Result<Manifest> {
+    let nuspec = shared::retry_io(|| fs::read_to_string(retained_path.join("sq.version")))?;
+    let manifest = bundle::read_manifest_from_string(&nuspec)?;
+    info!("Restoring retained version to current: {}", manifest.version);
+
+    if runhooks {
+        let payload = HookPayload::new("--veloapp-obsolete", Some(&app.version), &manifest, root_path, false);
+        crate::windows::run_hook(&app, &root_path, &payload, 15).ensure_continue("--veloapp-obsolete")?;
+    } else {
+        info!("Skipping --veloapp-obsolete hook.");
+    }
+
+    // the retained version is inside the staging dir already, so it is swapped in directly
+    let current_dir = app.get_current_path(&root_path);
+    let staging_dir = shared::get_location_checked(shared::LocationKind::Staging, &app.id, root_path)?;
+    let temp_path_old = staging_dir.join(format!("tmp_{}", shared::random_string(8)));
+    let journal_path = super::get_journal_path(&app.id, root_path)?;
+    let mut journal: Option<ApplyJournal> = None;
+
+    let _ = shared::force_stop_package(&root_path);
+
+    if let Err(e) = swap_current_dir(app, &manifest, &journal_path, &mut journal, Path::new(&current_dir), retained_path, &temp_path_old) {
+        if journal.as_ref().is_some_and(|j| j.phase != JournalPhase::Prepared) {
+            bail!("Failed to restore retained version {}, it will be recovered on next start: {}", manifest.version, e);
+        }
+        if let Some(j) = journal {
+            let _ = j.complete();
+        }
+        bail!("Failed to restore retained version {} ({}).", manifest.version, e);
+    }
+
+    if retain {
+        let retained = super::get_rollback_dir(&app, &root_path).and_then(|dir| super::retain_version(&dir, &app.version, &app.version.to_string(), &temp_path_old));
+        if let Err(e) = retained {
+            warn!("Failed to retain version {} for rollback ({}).", app.version, e);
+        }
+    }
+    // anything which was not retained is not needed any more
+    let _ = remove_dir_all::remove_dir_all(&temp_path_old);
+    journal.take().unwrap().complete()?;
+
+    if let Err(e) = manifest.write_uninstall_entry(root_path) {
+        warn!("Failed to write uninstall entry ({}).", e);
+    }
+
+    if runhooks {
+        // a rollback is not itself rolled back, so the result is only logged
+        let payload = HookPayload::new("--veloapp-updated", Some(&app.version), &manifest, root_path, false);
+        let _ = crate::windows::run_hook(&manifest, &root_path, &payload, 15);
+    } else {
+        info!("Skipping --veloapp-updated hook.");
+    }
+
+    Ok(manifest)
+}
+
+#[cfg(test)]
+fn run_test_swap_current_dir(dir: &Path, failures: &[(&'static str, super::InjectedFailure)]) -> (Result<()>, Option<ApplyJournal>) {
+    let current_dir = dir.join("current");
+    let staging_dir = dir.join("packages");
+    for (path, version) in [(&current_dir, "1.0.0"), (&staging_dir.join("tmp_new"), "2.0.0")] {
+        fs::create_dir_all(path).unwrap();
+        fs::write(path.join("version"), version).unwrap();
+    }
+
+    let mut app = Manifest::default();
+    app.id = "MyApp".to_owned();
+    app.version = semver::Version::new(1, 0, 0);
+    let mut manifest = app.clone();
+    manifest.version = semver::Version::new(2, 0, 0);
+
+    for (name, failure) in failures {
+        super::inject_failure(name, *failure);
+    }
+    let mut journal = None;
+    let journal_path = staging_dir.join("apply.journal");
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        swap_current_dir(&app, &manifest, &journal_path, &mut journal, &current_dir, &staging_dir.join("tmp_new"), &staging_dir.join("tmp_old"))
+    }))
+    .unwrap_or_else(|_| Err(anyhow::anyhow!("crashed")));
+    (result, journal)
+}
+
+#[test]
+fn test_swap_current_dir_restores_current_on_error() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (result, journal) = run_test_swap_current_dir(tmp.path(), &[("move_new", super::InjectedFailure::Error(5))]);
+    assert!(result.is_err());
+    assert_eq!(journal.unwrap().phase, JournalPhase::Prepared);
+    assert_eq!(fs::read_to_string(tmp.path().join("current").join("version")).unwrap(), "1.0.0");
+    assert!(tmp.path().join("packages").join("tmp_new").exists());
+    assert!(!tmp.path().join("packages").join("tmp_old").exists());
+}
+
+#[test]
+fn test_swap_current_dir_recovers_from_crash() {
+    use super::{InjectedFailure::Crash, RecoveryOutcome::*};
+    for (fail_point, expected, expected_version) in [("move_old", Consistent, "1.0.0"), ("move_new", RolledBack, "1.0.0"), ("new_moved", Consistent, "2.0.0")] {
+        let tmp = tempfile::tempdir().unwrap();
+        let (result, _) = run_test_swap_current_dir(tmp.path(), &[(fail_point, Crash)]);
+        assert!(result.is_err());
+        let staging_dir = tmp.path().join("packages");
+        let outcome = super::recover_apply_journal(staging_dir.join("apply.journal"), &tmp.path().join("current"), &staging_dir).unwrap();
+        assert_eq!(outcome, Some(expected), "crash at {}", fail_point);
+        assert_eq!(fs::read_to_string(tmp.path().join("current").join("version")).unwrap(), expected_version);
+        assert!(!staging_dir.join("tmp_old").exists());
+    }
+}
@@ -0,0 +1,192 @@
+use crate::shared::bundle::Manifest;
+use anyhow::Result;
+use semver::Version;
+
+/// The reason a package can not be applied to an installed application.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompatibilityError {
+    IdMismatch { installed: String, package: String },
+    WrongOs { package: String },
+    UnsupportedOsVersion { required: String, current: String },
+    UnverifiableOsVersion { required: String, reason: String },
+    UnsupportedArchitecture { package: String },
+    ChannelMismatch { installed: String, package: String },
+    Downgrade { installed: Version, package: Version },
+}
+
+impl std::fmt::Display for CompatibilityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompatibilityError::IdMismatch { installed, package } => {
+                write!(f, "Package id '{}' does not match the installed application '{}'.", package, installed)
+            }
+            CompatibilityError::WrongOs { package } => write!(f, "Package was built for '{}' and can not be applied on '{}'.", package, CURRENT_OS),
+            CompatibilityError::UnsupportedOsVersion { required, current } => {
+                write!(f, "This application requires {} {} or later, but this system is {}.", OS_DISPLAY_NAME, required, current)
+            }
+            CompatibilityError::UnverifiableOsVersion { required, reason } => {
+                write!(f, "Unable to verify that this system meets the OS requirement '{}' ({}).", required, reason)
+            }
+            CompatibilityError::UnsupportedArchitecture { package } => {
+                write!(f, "Package architecture '{}' is not supported on this machine.", package)
+            }
+            CompatibilityError::ChannelMismatch { installed, package } => write!(
+                f,
+                "Package is on channel '{}', but the application follows channel '{}'. Use switch-channel to change channels.",
+                package, installed
+            ),
+            CompatibilityError::Downgrade { installed, package } => write!(
+                f,
+                "Package version {} is older than the installed version {}. Pass --allow-downgrade to apply it anyway.",
+                package, installed
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CompatibilityError {}
+
+#[cfg(target_os = "windows")]
+const CURRENT_OS: &str = "win";
+#[cfg(target_os = "linux")]
+const CURRENT_OS: &str = "linux";
+#[cfg(target_os = "macos")]
+const CURRENT_OS: &str = "osx";
+
+#[cfg(target_os = "windows")]
+const OS_DISPLAY_NAME: &str = "Windows";
+#[cfg(target_os = "linux")]
+const OS_DISPLAY_NAME: &str = "Linux";
+#[cfg(target_os = "macos")]
+const OS_DISPLAY_NAME: &str = "macOS";
+
+/// Checks that a package belongs to the installed application, its channel, and can run on this machine.
+/// Packages older than the installed version are rejected unless `allow_downgrade` is set. Switching
+/// channels is done by checking against the installed application with the new channel set.
+pub fn verify_package_compatibility(app: &Manifest, package: &Manifest, allow_downgrade: bool) -> Result<(), CompatibilityError> {
+    if !package.id.eq_ignore_ascii_case(&app.id) {
+        return Err(CompatibilityError::IdMismatch { installed: app.id.clone(), package: package.id.clone() });
+    }
+    if !is_os_match(&package.os) {
+        return Err(CompatibilityError::WrongOs { package: package.os.clone() });
+    }
+    verify_os_requirements(package)?;
+    if !super::is_channel_match(&app.channel, &package.channel) {
+        return Err(CompatibilityError::ChannelMismatch { installed: app.channel.clone(), package: package.channel.clone() });
+    }
+    if package.version < app.version && !allow_downgrade {
+        return Err(CompatibilityError::Downgrade { installed: app.version.clone(), package: package.version.clone() });
+    }
+    Ok(())
+}
+
+/// Checks the `os_min_version` and `machine_architecture` of a package against the running system.
+pub fn verify_os_requirements(package: &Manifest) -> Result<(), CompatibilityError> {
+    let required = package.os_min_version.trim();
+    if !required.is_empty() {
+        match get_unmet_os_version(required) {
+            Ok(Some(current)) => return Err(CompatibilityError::UnsupportedOsVersion { required: required.to_owned(), current }),
+            Ok(None) => {}
+            Err(e) => return Err(CompatibilityError::UnverifiableOsVersion { required: required.to_owned(), reason: e.to_string() }),
+        }
+    }
+    if !package.machine_architecture.is_empty() && !is_architecture_supported(&package.machine_architecture) {
+        return Err(CompatibilityError::UnsupportedArchitecture { package: package.machine_architecture.clone() });
+    }
+    Ok(())
+}
+
+/// Returns a description of this system if it does not meet the required OS version.
+#[cfg(target_os = "windows")]
+fn get_unmet_os_version(required: &str) -> Result<Option<String>> {
+    if crate::windows::is_os_version_or_greater(required)? {
+        return Ok(None);
+    }
+    Ok(Some(crate::windows::os_info::get().to_string()))
+}
+
+/// Returns a description of this system if it does not meet the required OS version.
+#[cfg(target_os = "linux")]
+fn get_unmet_os_version(required: &str) -> Result<Option<String>> {
+    let system = crate::shared::LinuxSystemInfo::detect()?;
+    if crate::shared::is_os_requirement_met(required, &system)? {
+        return Ok(None);
+    }
+    Ok(Some(system.to_string()))
+}
+
+/// Returns a description of this system if it does not meet the required OS version.
+#[cfg(target_os = "macos")]
+fn get_unmet_os_version(required: &str) -> Result<Option<String>> {
+    crate::shared::parse_version(required)?;
+    let current = crate::shared::get_macos_version()?;
+    if crate::shared::is_version_at_least(&current, required) {
+        return Ok(None);
+    }
+    Ok(Some(format!("macOS {}", current)))
+}
+
+fn is_os_match(package_os: &str) -> bool {
+    // packages created before the os was written to the manifest are accepted everywhere
+    match package_os.to_ascii_lowercase().as_str() {
+        "" => true,
+        "win" | "windows" => CURRENT_OS == "win",
+        "osx" | "macos" => CURRENT_OS == "osx",
+        other => other == CURRENT_OS,
+    }
+}
+
+fn is_architecture_supported(architecture: &str) -> bool {
+    #[cfg(target_os = "windows")]
+    return crate::windows::is_cpu_architecture_supported(architecture).unwrap_or(false);
+    #[cfg(not(target_os = "windows"))]
+    return crate::shared::is_cpu_architecture_supported(architecture);
+}
+
+#[test]
+fn test_verify_package_compatibility() {
+    let mut app = Manifest::default();
+    app.id = "MyApp".to_owned();
+    app.version = Version::new(2, 0, 0);
+
+    let mut package = app.clone();
+    package.id = "myapp".to_owned();
+    package.version = Version::new(3, 0, 0);
+    assert_eq!(verify_package_compatibility(&app, &package, false), Ok(()));
+
+    package.os = CURRENT_OS.to_owned();
+    assert_eq!(verify_package_compatibility(&app, &package, false), Ok(()));
+
+    let mut other = package.clone();
+    other.id = "OtherApp".to_owned();
+    assert!(matches!(verify_package_compatibility(&app, &other, false), Err(CompatibilityError::IdMismatch { .. })));
+
+    let mut other = package.clone();
+    other.os = if CURRENT_OS == "linux" { "win" } else { "linux" }.to_owned();
+    assert!(matches!(verify_package_compatibility(&app, &other, false), Err(CompatibilityError::WrongOs { .. })));
+
+    let mut other = package.clone();
+    other.machine_architecture = "sparc".to_owned();
+    assert!(matches!(verify_package_compatibility(&app, &other, false), Err(CompatibilityError::UnsupportedArchitecture { .. })));
+
+    let mut other = package.clone();
+    other.os_min_version = "999".to_owned();
+    assert!(matches!(verify_package_compatibility(&app, &other, false), Err(CompatibilityError::UnsupportedOsVersion { .. })));
+    other.os_min_version = "not a version".to_owned();
+    assert!(matches!(verify_package_compatibility(&app, &other, false), Err(CompatibilityError::UnverifiableOsVersion { .. })));
+
+    let mut other = package.clone();
+    other.channel = "beta".to_owned();
+    assert_eq!(verify_package_compatibility(&app, &other, false), Ok(()), "an installation without a channel accepts any channel");
+    let mut on_stable = app.clone();
+    on_stable.channel = "stable".to_owned();
+    assert!(matches!(verify_package_compatibility(&on_stable, &other, false), Err(CompatibilityError::ChannelMismatch { .. })));
+    let mut switching = on_stable.clone();
+    switching.channel = "Beta".to_owned();
+    assert_eq!(verify_package_compatibility(&switching, &other, false), Ok(()));
+
+    let mut other = package.clone();
+    other.version = Version::new(1, 0, 0);
+    assert!(matches!(verify_package_compatibility(&app, &other, false), Err(CompatibilityError::Downgrade { .. })));
+    assert_eq!(verify_package_compatibility(&app, &other, true), Ok(()));
+}
@@ -0,0 +1,192 @@
+use crate::shared::{
+    self,
+    bundle::{self, BundleInfo, Manifest},
+};
+use anyhow::{bail, Result};
+use pretty_bytes_rust::pretty_bytes;
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The changes which applying or installing a package would make to the files of an installation.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FileOperations {
+    pub added: Vec<String>,
+    pub changed: Vec<String>,
+    pub removed: Vec<String>,
+    pub unchanged: usize,
+}
+
+/// Validates a package against the current installation and reports what applying it would do,
+/// without extracting, swapping or running any hooks.
+pub fn apply_dry_run(root_path: &PathBuf, app: &Manifest, package: Option<&PathBuf>, allow_downgrade: bool) -> Result<FileOperations> {
+    let package = match package {
+        Some(p) => p.clone(),
+        None => super::locate_latest_package(app, root_path, &app.channel)?.0,
+    };
+    info!("Dry run: validating package {}", package.to_string_lossy());
+    let bundle = bundle::load_bundle_from_file(&package)?;
+    let manifest = bundle.read_manifest()?;
+
+    super::verify_package_compatibility(app, &manifest, allow_downgrade)?;
+    if !super::is_channel_match(&app.channel, &manifest.channel) {
+        bail!("Package channel '{}' does not match the installed channel '{}'.", manifest.channel, app.channel);
+    }
+    info!("Package {} {} (channel '{}') is valid for this installation ({}).", manifest.id, manifest.version, manifest.channel, app.version);
+
+    // packages are extracted into the staging dir before being swapped in
+    let extract_dir = shared::get_location(shared::LocationKind::Staging, &app.id, root_path);
+    let (_, extracted_size) = bundle.calculate_size();
+    check_free_space(&manifest, extracted_size, &extract_dir)?;
+
+    #[cfg(target_os = "windows")]
+    let operations = plan_lib_file_operations(&bundle, Path::new(&app.get_current_path(root_path)))?;
+    #[cfg(target_os = "linux")]
+    let operations = plan_appimage_operation(&bundle, root_path)?;
+    #[cfg(target_os = "macos")]
+    let operations = plan_lib_file_operations(&bundle, root_path)?;
+
+    report_file_operations(&operations);
+    info!("Dry run complete, nothing was changed.");
+    Ok(operations)
+}
+
+/// Checks there is enough space on the volume containing `target_dir`, which does not need to exist yet.
+pub fn check_free_space(app: &Manifest, required_space: u64, target_dir: &Path) -> Result<()> {
+    let existing_dir = target_dir.ancestors().find(|p| p.exists()).unwrap_or(target_dir);
+    let free_space = shared::get_free_space(existing_dir)?;
+    if free_space < required_space {
+        bail!(
+            "{} requires at least {} disk space to be installed. There is only {} available.",
+            &app.title,
+            pretty_bytes(required_space, None),
+            pretty_bytes(free_space, None)
+        );
+    }
+    info!("There is {} free space available at {}, this package requires {}.", pretty_bytes(free_space, None), existing_dir.to_string_lossy(), pretty_bytes(required_space, None));
+    Ok(())
+}
+
+/// Compares the app files in a package with an installed directory. The directory does not need to exist.
+pub fn plan_lib_file_operations(bundle: &BundleInfo, current_dir: &Path) -> Result<FileOperations> {
+    let mut operations = FileOperations::default();
+    let mut package_files = HashSet::new();
+
+    for (idx, relative_path) in bundle.get_lib_file_entries()? {
+        let disk_path = current_dir.join(&relative_path);
+        match fs::symlink_metadata(&disk_path) {
+            // symlinks are recreated on every apply, and only change when the package layout changes
+            Ok(m) if m.file_type().is_symlink() => operations.unchanged += 1,
+            Ok(_) if bundle.is_zip_idx_same_as_file(idx, &disk_path)? => operations.unchanged += 1,
+            Ok(_) => operations.changed.push(relative_path.clone()),
+            Err(_) => operations.added.push(relative_path.clone()),
+        }
+        package_files.insert(relative_path);
+    }
+
+    let mut installed_files = Vec::new();
+    list_files_recursive(current_dir, current_dir, &mut installed_files);
+    operations.removed = installed_files.into_iter().filter(|f| !package_files.contains(f)).collect();
+
+    operations.added.sort();
+    operations.changed.sort();
+    operations.removed.sort();
+    Ok(operations)
+}
+
+#[cfg(target_os = "linux")]
+fn plan_appimage_operation(bundle: &BundleInfo, root_path: &Path) -> Result<FileOperations> {
+    let idx = match bundle.find_zip_file(|name| name.ends_with(".AppImage")) {
+        Some(idx) => idx,
+        None => bail!("Package does not contain an AppImage."),
+    };
+    let mut operations = FileOperations::default();
+    let name = root_path.to_string_lossy().to_string();
+    if !root_path.exists() {
+        operations.added.push(name);
+    } else if bundle.is_zip_idx_same_as_file(idx, root_path)? {
+        operations.unchanged += 1;
+    } else {
+        operations.changed.push(name);
+    }
+    Ok(operations)
+}
+
+fn list_files_recursive(base: &Path, dir: &Path, files: &mut Vec<String>) {
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+            if is_dir {
+                list_files_recursive(base, &path, files);
+            } else if let Ok(relative) = path.strip_prefix(base) {
+                files.push(relative.to_string_lossy().replace('\\', "/"));
+            }
+        }
+    }
+}
+
+pub fn report_file_operations(operations: &FileOperations) {
+    info!(
+        "Planned file operations: {} added, {} changed, {} removed, {} unchanged.",
+        operations.added.len(),
+        operations.changed.len(),
+        operations.removed.len(),
+        operations.unchanged
+    );
+    for f in &operations.added {
+        info!("    + {}", f);
+    }
+    for f in &operations.changed {
+        info!("    ~ {}", f);
+    }
+    for f in &operations.removed {
+        info!("    - {}", f);
+    }
+}
+
+#[cfg(test)]
+fn create_test_package(path: &Path, files: &[(&str, &str)]) {
+    use std::io::Write;
+    let mut zip = zip::ZipWriter::new(fs::File::create(path).unwrap());
+    let options = zip::write::FileOptions::default();
+    zip.start_file("MyApp.nuspec", options).unwrap();
+    zip.write_all(b"<?xml version=\"1.0\"?><package><metadata><id>MyApp</id><version>2.0.0</version></metadata></package>").unwrap();
+    for (name, contents) in files {
+        zip.start_file(format!("lib/app/{}", name), options).unwrap();
+        zip.write_all(contents.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap();
+}
+
+#[test]
+#[cfg(unix)]
+fn test_plan_lib_file_operations() {
+    let tmp = tempfile::tempdir().unwrap();
+    let package = tmp.path().join("MyApp-2.0.0-full.nupkg");
+    create_test_package(&package, &[("same.txt", "same"), ("changed.txt", "new"), ("sub/added.txt", "added")]);
+
+    let current = tmp.path().join("current");
+    fs::create_dir_all(current.join("sub")).unwrap();
+    fs::write(current.join("same.txt"), "same").unwrap();
+    fs::write(current.join("changed.txt"), "old").unwrap();
+    fs::write(current.join("sub").join("removed.txt"), "removed").unwrap();
+
+    let bundle = bundle::load_bundle_from_file(&package).unwrap();
+    let operations = plan_lib_file_operations(&bundle, &current).unwrap();
+    assert_eq!(
+        operations,
+        FileOperations {
+            added: vec!["sub/added.txt".to_owned()],
+            changed: vec!["changed.txt".to_owned()],
+            removed: vec!["sub/removed.txt".to_owned()],
+            unchanged: 1,
+        }
+    );
+
+    // nothing is installed yet
+    let operations = plan_lib_file_operations(&bundle, &tmp.path().join("missing")).unwrap();
+    assert_eq!(operations.added.len(), 3);
+}
@@ -0,0 +1,135 @@
+use crate::shared::{self, bundle::Manifest};
+use anyhow::{bail, Result};
+use semver::Version;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Child,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The environment variable naming the file which a restarted application creates once it has started
+/// successfully. `VelopackApp::run` does this automatically.
+pub const HEALTH_FILE_ENV: &str = "VELOPACK_HEALTH_FILE";
+
+const FAILED_VERSIONS_FILE_NAME: &str = "failed-versions.txt";
+
+/// Starts the application, and waits for it to signal that it started successfully.
+pub fn start_and_wait_until_healthy(app: &Manifest, root_path: &PathBuf, exe_args: Option<Vec<&str>>, timeout: Duration) -> Result<()> {
+    let packages_dir = super::get_packages_dir(app, root_path);
+    fs::create_dir_all(&packages_dir)?;
+    let marker_path = Path::new(&packages_dir).join(format!("health_{}.ready", shared::random_string(8)));
+
+    info!("Waiting up to {:?} for version {} to signal that it started successfully ({}).", timeout, app.version, marker_path.to_string_lossy());
+    let marker_env = marker_path.to_string_lossy().to_string();
+    let mut child = shared::start_package_with_env(app, root_path, exe_args, &[("VELOPACK_RESTART", "true"), (HEALTH_FILE_ENV, &marker_env)])?;
+
+    let result = wait_for_health_signal(&marker_path, timeout, || has_exited(&mut child));
+    let _ = fs::remove_file(&marker_path);
+    if let Err(e) = result {
+        bail!("Version {} failed its health check ({}).", app.version, e);
+    }
+    info!("Version {} started successfully.", app.version);
+    Ok(())
+}
+
+fn has_exited(child: &mut Child) -> bool {
+    match child.try_wait() {
+        // on macos the child is `open`, which exits as soon as the app is launched, so only a failed launch counts
+        Ok(Some(status)) => !cfg!(target_os = "macos") || !status.success(),
+        Ok(None) => false,
+        Err(e) => {
+            warn!("Unable to check whether the application is still running ({}).", e);
+            false
+        }
+    }
+}
+
+/// Waits for the marker file to be created, failing early if `has_exited` reports that the application exited first.
+pub fn wait_for_health_signal<P: AsRef<Path>, F: FnMut() -> bool>(marker_path: P, timeout: Duration, mut has_exited: F) -> Result<()> {
+    let started = Instant::now();
+    loop {
+        if marker_path.as_ref().exists() {
+            return Ok(());
+        }
+        if has_exited() {
+            // the marker may have been written just before the application exited
+            if marker_path.as_ref().exists() {
+                return Ok(());
+            }
+            bail!("the application exited without signalling that it started successfully");
+        }
+        if started.elapsed() >= timeout {
+            bail!("the application did not signal that it started successfully within {:?}", timeout);
+        }
+        thread::sleep(Duration::from_millis(250));
+    }
+}
+
+/// Versions which failed their health check. These are never selected to be applied again.
+pub fn get_failed_versions<P: AsRef<Path>>(packages_dir: P) -> Vec<Version> {
+    fs::read_to_string(packages_dir.as_ref().join(FAILED_VERSIONS_FILE_NAME))
+        .map(|s| s.lines().filter_map(|l| Version::parse(l.trim()).ok()).collect())
+        .unwrap_or_default()
+}
+
+pub fn is_failed_version<P: AsRef<Path>>(packages_dir: P, version: &Version) -> bool {
+    get_failed_versions(packages_dir).contains(version)
+}
+
+pub fn record_failed_version<P: AsRef<Path>>(packages_dir: P, version: &Version) -> Result<()> {
+    let packages_dir = packages_dir.as_ref();
+    let mut versions = get_failed_versions(packages_dir);
+    if !versions.contains(version) {
+        versions.push(version.clone());
+    }
+    let contents: Vec<String> = versions.iter().map(|v| v.to_string()).collect();
+    fs::create_dir_all(packages_dir)?;
+    fs::write(packages_dir.join(FAILED_VERSIONS_FILE_NAME), contents.join("\n") + "\n")?;
+    warn!("Version {} has been recorded as failed, and will not be applied again.", version);
+    Ok(())
+}
+
+#[test]
+fn test_failed_versions_are_recorded_once() {
+    let tmp = tempfile::tempdir().unwrap();
+    let v2 = Version::parse("2.0.0").unwrap();
+    assert!(!is_failed_version(tmp.path(), &v2));
+    record_failed_version(tmp.path(), &v2).unwrap();
+    record_failed_version(tmp.path(), &v2).unwrap();
+    record_failed_version(tmp.path(), &Version::parse("3.0.0-beta").unwrap()).unwrap();
+    assert!(is_failed_version(tmp.path(), &v2));
+    assert_eq!(get_failed_versions(tmp.path()).len(), 2);
+}
+
+#[test]
+fn test_wait_for_health_signal() {
+    let tmp = tempfile::tempdir().unwrap();
+    let marker = tmp.path().join("health.ready");
+    assert!(wait_for_health_signal(&marker, Duration::from_millis(300), || false).is_err());
+
+    let marker_clone = marker.clone();
+    let writer = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(300));
+        fs::write(marker_clone, "").unwrap();
+    });
+    assert!(wait_for_health_signal(&marker, Duration::from_secs(10), || false).is_ok());
+    writer.join().unwrap();
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_wait_for_health_signal_fails_when_application_exits() {
+    let tmp = tempfile::tempdir().unwrap();
+    let marker = tmp.path().join("health.ready");
+    let mut child = std::process::Command::new("sh").arg("-c").arg("exit 0").spawn().unwrap();
+    let started = Instant::now();
+    let err = wait_for_health_signal(&marker, Duration::from_secs(30), || has_exited(&mut child)).unwrap_err();
+    assert!(err.to_string().contains("exited"));
+    assert!(started.elapsed() < Duration::from_secs(10));
+
+    let marker_env = marker.to_string_lossy().to_string();
+    let mut child = std::process::Command::new("sh").arg("-c").arg("touch \"$VELOPACK_HEALTH_FILE\"").env(HEALTH_FILE_ENV, &marker_env).spawn().unwrap();
+    assert!(wait_for_health_signal(&marker, Duration::from_secs(30), || has_exited(&mut child)).is_ok());
+}
@@ -1,7 +1,11 @@
-use crate::{dialogs, shared, shared::bundle, windows};
+use crate::{
+    dialogs, shared,
+    shared::bundle,
+    shared::hooks::{HookPayload, HookResult, HOOK_PAYLOAD_ENV},
+    windows,
+};
 use anyhow::{anyhow, bail, Result};
 use memmap2::Mmap;
-use pretty_bytes_rust::pretty_bytes;
 use std::{
     env,
     fs::{self, File},
@@ -10,7 +14,7 @@ use std::{
 };
 use winsafe::{self as w, co};
 
-pub fn install(debug_pkg: Option<&PathBuf>, install_to: Option<&PathBuf>) -> Result<()> {
+pub fn install(debug_pkg: Option<&PathBuf>, install_to: Option<&PathBuf>, dry_run: bool) -> Result<()> {
     let osinfo = windows::os_info::get();
     info!("OS: {}, Arch={}", osinfo, osinfo.architecture().unwrap_or("unknown"));
 
@@ -36,7 +40,14 @@ pub fn install(debug_pkg: Option<&PathBuf>, install_to: Option<&PathBuf>) -> Res
     info!("    Package Machine Architecture: {}", &app.machine_architecture);
     info!("    Package Runtime Dependencies: {}", &app.runtime_dependencies);
 
-    let _mutex = shared::retry_io(|| windows::create_global_mutex(&app))?;
+    // does this app support this OS / architecture?
+    super::verify_os_requirements(&app)?;
+
+    if dry_run {
+        return install_dry_run(&pkg, &app, install_to);
+    }
+
+    let _lock = shared::retry_io(|| shared::acquire_operation_lock(&app))?;
 
     if !windows::prerequisite::prompt_and_install_all_missing(&app, None)? {
         info!("Cancelling setup. Pre-requisites not installed.");
@@ -44,12 +55,7 @@ pub fn install(debug_pkg: Option<&PathBuf>, install_to: Option<&PathBuf>) -> Res
     }
 
     info!("Determining install directory...");
-    let (root_path, root_is_default) = if install_to.is_some() {
-        (install_to.unwrap().clone(), false)
-    } else {
-        let appdata = w::SHGetKnownFolderPath(&co::KNOWNFOLDERID::LocalAppData, co::KF::DONT_UNEXPAND, None)?;
-        (Path::new(&appdata).join(&app.id), true)
-    };
+    let (root_path, root_is_default) = get_install_dir(&app, install_to)?;
 
     // path needs to exist for future operations (disk space etc)
     if !root_path.exists() {
@@ -60,29 +66,7 @@ pub fn install(debug_pkg: Option<&PathBuf>, install_to: Option<&PathBuf>) -> Res
     info!("Installation Directory: {:?}", root_path_str);
 
     // do we have enough disk space?
-    let (compressed_size, extracted_size) = pkg.calculate_size();
-    let required_space = compressed_size + extracted_size + (50 * 1000 * 1000); // archive + velopack overhead
-    let mut free_space: u64 = 0;
-    w::GetDiskFreeSpaceEx(Some(&root_path_str), None, None, Some(&mut free_space))?;
-    if free_space < required_space {
-        bail!(
-            "{} requires at least {} disk space to be installed. There is only {} available.",
-            &app.title,
-            pretty_bytes(required_space, None),
-            pretty_bytes(free_space, None)
-        );
-    }
-
-    info!("There is {} free space available at destination, this package requires {}.", pretty_bytes(free_space, None), pretty_bytes(required_space, None));
-
-    // does this app support this OS / architecture?
-    if !app.os_min_version.is_empty() && !windows::is_os_version_or_greater(&app.os_min_version)? {
-        bail!("This application requires Windows {} or later.", &app.os_min_version);
-    }
-
-    if !app.machine_architecture.is_empty() && !windows::is_cpu_architecture_supported(&app.machine_architecture)? {
-        bail!("This application ({}) does not support your CPU architecture.", &app.machine_architecture);
-    }
+    super::check_free_space(&app, get_required_install_space(&pkg), &root_path)?;
 
     let mut root_path_renamed = String::new();
     // does the target directory exist and have files? (eg. already installed)
@@ -145,6 +129,30 @@ pub fn install(debug_pkg: Option<&PathBuf>, install_to: Option<&PathBuf>) -> Res
     Ok(())
 }
 
+fn get_install_dir(app: &bundle::Manifest, install_to: Option<&PathBuf>) -> Result<(PathBuf, bool)> {
+    if let Some(install_to) = install_to {
+        return Ok((install_to.clone(), false));
+    }
+    let appdata = w::SHGetKnownFolderPath(&co::KNOWNFOLDERID::LocalAppData, co::KF::DONT_UNEXPAND, None)?;
+    Ok((Path::new(&appdata).join(&app.id), true))
+}
+
+fn get_required_install_space(pkg: &bundle::BundleInfo) -> u64 {
+    let (compressed_size, extracted_size) = pkg.calculate_size();
+    compressed_size + extracted_size + (50 * 1000 * 1000) // archive + velopack overhead
+}
+
+/// Reports what installing the bundle would do, without installing prerequisites or touching the install directory.
+fn install_dry_run(pkg: &bundle::BundleInfo, app: &bundle::Manifest, install_to: Option<&PathBuf>) -> Result<()> {
+    let (root_path, _) = get_install_dir(app, install_to)?;
+    info!("Dry run: installation directory would be {:?}", root_path);
+    super::check_free_space(app, get_required_install_space(pkg), &root_path)?;
+    let operations = super::plan_lib_file_operations(pkg, Path::new(&app.get_current_path(&root_path)))?;
+    super::report_file_operations(&operations);
+    info!("Dry run complete, nothing was changed.");
+    Ok(())
+}
+
 fn install_impl(pkg: &bundle::BundleInfo, root_path: &PathBuf, tx: &std::sync::mpsc::Sender<i16>) -> Result<()> {
     info!("Starting installation!");
 
@@ -182,15 +190,27 @@ fn install_impl(pkg: &bundle::BundleInfo, root_path: &PathBuf, tx: &std::sync::m
     let ver_string = app.version.to_string();
     info!("Starting process install hook: \"{}\" --veloapp-install {}", &main_exe_path, &ver_string);
     let args = vec!["--veloapp-install", &ver_string];
-    if let Err(e) = windows::run_process_no_console_and_wait(&main_exe_path, args, &current_path, Some(Duration::from_secs(30))) {
-        let setup_name = format!("{} Setup {}", app.title, app.version);
-        error!("Process install hook failed: {}", e);
-        let _ = tx.send(windows::splash::MSG_CLOSE);
-        dialogs::show_warn(
-            &setup_name,
-            None,
-            format!("Installation has completed, but the application install hook failed ({}). It may not have installed correctly.", e).as_str(),
-        );
+    let payload = HookPayload::new("--veloapp-install", None, &app, root_path, true).to_json();
+    let envs = [(HOOK_PAYLOAD_ENV, payload.as_str())];
+    let result = windows::run_process_no_console_and_wait(&main_exe_path, args, &current_path, &envs, Some(Duration::from_secs(30)));
+    match HookResult::from_process_result(&result) {
+        HookResult::Ok => {}
+        HookResult::Abort => bail!("The application's --veloapp-install hook aborted the installation."),
+        HookResult::RetryLater => {
+            warn!("The application's --veloapp-install hook asked to be run again later.");
+            super::record_pending_retry(&packages_path, &super::PendingRetry::InstallHook)?;
+        }
+        HookResult::Failed => {
+            let e = result.err().map(|e| e.to_string()).unwrap_or_default();
+            let setup_name = format!("{} Setup {}", app.title, app.version);
+            error!("Process install hook failed: {}", e);
+            let _ = tx.send(windows::splash::MSG_CLOSE);
+            dialogs::show_warn(
+                &setup_name,
+                None,
+                format!("Installation has completed, but the application install hook failed ({}). It may not have installed correctly.", e).as_str(),
+            );
+        }
     }
 
     let _ = tx.send(100);
@@ -0,0 +1,344 @@
+use crate::shared;
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+const JOURNAL_FILE_NAME: &str = "apply.journal";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum JournalKind {
+    /// `current` is renamed to `old`, then `new` is renamed to `current`.
+    DirectorySwap,
+    /// `current` is overwritten by `new` (renamed or copied) in one step.
+    FileReplace,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub enum JournalPhase {
+    /// The new version is fully staged at `new_path`, nothing destructive has happened yet.
+    Prepared,
+    /// `current_path` has been moved to `old_path`.
+    OldMoved,
+    /// `new_path` has been moved or copied to `current_path`.
+    NewMoved,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecoveryOutcome {
+    /// The install was already consistent, only the journal was left behind.
+    Consistent,
+    /// The previous version was restored.
+    RolledBack,
+    /// The new version was put in place.
+    RolledForward,
+}
+
+/// Records the intent and progress of an apply, so that an install left half-swapped (eg. by a
+/// power loss) can be put back into a consistent state by the next updater invocation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApplyJournal {
+    pub kind: JournalKind,
+    pub phase: JournalPhase,
+    pub from_version: String,
+    pub to_version: String,
+    pub current_path: PathBuf,
+    pub old_path: Option<PathBuf>,
+    pub new_path: PathBuf,
+    #[serde(skip)]
+    journal_path: PathBuf,
+}
+
+/// The journal lives next to the temp files used while applying, which survive a reboot.
+pub fn get_journal_path(app_id: &str, root_path: &Path) -> Result<PathBuf> {
+    Ok(shared::get_location_checked(shared::LocationKind::Staging, app_id, root_path)?.join(JOURNAL_FILE_NAME))
+}
+
+#[cfg(test)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum InjectedFailure {
+    /// The process dies at this point, nothing after it runs.
+    Crash,
+    /// The step fails with this OS error code.
+    Error(i32),
+}
+
+#[cfg(test)]
+thread_local! {
+    static INJECTED_FAILURES: std::cell::RefCell<Vec<(&'static str, InjectedFailure)>> = std::cell::RefCell::new(Vec::new());
+}
+
+/// Makes the named `fail_point` fail once, on the current thread only.
+#[cfg(test)]
+pub(crate) fn inject_failure(name: &'static str, failure: InjectedFailure) {
+    INJECTED_FAILURES.with(|f| f.borrow_mut().push((name, failure)));
+}
+
+/// Marks a step of a destructive apply where tests can inject an I/O error or a crash. Does nothing outside of tests.
+pub(crate) fn fail_point(name: &str) -> std::io::Result<()> {
+    #[cfg(test)]
+    {
+        let failure = INJECTED_FAILURES.with(|f| {
+            let mut failures = f.borrow_mut();
+            let idx = failures.iter().position(|(n, _)| *n == name)?;
+            Some(failures.remove(idx).1)
+        });
+        match failure {
+            Some(InjectedFailure::Crash) => panic!("injected crash at {}", name),
+            Some(InjectedFailure::Error(code)) => return Err(std::io::Error::from_raw_os_error(code)),
+            None => {}
+        }
+    }
+    let _ = name;
+    Ok(())
+}
+
+impl ApplyJournal {
+    /// Writes a new journal in the `Prepared` phase. Must be called after the new version has been
+    /// fully staged, and before anything at `current_path` is touched.
+    pub fn begin<P: AsRef<Path>>(
+        journal_path: P,
+        kind: JournalKind,
+        from_version: &str,
+        to_version: &str,
+        current_path: &Path,
+        old_path: Option<&Path>,
+        new_path: &Path,
+    ) -> Result<ApplyJournal> {
+        let journal = ApplyJournal {
+            kind,
+            phase: JournalPhase::Prepared,
+            from_version: from_version.to_owned(),
+            to_version: to_version.to_owned(),
+            current_path: current_path.to_path_buf(),
+            old_path: old_path.map(|p| p.to_path_buf()),
+            new_path: new_path.to_path_buf(),
+            journal_path: journal_path.as_ref().to_path_buf(),
+        };
+        info!("Writing apply journal to {}", journal.journal_path.to_string_lossy());
+        journal.write()?;
+        Ok(journal)
+    }
+
+    pub fn load<P: AsRef<Path>>(journal_path: P) -> Result<Option<ApplyJournal>> {
+        let journal_path = journal_path.as_ref();
+        if !journal_path.exists() {
+            return Ok(None);
+        }
+        let json = fs::read_to_string(journal_path)?;
+        let mut journal: ApplyJournal = serde_json::from_str(&json)?;
+        journal.journal_path = journal_path.to_path_buf();
+        Ok(Some(journal))
+    }
+
+    /// Records that a destructive step has completed.
+    pub fn set_phase(&mut self, phase: JournalPhase) -> Result<()> {
+        self.phase = phase;
+        self.write()
+    }
+
+    /// Removes the journal once the install is consistent again.
+    pub fn complete(self) -> Result<()> {
+        if self.journal_path.exists() {
+            shared::retry_io(|| fs::remove_file(&self.journal_path))?;
+        }
+        Ok(())
+    }
+
+    fn write(&self) -> Result<()> {
+        if let Some(parent) = self.journal_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // write to a temp file and rename, so a crash can never leave a truncated journal behind
+        let tmp_path = self.journal_path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        file.sync_all()?;
+        shared::retry_io(|| fs::rename(&tmp_path, &self.journal_path))?;
+        Ok(())
+    }
+}
+
+/// Detects a journal left behind by an interrupted apply for the current installation, and restores it to a consistent state.
+/// On Linux and macOS the updater lives inside the bundle which is being replaced, so this can only help if it is still runnable.
+pub fn recover_interrupted_apply() -> Result<Option<RecoveryOutcome>> {
+    #[cfg(target_os = "windows")]
+    let (app_id, root_path, current_path) = {
+        let mut root_path = std::env::current_exe()?;
+        root_path.pop();
+        let current_path = root_path.join("current");
+        (String::new(), root_path, current_path)
+    };
+    #[cfg(not(target_os = "windows"))]
+    let (app_id, root_path, current_path) = {
+        let (root_path, app) = shared::detect_current_manifest()?;
+        (app.id, root_path.clone(), root_path)
+    };
+    let journal_path = get_journal_path(&app_id, &root_path)?;
+    let staging_dir = shared::get_location(shared::LocationKind::Staging, &app_id, &root_path);
+    recover_apply_journal(journal_path, &current_path, &staging_dir)
+}
+
+/// Recovers an interrupted apply of the install at `current_path`. The journal is only trusted if its directory can not have
+/// been written by another user, and it only refers to `current_path` and to temp files inside `staging_dir`.
+pub fn recover_apply_journal<P: AsRef<Path>>(journal_path: P, current_path: &Path, staging_dir: &Path) -> Result<Option<RecoveryOutcome>> {
+    let journal_path = journal_path.as_ref();
+    if !journal_path.exists() {
+        return Ok(None);
+    }
+    if let Some(journal_dir) = journal_path.parent() {
+        shared::verify_location(journal_dir)?;
+    }
+    let journal = match ApplyJournal::load(journal_path)? {
+        Some(j) => j,
+        None => return Ok(None),
+    };
+
+    if journal.current_path != current_path {
+        bail!("Refusing to recover apply journal, it refers to '{}' instead of '{}'.", journal.current_path.to_string_lossy(), current_path.to_string_lossy());
+    }
+    for temp_path in std::iter::once(&journal.new_path).chain(journal.old_path.iter()) {
+        if !is_inside_dir(temp_path, staging_dir) {
+            bail!("Refusing to recover apply journal, '{}' is outside of '{}'.", temp_path.to_string_lossy(), staging_dir.to_string_lossy());
+        }
+    }
+
+    warn!(
+        "Found an interrupted apply ({} -> {}, phase {:?}) in {}, recovering...",
+        journal.from_version,
+        journal.to_version,
+        journal.phase,
+        journal_path.to_string_lossy()
+    );
+
+    let current_exists = journal.current_path.exists();
+    let new_exists = journal.new_path.exists();
+    let old_exists = journal.old_path.as_ref().is_some_and(|p| p.exists());
+
+    let outcome = match journal.kind {
+        JournalKind::DirectorySwap => {
+            // the new version is never deleted here, because it may be a retained version being rolled back to
+            if current_exists {
+                // either nothing was moved yet, or both moves completed and the previous version is no longer needed
+                if let Some(old_path) = journal.old_path.as_ref().filter(|_| old_exists) {
+                    let _ = remove_path(old_path);
+                }
+                RecoveryOutcome::Consistent
+            } else if old_exists {
+                let old_path = journal.old_path.as_ref().unwrap();
+                info!("Restoring previous version {} from {}", journal.from_version, old_path.to_string_lossy());
+                shared::retry_io(|| fs::rename(old_path, &journal.current_path))?;
+                RecoveryOutcome::RolledBack
+            } else if new_exists {
+                info!("Completing update to {} from {}", journal.to_version, journal.new_path.to_string_lossy());
+                shared::retry_io(|| fs::rename(&journal.new_path, &journal.current_path))?;
+                RecoveryOutcome::RolledForward
+            } else {
+                bail!("Unable to recover interrupted apply, neither the previous nor the new version could be found.");
+            }
+        }
+        JournalKind::FileReplace => {
+            if new_exists {
+                // a rename would have consumed the staged file, so the target may be partially copied
+                info!("Completing update to {} from {}", journal.to_version, journal.new_path.to_string_lossy());
+                if shared::retry_io(|| fs::rename(&journal.new_path, &journal.current_path)).is_err() {
+                    shared::retry_io(|| fs::copy(&journal.new_path, &journal.current_path))?;
+                    let _ = fs::remove_file(&journal.new_path);
+                }
+                RecoveryOutcome::RolledForward
+            } else if current_exists {
+                RecoveryOutcome::Consistent
+            } else {
+                bail!("Unable to recover interrupted apply, neither the current nor the new version could be found.");
+            }
+        }
+    };
+
+    info!("Recovery of interrupted apply finished: {:?}", outcome);
+    journal.complete()?;
+    Ok(Some(outcome))
+}
+
+fn remove_path(path: &Path) -> Result<()> {
+    if path.is_dir() {
+        remove_dir_all::remove_dir_all(path)?;
+    } else {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+fn is_inside_dir(path: &Path, dir: &Path) -> bool {
+    path != dir && path.starts_with(dir) && !path.components().any(|c| c == std::path::Component::ParentDir)
+}
+
+#[cfg(test)]
+fn write_test_directory_swap(dir: &Path, phase: JournalPhase, current: Option<&str>, old: Option<&str>, new: Option<&str>) -> PathBuf {
+    let journal_path = dir.join(JOURNAL_FILE_NAME);
+    for (name, version) in [("current", current), ("tmp_old", old), ("tmp_new", new)] {
+        if let Some(version) = version {
+            fs::create_dir_all(dir.join(name)).unwrap();
+            fs::write(dir.join(name).join("version"), version).unwrap();
+        }
+    }
+    let mut journal =
+        ApplyJournal::begin(&journal_path, JournalKind::DirectorySwap, "1.0.0", "2.0.0", &dir.join("current"), Some(&dir.join("tmp_old")), &dir.join("tmp_new"))
+            .unwrap();
+    journal.set_phase(phase).unwrap();
+    journal_path
+}
+
+#[test]
+fn test_journal_recovers_directory_swap_states() {
+    use JournalPhase::*;
+    let cases = [
+        // nothing moved yet
+        (Prepared, Some("1.0.0"), None, Some("2.0.0"), RecoveryOutcome::Consistent, "1.0.0"),
+        // current moved away, the new version is not in place yet
+        (Prepared, None, Some("1.0.0"), Some("2.0.0"), RecoveryOutcome::RolledBack, "1.0.0"),
+        (OldMoved, None, Some("1.0.0"), Some("2.0.0"), RecoveryOutcome::RolledBack, "1.0.0"),
+        // the previous version was lost (eg. removed by a cleanup tool), so the update is completed instead
+        (OldMoved, None, None, Some("2.0.0"), RecoveryOutcome::RolledForward, "2.0.0"),
+        // both moves completed, only the cleanup is missing
+        (OldMoved, Some("2.0.0"), Some("1.0.0"), None, RecoveryOutcome::Consistent, "2.0.0"),
+        (NewMoved, Some("2.0.0"), Some("1.0.0"), None, RecoveryOutcome::Consistent, "2.0.0"),
+    ];
+    for (phase, current, old, new, expected, expected_version) in cases {
+        let tmp = tempfile::tempdir().unwrap();
+        let journal_path = write_test_directory_swap(tmp.path(), phase, current, old, new);
+        let outcome = recover_apply_journal(&journal_path, &tmp.path().join("current"), tmp.path()).unwrap();
+        assert_eq!(outcome, Some(expected));
+        assert_eq!(fs::read_to_string(tmp.path().join("current").join("version")).unwrap(), expected_version);
+        assert!(!journal_path.exists());
+        assert!(!tmp.path().join("tmp_old").exists());
+    }
+}
+
+#[test]
+fn test_journal_is_only_trusted_for_this_install() {
+    let tmp = tempfile::tempdir().unwrap();
+    let staging_dir = tmp.path().join("staging");
+    let journal_path = write_test_directory_swap(&staging_dir, JournalPhase::OldMoved, None, Some("1.0.0"), Some("2.0.0"));
+
+    // the journal must refer to this install, and only to temp files in the staging dir
+    let err = recover_apply_journal(&journal_path, &tmp.path().join("other"), &staging_dir).unwrap_err();
+    assert!(err.to_string().contains("instead of"), "{}", err);
+    let err = recover_apply_journal(&journal_path, &staging_dir.join("current"), &tmp.path().join("elsewhere")).unwrap_err();
+    assert!(err.to_string().contains("is outside of"), "{}", err);
+    assert!(!is_inside_dir(&staging_dir.join("..").join("current"), &staging_dir));
+    assert!(staging_dir.join("tmp_old").exists());
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&staging_dir, fs::Permissions::from_mode(0o777)).unwrap();
+        let err = recover_apply_journal(&journal_path, &staging_dir.join("current"), &staging_dir).unwrap_err();
+        assert!(err.to_string().contains("writable by other users"), "{}", err);
+        fs::set_permissions(&staging_dir, fs::Permissions::from_mode(0o700)).unwrap();
+    }
+
+    assert_eq!(recover_apply_journal(&journal_path, &staging_dir.join("current"), &staging_dir).unwrap(), Some(RecoveryOutcome::RolledBack));
+}
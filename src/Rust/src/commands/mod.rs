@@ -1,9 +1,36 @@
 mod apply;
 pub use apply::*;
 
+mod compatibility;
+pub use compatibility::*;
+
+mod dry_run;
+pub use dry_run::*;
+
+mod health;
+pub use health::*;
+
+mod journal;
+pub use journal::*;
+
 mod patch;
 pub use patch::*;
 
+mod prune;
+pub use prune::*;
+
+mod retry;
+pub use retry::*;
+
+mod rollback;
+pub use rollback::*;
+
+mod switch_channel;
+pub use switch_channel::*;
+
+mod uninstall;
+pub use uninstall::*;
+
 #[cfg(target_os = "linux")]
 mod apply_linux_impl;
 #[cfg(target_os = "macos")]
@@ -11,9 +38,7 @@ mod apply_osx_impl;
 #[cfg(target_os = "windows")]
 mod apply_windows_impl;
 
-#[cfg(target_os = "windows")]
 mod start;
-#[cfg(target_os = "windows")]
 pub use start::*;
 
 #[cfg(target_os = "windows")]
@@ -21,7 +46,3 @@ mod install;
 #[cfg(target_os = "windows")]
 pub use install::*;
 
-#[cfg(target_os = "windows")]
-mod uninstall;
-#[cfg(target_os = "windows")]
-pub use uninstall::*;
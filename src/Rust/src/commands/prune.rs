@@ -0,0 +1,177 @@
+use crate::shared::{self, bundle, bundle::Manifest};
+use anyhow::Result;
+use pretty_bytes_rust::pretty_bytes;
+use semver::Version;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The number of full packages which are kept in the packages directory after an update.
+pub const PACKAGES_RETAIN_COUNT: usize = 2;
+
+#[derive(Debug, Clone, PartialEq, strum::IntoStaticStr)]
+pub enum PruneReason {
+    OldFullPackage,
+    AppliedDeltaPackage,
+    PartialDownload,
+    TempDirectory,
+}
+
+#[derive(Debug, Clone)]
+pub struct PruneItem {
+    pub path: PathBuf,
+    pub reason: PruneReason,
+    pub size: u64,
+}
+
+pub fn prune(root_path: &PathBuf, app: &Manifest, keep: usize, dry_run: bool) -> Result<()> {
+    let packages_dir = super::get_packages_dir(app, root_path);
+    let items = prune_packages(&packages_dir, &app.version, keep, dry_run)?;
+    let total: u64 = items.iter().map(|i| i.size).sum();
+    if dry_run {
+        info!("Dry run: {} item(s) totalling {} would be removed from {}", items.len(), pretty_bytes(total, None), packages_dir);
+    } else {
+        info!("Removed {} item(s) totalling {} from {}", items.len(), pretty_bytes(total, None), packages_dir);
+    }
+    Ok(())
+}
+
+/// Removes everything which is no longer needed from the packages directory: full packages
+/// beyond the newest `keep` (the package for the current version is always kept, as it is the
+/// base for future delta updates), delta packages which have already been applied, incomplete
+/// `.partial` downloads and `tmp_*` directories left over from an interrupted apply.
+pub fn prune_packages<P: AsRef<Path>>(packages_dir: P, current_version: &Version, keep: usize, dry_run: bool) -> Result<Vec<PruneItem>> {
+    let items = plan_prune_packages(packages_dir, current_version, keep)?;
+    for item in &items {
+        let reason: &str = (&item.reason).into();
+        if dry_run {
+            info!("Would remove '{}' ({}, {})", item.path.to_string_lossy(), reason, pretty_bytes(item.size, None));
+            continue;
+        }
+        info!("Removing '{}' ({}, {})", item.path.to_string_lossy(), reason, pretty_bytes(item.size, None));
+        let result = if item.path.is_dir() { remove_dir_all::remove_dir_all(&item.path) } else { shared::retry_io(|| fs::remove_file(&item.path)) };
+        if let Err(e) = result {
+            warn!("Failed to remove '{}' ({}).", item.path.to_string_lossy(), e);
+        }
+    }
+    Ok(items)
+}
+
+pub fn plan_prune_packages<P: AsRef<Path>>(packages_dir: P, current_version: &Version, keep: usize) -> Result<Vec<PruneItem>> {
+    let packages_dir = packages_dir.as_ref();
+    let mut items = Vec::new();
+    let mut full_packages = Vec::new();
+
+    if !packages_dir.exists() {
+        return Ok(items);
+    }
+
+    for entry in fs::read_dir(packages_dir)?.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+
+        if is_dir {
+            if name.starts_with("tmp_") {
+                items.push(PruneItem { size: get_size(&path), path, reason: PruneReason::TempDirectory });
+            }
+        } else if name.to_ascii_lowercase().ends_with(".partial") {
+            items.push(PruneItem { size: get_size(&path), path, reason: PruneReason::PartialDownload });
+        } else if let Some(pkg) = bundle::parse_package_file_path(path.clone()) {
+            if pkg.is_delta {
+                if &pkg.version <= current_version {
+                    items.push(PruneItem { size: get_size(&path), path, reason: PruneReason::AppliedDeltaPackage });
+                }
+            } else {
+                full_packages.push(pkg);
+            }
+        }
+    }
+
+    full_packages.sort_by(|a, b| b.version.cmp(&a.version));
+    for pkg in full_packages.iter().skip(keep) {
+        if &pkg.version == current_version {
+            continue;
+        }
+        let path = PathBuf::from(&pkg.file_path);
+        items.push(PruneItem { size: get_size(&path), path, reason: PruneReason::OldFullPackage });
+    }
+
+    Ok(items)
+}
+
+fn get_size(path: &Path) -> u64 {
+    let meta = match fs::symlink_metadata(path) {
+        Ok(m) => m,
+        Err(_) => return 0,
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+    fs::read_dir(path).map(|entries| entries.flatten().map(|e| get_size(&e.path())).sum()).unwrap_or(0)
+}
+
+#[test]
+fn test_prune_plan_keeps_current_and_newest_packages() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    for name in [
+        "MyApp-1.0.0-full.nupkg",
+        "MyApp-1.1.0-full.nupkg",
+        "MyApp-1.1.0-delta.nupkg",
+        "MyApp-1.2.0-full.nupkg",
+        "MyApp-1.2.0-delta.nupkg",
+        "MyApp-1.3.0-full.nupkg",
+        "MyApp-1.3.0-delta.nupkg",
+        "MyApp-1.4.0-full.nupkg.partial",
+        ".betaId",
+        "notes.txt",
+    ] {
+        fs::write(dir.join(name), "x").unwrap();
+    }
+    fs::create_dir_all(dir.join("tmp_abcdefgh").join("lib")).unwrap();
+    fs::write(dir.join("tmp_abcdefgh").join("lib").join("a.dll"), "abc").unwrap();
+    fs::create_dir_all(dir.join("rollback").join("1.0.0")).unwrap();
+
+    let current = Version::parse("1.2.0").unwrap();
+    let mut plan: Vec<(String, PruneReason)> = plan_prune_packages(dir, &current, 1)
+        .unwrap()
+        .into_iter()
+        .map(|i| (i.path.file_name().unwrap().to_string_lossy().to_string(), i.reason))
+        .collect();
+    plan.sort_by(|a, b| a.0.cmp(&b.0));
+
+    assert_eq!(
+        plan,
+        vec![
+            ("MyApp-1.0.0-full.nupkg".to_string(), PruneReason::OldFullPackage),
+            ("MyApp-1.1.0-delta.nupkg".to_string(), PruneReason::AppliedDeltaPackage),
+            ("MyApp-1.1.0-full.nupkg".to_string(), PruneReason::OldFullPackage),
+            ("MyApp-1.2.0-delta.nupkg".to_string(), PruneReason::AppliedDeltaPackage),
+            ("MyApp-1.4.0-full.nupkg.partial".to_string(), PruneReason::PartialDownload),
+            ("tmp_abcdefgh".to_string(), PruneReason::TempDirectory),
+        ]
+    );
+}
+
+#[test]
+fn test_prune_dry_run_does_not_delete() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    fs::write(dir.join("MyApp-1.0.0-full.nupkg"), "x").unwrap();
+    fs::write(dir.join("MyApp-1.1.0-full.nupkg"), "x").unwrap();
+    fs::write(dir.join("MyApp-1.1.0-full.nupkg.partial"), "xyz").unwrap();
+
+    let current = Version::parse("1.1.0").unwrap();
+    let items = prune_packages(dir, &current, 1, true).unwrap();
+    assert_eq!(items.len(), 2);
+    assert_eq!(items.iter().map(|i| i.size).sum::<u64>(), 4);
+    assert!(dir.join("MyApp-1.0.0-full.nupkg").exists());
+    assert!(dir.join("MyApp-1.1.0-full.nupkg.partial").exists());
+
+    prune_packages(dir, &current, 1, false).unwrap();
+    assert!(!dir.join("MyApp-1.0.0-full.nupkg").exists());
+    assert!(!dir.join("MyApp-1.1.0-full.nupkg.partial").exists());
+    assert!(dir.join("MyApp-1.1.0-full.nupkg").exists());
+}
@@ -0,0 +1,142 @@
+use crate::shared::{self, bundle::Manifest, OperationWait};
+use anyhow::{anyhow, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+const PENDING_RETRY_FILE_NAME: &str = "pending-retry.txt";
+
+/// An operation which a hook asked to be retried later. It is retried the next time the application is started.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PendingRetry {
+    /// The package with this file name in the packages directory is applied again.
+    Apply(String),
+    /// The `--veloapp-install` hook is run again.
+    InstallHook,
+}
+
+/// Records an apply which a hook postponed. The package is copied into the packages directory if it is not
+/// already there, so it is still available when the apply is retried.
+pub fn record_pending_apply<P: AsRef<Path>>(packages_dir: P, package: &Path) -> Result<()> {
+    let packages_dir = packages_dir.as_ref();
+    let file_name = package.file_name().ok_or_else(|| anyhow!("Package path '{}' has no file name.", package.to_string_lossy()))?;
+    let kept_path = packages_dir.join(file_name);
+    fs::create_dir_all(packages_dir)?;
+    if !kept_path.exists() {
+        info!("Keeping package {} for a later retry.", package.to_string_lossy());
+        shared::retry_io(|| fs::copy(package, &kept_path))?;
+    }
+    record_pending_retry(packages_dir, &PendingRetry::Apply(file_name.to_string_lossy().to_string()))
+}
+
+pub fn record_pending_retry<P: AsRef<Path>>(packages_dir: P, pending: &PendingRetry) -> Result<()> {
+    let packages_dir = packages_dir.as_ref();
+    let contents = match pending {
+        PendingRetry::Apply(file_name) => format!("apply\n{}\n", file_name),
+        PendingRetry::InstallHook => "install-hook\n".to_owned(),
+    };
+    fs::create_dir_all(packages_dir)?;
+    fs::write(packages_dir.join(PENDING_RETRY_FILE_NAME), contents)?;
+    info!("Recorded {:?} to be retried the next time the application is started.", pending);
+    Ok(())
+}
+
+/// Reads and clears the operation which is waiting to be retried, if any. If it is postponed again, it is
+/// recorded again by the retried operation.
+pub fn take_pending_retry<P: AsRef<Path>>(packages_dir: P) -> Option<PendingRetry> {
+    let marker_path = packages_dir.as_ref().join(PENDING_RETRY_FILE_NAME);
+    let contents = fs::read_to_string(&marker_path).ok()?;
+    let _ = fs::remove_file(&marker_path);
+    let mut lines = contents.lines().map(|l| l.trim());
+    match (lines.next(), lines.next()) {
+        (Some("apply"), Some(file_name)) if is_plain_file_name(file_name) => Some(PendingRetry::Apply(file_name.to_owned())),
+        (Some("install-hook"), _) => Some(PendingRetry::InstallHook),
+        _ => {
+            warn!("Ignoring unrecognised pending retry: {:?}", contents);
+            None
+        }
+    }
+}
+
+/// Returns true if an apply of this package is waiting to be retried.
+pub fn has_pending_apply<P: AsRef<Path>>(packages_dir: P, package: &Path) -> bool {
+    let marker_path = packages_dir.as_ref().join(PENDING_RETRY_FILE_NAME);
+    let Ok(contents) = fs::read_to_string(marker_path) else {
+        return false;
+    };
+    let mut lines = contents.lines().map(|l| l.trim());
+    match (lines.next(), lines.next(), package.file_name()) {
+        (Some("apply"), Some(file_name), Some(package_name)) => package_name.to_string_lossy() == file_name,
+        _ => false,
+    }
+}
+
+fn is_plain_file_name(file_name: &str) -> bool {
+    let mut components = Path::new(file_name).components();
+    matches!((components.next(), components.next()), (Some(std::path::Component::Normal(_)), None))
+}
+
+/// Retries an operation which a hook previously postponed. Returns true if a new version was applied.
+pub fn retry_pending_operation(root_path: &PathBuf, app: &Manifest) -> Result<bool> {
+    let packages_dir = super::get_packages_dir(app, root_path);
+    match take_pending_retry(&packages_dir) {
+        None => Ok(false),
+        Some(PendingRetry::Apply(file_name)) => {
+            let package = Path::new(&packages_dir).join(file_name);
+            if !package.exists() {
+                warn!("Unable to retry applying {}, it no longer exists.", package.to_string_lossy());
+                return Ok(false);
+            }
+            info!("Retrying postponed apply of {}.", package.to_string_lossy());
+            match super::apply(root_path, app, false, OperationWait::NoWait, Some(&package), None, None, false, true) {
+                Ok(()) => Ok(true),
+                Err(e) => {
+                    warn!("Postponed apply did not succeed ({}).", e);
+                    Ok(false)
+                }
+            }
+        }
+        #[cfg(target_os = "windows")]
+        Some(PendingRetry::InstallHook) => {
+            use crate::shared::hooks::{HookPayload, HookResult};
+            info!("Retrying postponed --veloapp-install hook.");
+            let payload = HookPayload::new("--veloapp-install", None, app, root_path, true);
+            match crate::windows::run_hook(app, root_path, &payload, 30) {
+                HookResult::RetryLater => record_pending_retry(&packages_dir, &PendingRetry::InstallHook)?,
+                result => result.ensure_continue("--veloapp-install")?,
+            }
+            Ok(false)
+        }
+        #[cfg(not(target_os = "windows"))]
+        Some(PendingRetry::InstallHook) => {
+            warn!("The --veloapp-install hook is only run on Windows, ignoring.");
+            Ok(false)
+        }
+    }
+}
+
+#[test]
+fn test_pending_apply_keeps_package_until_retried() {
+    let tmp = tempfile::tempdir().unwrap();
+    let packages_dir = tmp.path().join("packages");
+    let package = tmp.path().join("MyApp-2.0.0-full.nupkg");
+    fs::write(&package, "package").unwrap();
+
+    assert_eq!(take_pending_retry(&packages_dir), None);
+    assert!(!has_pending_apply(&packages_dir, &package));
+    record_pending_apply(&packages_dir, &package).unwrap();
+    assert!(has_pending_apply(&packages_dir, &package));
+    assert!(!has_pending_apply(&packages_dir, Path::new("MyApp-3.0.0-full.nupkg")));
+    fs::remove_file(&package).unwrap();
+    assert_eq!(fs::read_to_string(packages_dir.join("MyApp-2.0.0-full.nupkg")).unwrap(), "package");
+
+    assert_eq!(take_pending_retry(&packages_dir), Some(PendingRetry::Apply("MyApp-2.0.0-full.nupkg".to_owned())));
+    assert_eq!(take_pending_retry(&packages_dir), None);
+
+    record_pending_retry(&packages_dir, &PendingRetry::InstallHook).unwrap();
+    assert_eq!(take_pending_retry(&packages_dir), Some(PendingRetry::InstallHook));
+
+    fs::write(packages_dir.join(PENDING_RETRY_FILE_NAME), "apply\n../../evil.nupkg\n").unwrap();
+    assert_eq!(take_pending_retry(&packages_dir), None);
+}
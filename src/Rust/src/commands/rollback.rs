@@ -0,0 +1,227 @@
+use crate::shared::{self, bundle::Manifest, OperationWait};
+use anyhow::{anyhow, bail, Result};
+use semver::Version;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+#[cfg(target_os = "linux")]
+use super::apply_linux_impl::rollback_package_impl;
+#[cfg(target_os = "macos")]
+use super::apply_osx_impl::rollback_package_impl;
+#[cfg(target_os = "windows")]
+use super::apply_windows_impl::rollback_package_impl;
+
+/// The number of previously installed versions which are kept available for `rollback`, unless configured otherwise.
+pub const ROLLBACK_RETAIN_COUNT: usize = 2;
+
+/// The environment variable which overrides `ROLLBACK_RETAIN_COUNT`, it is inherited by the updater when started by the app.
+pub const ROLLBACK_RETAIN_COUNT_ENV: &str = "VELOPACK_ROLLBACK_RETAIN_COUNT";
+
+static ROLLBACK_RETAIN_OVERRIDE: Mutex<Option<usize>> = Mutex::new(None);
+
+/// Overrides the number of retained versions for the rest of this process, eg. from a command line flag. Takes precedence over the environment.
+pub fn set_rollback_retain_count(count: usize) {
+    info!("Keeping up to {} previous version(s) for rollback.", count);
+    *ROLLBACK_RETAIN_OVERRIDE.lock().unwrap() = Some(count);
+}
+
+/// The number of previously installed versions to keep, from a command line override, the environment, or the default.
+pub fn get_rollback_retain_count() -> usize {
+    if let Some(count) = *ROLLBACK_RETAIN_OVERRIDE.lock().unwrap() {
+        return clamp_retain_count(count);
+    }
+    let count = match std::env::var(ROLLBACK_RETAIN_COUNT_ENV) {
+        Ok(value) => value.trim().parse::<usize>().unwrap_or_else(|_| {
+            warn!("Ignoring invalid {} '{}'.", ROLLBACK_RETAIN_COUNT_ENV, value);
+            ROLLBACK_RETAIN_COUNT
+        }),
+        Err(_) => ROLLBACK_RETAIN_COUNT,
+    };
+    clamp_retain_count(count)
+}
+
+/// At least one version is always kept, otherwise the version which was just retained would be removed straight away.
+fn clamp_retain_count(count: usize) -> usize {
+    if count == 0 {
+        warn!("A rollback retain count of 0 is not supported, keeping 1 previous version.");
+        return 1;
+    }
+    count
+}
+
+#[derive(Debug, Clone)]
+pub struct RetainedVersion {
+    pub version: Version,
+    pub path: PathBuf,
+}
+
+pub fn rollback(root_path: &PathBuf, app: &Manifest, restart: bool, wait: OperationWait, exe_args: Option<Vec<&str>>, runhooks: bool) -> Result<()> {
+    shared::operation_wait(wait);
+
+    let rollback_dir = get_rollback_dir(app, root_path)?;
+    let retained = list_retained_versions(&rollback_dir);
+    info!("Found {} retained version(s) in {}", retained.len(), rollback_dir.to_string_lossy());
+
+    let packages_dir = super::get_packages_dir(app, root_path);
+    let target = select_rollback_target(&retained, &app.version, &packages_dir);
+    let target = match target {
+        Some(t) => t,
+        None => bail!("There is no previous version of {} available to roll back to.", app.title),
+    };
+
+    info!("Rolling back {} from {} to {}: {}", app.id, app.version, target.version, target.path.to_string_lossy());
+    match rollback_package_impl(root_path, app, &target.path, true, runhooks) {
+        Ok(restored_app) => {
+            info!("Rolled back to version {} successfully.", restored_app.version);
+            if restart {
+                shared::start_package(&restored_app, root_path, exe_args, Some("VELOPACK_RESTART"))?;
+            }
+            Ok(())
+        }
+        Err(e) => {
+            error!("Error rolling back: {}", e);
+            if restart {
+                shared::start_package(app, root_path, exe_args, Some("VELOPACK_RESTART"))?;
+            }
+            bail!("Rollback failed, see logs for details.");
+        }
+    }
+}
+
+/// Restores the retained copy of `previous` over the `applied` version which replaced it.
+pub fn restore_previous_version(root_path: &PathBuf, previous: &Manifest, applied: &Manifest) -> Result<Manifest> {
+    let rollback_dir = get_rollback_dir(applied, root_path)?;
+    let retained = list_retained_versions(&rollback_dir);
+    let target = match retained.iter().find(|r| r.version == previous.version) {
+        Some(t) => t,
+        None => bail!("There is no retained copy of version {} to restore.", previous.version),
+    };
+    info!("Restoring version {} over {}: {}", previous.version, applied.version, target.path.to_string_lossy());
+    // the version being replaced failed or was vetoed, so it is not retained
+    rollback_package_impl(root_path, applied, &target.path, false, false)
+}
+
+/// Restores the previous version after the `--veloapp-updated` hook of the new version vetoed the update.
+/// Always returns an error describing the outcome, so the apply is reported as failed.
+pub fn rollback_vetoed_update(root_path: &PathBuf, previous: &Manifest, applied: &Manifest) -> anyhow::Error {
+    warn!("The --veloapp-updated hook of version {} vetoed the update, rolling back to {}.", applied.version, previous.version);
+    match restore_previous_version(root_path, previous, applied) {
+        Ok(_) => anyhow!("The update to {} was vetoed by the application, version {} has been restored.", applied.version, previous.version),
+        Err(e) => anyhow!("The update to {} was vetoed by the application, but restoring version {} failed ({}).", applied.version, previous.version, e),
+    }
+}
+
+/// The directory where previous versions are retained. It is placed on the same volume as the
+/// temp files used while applying updates, so versions can be moved in and out of it quickly.
+/// Retained versions are restored over the installed application, so the dir is verified to be private.
+pub fn get_rollback_dir(app: &Manifest, root_path: &Path) -> Result<PathBuf> {
+    let rollback_dir = shared::get_location_checked(shared::LocationKind::Staging, &app.id, root_path)?.join("rollback");
+    shared::create_private_dir(&rollback_dir)?;
+    Ok(rollback_dir)
+}
+
+/// Lists the retained versions in a rollback directory, newest first. Each entry is named
+/// after the version it contains, optionally followed by an extension (eg. `1.0.0.app`).
+pub fn list_retained_versions<P: AsRef<Path>>(rollback_dir: P) -> Vec<RetainedVersion> {
+    let mut versions = Vec::new();
+    if let Ok(entries) = fs::read_dir(rollback_dir.as_ref()) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let name = name.strip_suffix(".app").unwrap_or(&name);
+            if let Ok(version) = Version::parse(name) {
+                versions.push(RetainedVersion { version, path: entry.path() });
+            }
+        }
+    }
+    versions.sort_by(|a, b| b.version.cmp(&a.version));
+    versions
+}
+
+/// Picks the newest retained version older than the current one. If there is none (eg. the current version was reached
+/// by a downgrade) the newest retained version is used instead. Versions which failed their health check are skipped.
+pub fn select_rollback_target<'a, P: AsRef<Path>>(retained: &'a [RetainedVersion], current: &Version, packages_dir: P) -> Option<&'a RetainedVersion> {
+    let candidates = || retained.iter().filter(|r| !super::is_failed_version(&packages_dir, &r.version));
+    candidates().find(|r| &r.version < current).or_else(|| candidates().find(|r| &r.version != current))
+}
+
+/// Removes the oldest retained versions until at most `keep` remain.
+pub fn prune_retained_versions<P: AsRef<Path>>(rollback_dir: P, keep: usize) {
+    for old in list_retained_versions(rollback_dir).iter().skip(keep) {
+        info!("Removing retained version {} ({})", old.version, old.path.to_string_lossy());
+        let result = if old.path.is_dir() { remove_dir_all::remove_dir_all(&old.path) } else { fs::remove_file(&old.path) };
+        if let Err(e) = result {
+            warn!("Failed to remove retained version {} ({}).", old.version, e);
+        }
+    }
+}
+
+/// Moves a previously installed version into the rollback directory, replacing any slot
+/// which already holds the same version, and then enforces the retention limit.
+pub fn retain_version<P: AsRef<Path>>(rollback_dir: &PathBuf, version: &Version, slot_name: &str, source: P) -> Result<PathBuf> {
+    let source = source.as_ref();
+    let slot_path = rollback_dir.join(slot_name);
+    fs::create_dir_all(rollback_dir)?;
+    if slot_path.exists() {
+        remove_dir_all::remove_dir_all(&slot_path)?;
+    }
+    info!("Retaining version {} for rollback at {}", version, slot_path.to_string_lossy());
+    shared::retry_io(|| fs::rename(source, &slot_path))?;
+    prune_retained_versions(rollback_dir, get_rollback_retain_count());
+    Ok(slot_path)
+}
+
+#[test]
+fn test_rollback_retention_keeps_newest_versions() {
+    let tmp = tempfile::tempdir().unwrap();
+    let rollback_dir = tmp.path().join("rollback");
+    for v in ["1.0.0", "1.1.0", "1.2.0"] {
+        let src = tmp.path().join(format!("tmp_{}", v));
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("sq.version"), v).unwrap();
+        retain_version(&rollback_dir, &Version::parse(v).unwrap(), v, &src).unwrap();
+        assert!(!src.exists());
+    }
+    fs::create_dir_all(rollback_dir.join("not-a-version")).unwrap();
+
+    let retained = list_retained_versions(&rollback_dir);
+    let versions: Vec<String> = retained.iter().map(|r| r.version.to_string()).collect();
+    assert_eq!(versions, vec!["1.2.0", "1.1.0"]);
+    assert_eq!(fs::read_to_string(retained[0].path.join("sq.version")).unwrap(), "1.2.0");
+}
+
+#[test]
+fn test_rollback_retain_count_of_zero_keeps_the_retained_version() {
+    assert_eq!(clamp_retain_count(0), 1);
+    assert_eq!(clamp_retain_count(3), 3);
+
+    let tmp = tempfile::tempdir().unwrap();
+    let rollback_dir = tmp.path().join("rollback");
+    let src = tmp.path().join("tmp_1.0.0");
+    fs::create_dir_all(&src).unwrap();
+    let slot = retain_version(&rollback_dir, &Version::parse("1.0.0").unwrap(), "1.0.0", &src).unwrap();
+    prune_retained_versions(&rollback_dir, clamp_retain_count(0));
+    assert!(slot.exists());
+}
+
+#[test]
+fn test_rollback_selects_previous_version() {
+    let retained: Vec<RetainedVersion> = ["2.0.0", "1.2.0", "1.0.0"]
+        .iter()
+        .map(|v| RetainedVersion { version: Version::parse(v).unwrap(), path: PathBuf::from(format!("{}.app", v)) })
+        .collect();
+    let tmp = tempfile::tempdir().unwrap();
+    let packages_dir = tmp.path();
+    let select = |current: &str| select_rollback_target(&retained, &Version::parse(current).unwrap(), packages_dir).map(|r| r.version.to_string());
+    assert_eq!(select("1.5.0"), Some("1.2.0".to_string()));
+    assert_eq!(select("1.2.0"), Some("1.0.0".to_string()));
+    assert_eq!(select("0.9.0"), Some("2.0.0".to_string()));
+    assert_eq!(select_rollback_target(&[], &Version::new(1, 0, 0), packages_dir).map(|r| r.version.to_string()), None);
+
+    super::record_failed_version(packages_dir, &Version::parse("1.2.0").unwrap()).unwrap();
+    super::record_failed_version(packages_dir, &Version::parse("2.0.0").unwrap()).unwrap();
+    assert_eq!(select("1.5.0"), Some("1.0.0".to_string()));
+    assert_eq!(select("0.9.0"), Some("1.0.0".to_string()));
+}
@@ -1,14 +1,12 @@
-use crate::{
-    dialogs,
-    shared::{self, bundle, OperationWait},
-    windows,
-};
+#[cfg(target_os = "windows")]
+use crate::{dialogs, windows};
+use crate::shared::{self, bundle, OperationWait};
 use anyhow::{anyhow, bail, Result};
-use std::{
-    fs,
-    path::{Path, PathBuf},
-};
+#[cfg(target_os = "windows")]
+use std::fs;
+use std::path::{Path, PathBuf};
 
+#[cfg(target_os = "windows")]
 pub fn start(wait: OperationWait, exe_name: Option<&String>, exe_args: Option<Vec<&str>>, legacy_args: Option<&String>) -> Result<()> {
     if legacy_args.is_some() && exe_args.is_some() {
         bail!("Cannot use both legacy args and new args format.");
@@ -47,6 +45,8 @@ pub fn start(wait: OperationWait, exe_name: Option<&String>, exe_args: Option<Ve
         Err(e) => warn!("Failed legacy check ({}).", e),
     }
 
+    let (root_dir, app) = if super::retry_pending_operation(&root_dir, &app)? { shared::detect_current_manifest()? } else { (root_dir, app) };
+
     let current = app.get_current_path(&root_dir);
     let exe_to_execute = if let Some(exe) = exe_name {
         Path::new(&current).join(exe)
@@ -72,6 +72,7 @@ pub fn start(wait: OperationWait, exe_name: Option<&String>, exe_args: Option<Ve
     Ok(())
 }
 
+#[cfg(target_os = "windows")]
 fn try_legacy_migration(root_dir: &PathBuf, app: &bundle::Manifest) -> Result<()> {
     let package = shared::find_latest_full_package(&root_dir).ok_or_else(|| anyhow!("Unable to find latest full package."))?;
     let bundle = bundle::load_bundle_from_file(&package.file_path)?;
@@ -89,7 +90,7 @@ fn try_legacy_migration(root_dir: &PathBuf, app: &bundle::Manifest) -> Result<()
 
     info!("Applying latest full package...");
     let buf = Path::new(&package.file_path).to_path_buf();
-    super::apply(&root_dir, &app, false, OperationWait::NoWait, Some(&buf), None, false)?;
+    super::apply(&root_dir, &app, false, OperationWait::NoWait, Some(&buf), None, None, false, false)?;
 
     info!("Removing old app-* folders...");
     shared::delete_app_prefixed_folders(&root_dir)?;
@@ -105,3 +106,81 @@ fn try_legacy_migration(root_dir: &PathBuf, app: &bundle::Manifest) -> Result<()
 
     Ok(())
 }
+
+#[cfg(unix)]
+pub fn start(wait: OperationWait, exe_name: Option<&String>, exe_args: Option<Vec<&str>>, legacy_args: Option<&String>) -> Result<()> {
+    if legacy_args.is_some() {
+        bail!("The legacy args format is only supported on Windows.");
+    }
+
+    shared::operation_wait(wait);
+
+    let (root_dir, app) = shared::detect_current_manifest()?;
+
+    // an update which was only partly applied must be finished first, or the app would start in a broken state
+    if let Some(outcome) = super::recover_interrupted_apply()? {
+        info!("Completed a pending apply before starting the application ({:?}).", outcome);
+    }
+
+    let (root_dir, app) = if super::retry_pending_operation(&root_dir, &app)? { shared::detect_current_manifest()? } else { (root_dir, app) };
+
+    #[cfg(target_os = "linux")]
+    if let Err(e) = shared::ensure_desktop_entry(&root_dir, &app) {
+        warn!("Failed to create desktop entry ({}).", e);
+    }
+
+    if let Some(exe_name) = exe_name {
+        let (exe_to_execute, work_dir) = get_start_exe_path(&root_dir, &app, exe_name)?;
+        info!("About to launch: '{}' in dir '{}'", exe_to_execute.to_string_lossy(), work_dir.to_string_lossy());
+        let mut cmd = std::process::Command::new(&exe_to_execute);
+        cmd.args(exe_args.unwrap_or_default()).current_dir(&work_dir);
+        cmd.spawn().map_err(|e| anyhow!("Failed to start application ({}).", e))?;
+    } else {
+        let (exe_to_execute, _) = shared::get_main_exe_path(&app, &root_dir);
+        if !exe_to_execute.exists() {
+            bail!("Unable to find executable to start: '{}'", exe_to_execute.to_string_lossy());
+        }
+        shared::start_package(&app, &root_dir, exe_args, None)?;
+    }
+
+    Ok(())
+}
+
+/// Resolves a named executable of the installed application, and the dir it should be started in. An
+/// AppImage only contains one entry point, so only the main executable can be started.
+#[cfg(unix)]
+fn get_start_exe_path(root_dir: &Path, app: &bundle::Manifest, exe_name: &str) -> Result<(PathBuf, PathBuf)> {
+    let (main_exe, work_dir) = shared::get_main_exe_path(app, root_dir);
+    let exe_to_execute = if root_dir.is_dir() {
+        work_dir.join(exe_name)
+    } else if Path::new(exe_name) == Path::new(&app.main_exe) || main_exe.file_name().is_some_and(|n| n == exe_name) {
+        main_exe
+    } else {
+        bail!("Unable to start '{}', an AppImage can only start its main executable '{}'.", exe_name, app.main_exe);
+    };
+    let is_in_work_dir = Path::new(exe_name).components().all(|c| matches!(c, std::path::Component::Normal(_)));
+    if !is_in_work_dir || !exe_to_execute.is_file() {
+        bail!("Unable to find executable to start: '{}'", exe_to_execute.to_string_lossy());
+    }
+    Ok((exe_to_execute, work_dir))
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_start_exe_path_is_resolved_in_install_dir() {
+    let tmp = tempfile::tempdir().unwrap();
+    let mut app = bundle::Manifest::default();
+    app.main_exe = "MyApp".to_owned();
+    std::fs::write(tmp.path().join("MyApp"), "").unwrap();
+    std::fs::write(tmp.path().join("Helper"), "").unwrap();
+
+    let (exe, work_dir) = get_start_exe_path(tmp.path(), &app, "Helper").unwrap();
+    assert_eq!(exe, tmp.path().join("Helper"));
+    assert_eq!(work_dir, tmp.path());
+    assert!(get_start_exe_path(tmp.path(), &app, "Missing").is_err());
+    assert!(get_start_exe_path(tmp.path(), &app, "../Helper").is_err());
+
+    let appimage = tmp.path().join("MyApp");
+    assert_eq!(get_start_exe_path(&appimage, &app, "MyApp").unwrap().0, appimage);
+    assert!(get_start_exe_path(&appimage, &app, "Helper").is_err());
+}
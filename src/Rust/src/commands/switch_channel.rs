@@ -0,0 +1,71 @@
+use crate::shared::{
+    self,
+    bundle::Manifest,
+    OperationWait,
+};
+use anyhow::{bail, Result};
+use std::path::PathBuf;
+
+pub fn switch_channel(
+    root_path: &PathBuf,
+    app: &Manifest,
+    channel: &str,
+    allow_downgrade: bool,
+    restart: bool,
+    wait: OperationWait,
+    exe_args: Option<Vec<&str>>,
+    runhooks: bool,
+) -> Result<()> {
+    shared::operation_wait(wait);
+
+    let channel = channel.trim();
+    if channel.is_empty() {
+        bail!("A channel name is required.");
+    }
+
+    if app.channel.eq_ignore_ascii_case(channel) {
+        info!("Application is already on channel '{}', nothing to do.", channel);
+        return Ok(());
+    }
+
+    info!("Switching {} from channel '{}' to '{}'", app.id, app.channel, channel);
+    let (package, package_manifest) = super::locate_latest_package(app, root_path, channel)?;
+
+    if package_manifest.version < app.version && !allow_downgrade {
+        bail!(
+            "The newest package on channel '{}' ({}) is older than the installed version ({}). Pass --allow-downgrade to switch anyway.",
+            channel,
+            package_manifest.version,
+            app.version
+        );
+    }
+
+    // the channel is recorded first, so a postponed apply is retried on the new channel
+    let state_dir = shared::get_app_state_dir(&app.id, root_path);
+    if let Err(e) = shared::write_installed_channel(&state_dir, channel) {
+        error!("Failed to record channel '{}' ({}).", channel, e);
+        if restart {
+            shared::start_package(app, root_path, exe_args, Some("VELOPACK_RESTART"))?;
+        }
+        bail!("Switch channel failed, see logs for details.");
+    }
+
+    let mut target_app = app.clone();
+    target_app.channel = channel.to_owned();
+
+    info!("Applying package {} from channel '{}': {}", package_manifest.version, channel, package.to_string_lossy());
+    match super::apply(root_path, &target_app, restart, OperationWait::NoWait, Some(&package), exe_args, None, allow_downgrade, runhooks) {
+        Ok(()) => {
+            info!("Switched to channel '{}' at version {}.", channel, package_manifest.version);
+            Ok(())
+        }
+        Err(e) => {
+            if super::has_pending_apply(super::get_packages_dir(app, root_path), &package) {
+                info!("The switch to channel '{}' will complete when the postponed apply is retried.", channel);
+            } else if let Err(e) = shared::write_installed_channel(&state_dir, &app.channel) {
+                warn!("Failed to restore channel '{}' ({}).", app.channel, e);
+            }
+            Err(e)
+        }
+    }
+}
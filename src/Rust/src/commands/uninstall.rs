@@ -1,60 +1,154 @@
-use crate::shared::{self, bundle::Manifest};
+use crate::shared::{self, bundle::Manifest, hooks::HookPayload};
+#[cfg(target_os = "windows")]
 use crate::windows;
 use anyhow::Result;
-use std::fs::File;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-pub fn uninstall(root_path: &PathBuf, app: &Manifest, delete_self: bool) -> Result<()> {
+pub fn uninstall(root_path: &PathBuf, app: &Manifest, delete_self: bool, keep_user_data: bool) -> Result<()> {
     info!("Command: Uninstall");
 
-    fn _uninstall_impl(app: &Manifest, root_path: &PathBuf) -> bool {
-        // the real app could be running at the moment
-        let _ = shared::force_stop_package(&root_path);
+    // if it returns true, it was a success.
+    // if it returns false, it was completed with errors which the user should be notified of.
+    let result = uninstall_impl(app, root_path, keep_user_data);
 
-        let mut finished_with_errors = false;
+    if result {
+        info!("Finished successfully.");
+        shared::dialogs::show_info(format!("{} Uninstall", app.title).as_str(), None, "The application was successfully uninstalled.");
+    } else {
+        error!("Finished with errors.");
+        #[cfg(target_os = "windows")]
+        shared::dialogs::show_uninstall_complete_with_errors_dialog(app, None);
+        #[cfg(not(target_os = "windows"))]
+        shared::dialogs::show_uninstall_complete_with_errors_dialog(app, Some(&crate::logging::get_app_log_path(&app.id)));
+    }
 
-        // run uninstall hook
-        windows::run_hook(&app, root_path, "--veloapp-uninstall", 60);
+    #[cfg(target_os = "windows")]
+    {
+        let dead_path = root_path.join(".dead");
+        let _ = std::fs::File::create(dead_path);
 
-        if let Err(e) = windows::remove_all_shortcuts_for_root_dir(&root_path) {
-            error!("Unable to remove shortcuts ({}).", e);
-            // finished_with_errors = true;
+        if delete_self {
+            if let Err(e) = windows::register_intent_to_delete_self(3, root_path) {
+                warn!("Unable to schedule self delete ({}).", e);
+            }
         }
+    }
+    #[cfg(not(target_os = "windows"))]
+    let _ = delete_self;
 
-        info!("Removing directory '{}'", root_path.to_string_lossy());
-        if let Err(e) = shared::retry_io(|| remove_dir_all::remove_dir_but_not_self(&root_path)) {
-            error!("Unable to remove directory, some files may be in use ({}).", e);
-            finished_with_errors = true;
-        }
+    Ok(())
+}
 
-        if let Err(e) = app.remove_uninstall_entry() {
-            error!("Unable to remove uninstall registry entry ({}).", e);
-            // finished_with_errors = true;
-        }
+#[cfg(target_os = "windows")]
+fn uninstall_impl(app: &Manifest, root_path: &PathBuf, _keep_user_data: bool) -> bool {
+    // the real app could be running at the moment
+    let _ = shared::force_stop_package(root_path);
+
+    let mut finished_with_errors = false;
+
+    // run uninstall hook
+    let payload = HookPayload::new("--veloapp-uninstall", None, app, root_path, false);
+    let _ = windows::run_hook(app, root_path, &payload, 60);
 
-        !finished_with_errors
+    if let Err(e) = windows::remove_all_shortcuts_for_root_dir(root_path) {
+        error!("Unable to remove shortcuts ({}).", e);
+        // finished_with_errors = true;
     }
 
-    // if it returns true, it was a success.
-    // if it returns false, it was completed with errors which the user should be notified of.
-    let result = _uninstall_impl(&app, &root_path);
+    info!("Removing directory '{}'", root_path.to_string_lossy());
+    if let Err(e) = shared::retry_io(|| remove_dir_all::remove_dir_but_not_self(root_path)) {
+        error!("Unable to remove directory, some files may be in use ({}).", e);
+        finished_with_errors = true;
+    }
 
-    if result {
-        info!("Finished successfully.");
-        shared::dialogs::show_info(format!("{} Uninstall", app.title).as_str(), None, "The application was successfully uninstalled.");
-    } else {
-        error!("Finished with errors.");
-        shared::dialogs::show_uninstall_complete_with_errors_dialog(&app, None);
+    if let Err(e) = app.remove_uninstall_entry() {
+        error!("Unable to remove uninstall registry entry ({}).", e);
+        // finished_with_errors = true;
     }
 
-    let dead_path = root_path.join(".dead");
-    let _ = File::create(dead_path);
+    !finished_with_errors
+}
+
+#[cfg(unix)]
+fn uninstall_impl(app: &Manifest, root_path: &PathBuf, keep_user_data: bool) -> bool {
+    // the real app could be running at the moment
+    let _ = shared::force_stop_package(root_path);
 
-    if delete_self {
-        if let Err(e) = windows::register_intent_to_delete_self(3, &root_path) {
-            warn!("Unable to schedule self delete ({}).", e);
+    let mut finished_with_errors = false;
+
+    // run uninstall hook
+    let payload = HookPayload::new("--veloapp-uninstall", None, app, root_path, false);
+    let _ = shared::run_hook(app, root_path, &payload, 60);
+
+    #[cfg(target_os = "linux")]
+    if let Err(e) = shared::remove_desktop_entries(&app.id) {
+        error!("Unable to remove desktop entries ({}).", e);
+        // finished_with_errors = true;
+    }
+
+    let log_dir = crate::logging::get_app_log_dir(&app.id);
+    for path in get_uninstall_paths(app, root_path, keep_user_data) {
+        if !path.exists() && !path.is_symlink() {
+            continue;
+        }
+        // the log dir is removed last, and kept if anything went wrong so the user can be pointed at the log
+        if path == log_dir && finished_with_errors {
+            info!("Keeping '{}' so the errors can be reviewed.", path.to_string_lossy());
+            continue;
+        }
+        info!("Removing '{}'", path.to_string_lossy());
+        let result = if path.is_dir() && !path.is_symlink() { remove_dir_all::remove_dir_all(&path) } else { std::fs::remove_file(&path) };
+        if let Err(e) = result {
+            error!("Unable to remove '{}' ({}).", path.to_string_lossy(), e);
+            // only failing to remove the application itself is reported, left-over caches are harmless
+            finished_with_errors |= path == *root_path;
         }
     }
 
-    Ok(())
+    !finished_with_errors
+}
+
+/// Everything which is removed when the application is uninstalled, in order: the application itself, the caches
+/// kept by velopack, its state (unless `keep_user_data` is set) and lastly its logs. Only dirs which velopack
+/// created are removed, the application's own settings and data are left alone.
+#[cfg(unix)]
+pub fn get_uninstall_paths(app: &Manifest, root_path: &Path, keep_user_data: bool) -> Vec<PathBuf> {
+    let mut paths = vec![root_path.to_path_buf()];
+    // locations overridden by the user may be shared, so only the default ones are removed
+    paths.push(shared::get_app_cache_dir(&app.id));
+    paths.push(shared::get_legacy_cache_dir(&app.id));
+
+    let state_dir = shared::get_app_state_dir(&app.id, root_path);
+    if !keep_user_data {
+        paths.push(state_dir.clone());
+    }
+    // on linux the logs are in the state dir, which must survive if the user data is kept
+    let log_dir = crate::logging::get_app_log_dir(&app.id);
+    let is_log_dir_removed = !keep_user_data || log_dir != state_dir;
+    if is_log_dir_removed && !paths.contains(&log_dir) {
+        paths.push(log_dir);
+    }
+    paths
+}
+
+#[test]
+#[cfg(unix)]
+fn test_uninstall_paths_keep_user_data() {
+    let mut app = Manifest::default();
+    app.id = "MyApp".to_owned();
+    let root_path = Path::new("/home/user/Apps/MyApp.AppImage");
+    let state_dir = shared::get_app_state_dir("MyApp", root_path);
+    let log_dir = crate::logging::get_app_log_dir("MyApp");
+
+    let all = get_uninstall_paths(&app, root_path, false);
+    let kept = get_uninstall_paths(&app, root_path, true);
+    assert_eq!(all[0], root_path);
+    assert_eq!(all.last(), Some(&log_dir));
+    assert!(crate::logging::get_app_log_path("MyApp").starts_with(&log_dir));
+    assert!(all.contains(&state_dir));
+    assert!(!kept.contains(&state_dir));
+    #[cfg(target_os = "linux")]
+    assert!(kept.contains(&PathBuf::from("/var/tmp/velopack/MyApp")));
+    assert!(kept.iter().all(|p| all.contains(p)));
+    assert!(all.iter().all(|p| p.ends_with("MyApp") || p == root_path));
 }
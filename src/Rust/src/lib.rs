@@ -1,5 +1,7 @@
+pub mod capi;
 pub mod commands;
 pub mod logging;
+pub mod manager;
 pub mod shared;
 #[cfg(target_os = "windows")]
 pub mod windows;
@@ -1,8 +1,44 @@
-use anyhow::Result;
+use crate::shared::events;
+use anyhow::{bail, Result};
+use log::{Level, Log, Metadata, Record};
 use simplelog::*;
-use std::path::PathBuf;
+use std::{
+    io::Write,
+    path::PathBuf,
+    sync::Mutex,
+};
 use time::format_description::{modifier, Component, FormatItem};
 
+/// How log lines are written, as text for people to read, or as JSON lines for log aggregators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => bail!("Unknown log format '{}', expected 'text' or 'json'.", s),
+        }
+    }
+}
+
+static LOG_FIELDS: Mutex<Vec<(&'static str, String)>> = Mutex::new(Vec::new());
+
+/// Adds a field (eg. the app id or current phase) to every JSON log line written from now on.
+pub fn set_log_field(key: &'static str, value: &str) {
+    if let Ok(mut fields) = LOG_FIELDS.lock() {
+        fields.retain(|(k, _)| *k != key);
+        fields.push((key, value.to_owned()));
+    }
+}
+
 pub fn trace_logger() {
     TermLogger::init(LevelFilter::Trace, get_config(None), TerminalMode::Mixed, ColorChoice::Never).unwrap();
 }
@@ -14,30 +50,63 @@ pub fn default_log_location() -> PathBuf {
         my_dir.pop();
         return my_dir.join("Velopack.log");
     }
-    #[cfg(target_os = "linux")]
+    #[cfg(unix)]
     {
-        return std::path::Path::new("/tmp/velopack.log").to_path_buf();
+        // each app has its own log, which survives a reboot and is only writable by the user who owns it
+        return match crate::shared::detect_current_manifest() {
+            Ok((_, app)) => get_app_log_path(&app.id),
+            Err(_) => get_log_root_dir().join("velopack.log"),
+        };
     }
+}
+
+/// The directory which holds the logs of a single application.
+#[cfg(unix)]
+pub fn get_app_log_dir(app_id: &str) -> PathBuf {
+    get_log_root_dir().join(app_id)
+}
+
+/// The log file of a single application, inside `get_app_log_dir`.
+#[cfg(unix)]
+pub fn get_app_log_path(app_id: &str) -> PathBuf {
+    get_app_log_dir(app_id).join("velopack.log")
+}
+
+/// `$XDG_STATE_HOME/velopack` (or `~/.local/state/velopack`) on Linux, and `~/Library/Logs/velopack` on macOS.
+#[cfg(unix)]
+fn get_log_root_dir() -> PathBuf {
+    #[allow(deprecated)]
+    let home = std::env::home_dir().expect("Could not locate user home directory via $HOME or /etc/passwd");
+    #[cfg(target_os = "linux")]
+    let logs_dir = std::env::var_os("XDG_STATE_HOME").map(PathBuf::from).filter(|p| p.is_absolute()).unwrap_or_else(|| home.join(".local").join("state"));
     #[cfg(target_os = "macos")]
-    {
-        #[allow(deprecated)]
-        let mut user_home = std::env::home_dir().expect("Could not locate user home directory via $HOME or /etc/passwd");
-        user_home.push("Library");
-        user_home.push("Logs");
-        user_home.push("velopack.log");
-        return user_home;
-    }
+    let logs_dir = home.join("Library").join("Logs");
+    logs_dir.join("velopack")
 }
 
-pub fn setup_logging(process_name: &str, file: Option<&PathBuf>, console: bool, verbose: bool, nocolor: bool) -> Result<()> {
+pub fn setup_logging(
+    process_name: &str,
+    file: Option<&PathBuf>,
+    console: bool,
+    verbose: bool,
+    nocolor: bool,
+    console_format: LogFormat,
+    file_format: LogFormat,
+) -> Result<()> {
     let mut loggers: Vec<Box<dyn SharedLogger>> = Vec::new();
     let color_choice = if nocolor { ColorChoice::Never } else { ColorChoice::Auto };
     if console {
         let console_level = if verbose { LevelFilter::Debug } else { LevelFilter::Info };
-        loggers.push(TermLogger::new(console_level, get_config(None), TerminalMode::Mixed, color_choice));
+        match console_format {
+            LogFormat::Text => loggers.push(TermLogger::new(console_level, get_config(None), TerminalMode::Mixed, color_choice)),
+            LogFormat::Json => loggers.push(Box::new(JsonLogger::new(console_level, process_name, None))),
+        }
     }
 
     if let Some(f) = file {
+        if let Some(parent) = f.parent().filter(|p| !p.as_os_str().is_empty()) {
+            let _ = std::fs::create_dir_all(parent);
+        }
         let file_level = if verbose { LevelFilter::Trace } else { LevelFilter::Info };
         let writer = file_rotate::FileRotate::new(
             f.clone(),
@@ -47,9 +116,13 @@ pub fn setup_logging(process_name: &str, file: Option<&PathBuf>, console: bool,
             #[cfg(unix)]
             None,
         );
-        loggers.push(WriteLogger::new(file_level, get_config(Some(process_name)), writer));
+        match file_format {
+            LogFormat::Text => loggers.push(WriteLogger::new(file_level, get_config(Some(process_name)), writer)),
+            LogFormat::Json => loggers.push(Box::new(JsonLogger::new(file_level, process_name, Some(Box::new(writer))))),
+        }
     }
 
+    loggers.push(Box::new(EventLogger));
     CombinedLogger::init(loggers)?;
     Ok(())
 }
@@ -78,3 +151,157 @@ fn get_config(process_name: Option<&str>) -> Config {
     let _ = c.set_time_offset_to_local(); // might fail if local tz can't be determined
     c.build()
 }
+
+/// Writes each record as a single line of JSON. Without a writer, lines go to the console like `TerminalMode::Mixed`.
+struct JsonLogger {
+    level: LevelFilter,
+    process_name: String,
+    writer: Option<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl JsonLogger {
+    fn new(level: LevelFilter, process_name: &str, writer: Option<Box<dyn Write + Send>>) -> JsonLogger {
+        JsonLogger { level, process_name: process_name.to_owned(), writer: writer.map(Mutex::new) }
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format_json_line(record, &self.process_name, chrono::Local::now().fixed_offset());
+        match &self.writer {
+            Some(writer) => {
+                if let Ok(mut w) = writer.lock() {
+                    let _ = writeln!(w, "{}", line);
+                }
+            }
+            None if record.level() == Level::Error => {
+                let _ = writeln!(std::io::stderr(), "{}", line);
+            }
+            None => {
+                let _ = writeln!(std::io::stdout(), "{}", line);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(writer) = &self.writer {
+            let _ = writer.lock().map(|mut w| w.flush());
+        }
+    }
+}
+
+impl SharedLogger for JsonLogger {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        Box::new(*self)
+    }
+}
+
+fn format_json_line(record: &Record, process_name: &str, timestamp: chrono::DateTime<chrono::FixedOffset>) -> String {
+    let mut fields = serde_json::Map::new();
+    if let Ok(context) = LOG_FIELDS.lock() {
+        for (key, value) in context.iter() {
+            fields.insert(key.to_string(), serde_json::Value::String(value.clone()));
+        }
+    }
+    let _ = record.key_values().visit(&mut FieldCollector(&mut fields));
+
+    let line = serde_json::json!({
+        "timestamp": timestamp.to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+        "level": record.level().as_str(),
+        "process": process_name,
+        "pid": std::process::id(),
+        "module": record.module_path().unwrap_or(record.target()),
+        "message": record.args().to_string(),
+        "fields": fields,
+    });
+    line.to_string()
+}
+
+/// Copies the key/values of a log record, eg. `info!(version = "1.0.0"; "message")`, into the JSON fields.
+struct FieldCollector<'a>(&'a mut serde_json::Map<String, serde_json::Value>);
+
+impl<'kvs> log::kv::VisitSource<'kvs> for FieldCollector<'_> {
+    fn visit_pair(&mut self, key: log::kv::Key<'kvs>, value: log::kv::Value<'kvs>) -> Result<(), log::kv::Error> {
+        self.0.insert(key.to_string(), serde_json::Value::String(value.to_string()));
+        Ok(())
+    }
+}
+
+/// Forwards warnings and errors to the machine-readable event output, if it has been enabled.
+struct EventLogger;
+
+impl Log for EventLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Warn && events::get_events_enabled()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let message = record.args().to_string();
+        if record.level() == Level::Error {
+            events::error(&message);
+        } else {
+            events::warning(&message);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+impl SharedLogger for EventLogger {
+    fn level(&self) -> LevelFilter {
+        LevelFilter::Warn
+    }
+
+    fn config(&self) -> Option<&Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        Box::new(*self)
+    }
+}
+
+#[test]
+fn test_json_log_line_has_context_and_record_fields() {
+    set_log_field("app_id", "MyApp");
+    let kvs = [("to_version", "2.0.0")];
+    let record = Record::builder()
+        .args(format_args!("Applying \"update\""))
+        .level(Level::Warn)
+        .module_path(Some("velopack::commands::apply"))
+        .key_values(&kvs)
+        .build();
+    let timestamp = chrono::DateTime::parse_from_rfc3339("2024-05-01T10:20:30.123+02:00").unwrap();
+
+    let line = format_json_line(&record, "update", timestamp);
+    assert!(!line.contains('\n'));
+    let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(json["timestamp"], "2024-05-01T10:20:30.123+02:00");
+    assert_eq!(json["level"], "WARN");
+    assert_eq!(json["process"], "update");
+    assert_eq!(json["pid"], std::process::id());
+    assert_eq!(json["module"], "velopack::commands::apply");
+    assert_eq!(json["message"], "Applying \"update\"");
+    assert_eq!(json["fields"]["app_id"], "MyApp");
+    assert_eq!(json["fields"]["to_version"], "2.0.0");
+    assert_eq!("JSON".parse::<LogFormat>().unwrap(), LogFormat::Json);
+    assert!("xml".parse::<LogFormat>().is_err());
+}
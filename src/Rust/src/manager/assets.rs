@@ -0,0 +1,113 @@
+use anyhow::Result;
+use semver::Version;
+use serde::{Deserialize, Deserializer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Default)]
+pub enum VelopackAssetType {
+    #[default]
+    Full,
+    Delta,
+}
+
+/// A single release in a release feed (`releases.{channel}.json`).
+#[derive(Debug, Clone, Deserialize, derivative::Derivative)]
+#[derivative(Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct VelopackAsset {
+    #[serde(default)]
+    pub package_id: String,
+    #[serde(deserialize_with = "deserialize_version")]
+    #[derivative(Default(value = "Version::new(0, 0, 0)"))]
+    pub version: Version,
+    #[serde(default, rename = "Type")]
+    pub asset_type: VelopackAssetType,
+    #[serde(default)]
+    pub file_name: String,
+    #[serde(default, rename = "SHA1")]
+    pub sha1: String,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub notes_markdown: Option<String>,
+    #[serde(default, rename = "NotesHTML")]
+    pub notes_html: Option<String>,
+    /// The percentage of installations this release is rolled out to, or None if it is available to everyone.
+    #[serde(default)]
+    pub staging_percentage: Option<f32>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct VelopackAssetFeed {
+    #[serde(default)]
+    pub assets: Vec<VelopackAsset>,
+}
+
+impl VelopackAssetFeed {
+    pub fn from_json(json: &str) -> Result<VelopackAssetFeed> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Returns the full release with the highest version, if there is one.
+    pub fn get_latest_full_release(&self) -> Option<&VelopackAsset> {
+        self.get_latest_full_release_for(None)
+    }
+
+    /// Returns the full release with the highest version which has been rolled out to the installation with this staging id.
+    pub fn get_latest_full_release_for(&self, staged_user_id: Option<&str>) -> Option<&VelopackAsset> {
+        self.assets
+            .iter()
+            .filter(|a| a.asset_type == VelopackAssetType::Full)
+            .filter(|a| crate::shared::is_staging_match(a.staging_percentage, staged_user_id))
+            .max_by(|a, b| a.version.cmp(&b.version))
+    }
+}
+
+fn deserialize_version<'de, D>(deserializer: D) -> Result<Version, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    Version::parse(&s).map_err(serde::de::Error::custom)
+}
+
+#[test]
+fn test_parse_release_feed() {
+    let json = r#"{
+  "Assets": [
+    {
+      "PackageId": "MyApp",
+      "Version": "1.0.0",
+      "Type": "Full",
+      "FileName": "MyApp-1.0.0-full.nupkg",
+      "SHA1": "A7B0E4D0B1F4A4A3A1A2B3C4D5E6F708192A3B4C",
+      "Size": 1024
+    },
+    {
+      "PackageId": "MyApp",
+      "Version": "1.1.0-beta.2",
+      "Type": "Full",
+      "FileName": "MyApp-1.1.0-beta.2-full.nupkg",
+      "SHA1": "B7B0E4D0B1F4A4A3A1A2B3C4D5E6F708192A3B4C",
+      "Size": 2048,
+      "NotesMarkdown": "Fixed things"
+    },
+    {
+      "PackageId": "MyApp",
+      "Version": "1.2.0",
+      "Type": "Delta",
+      "FileName": "MyApp-1.2.0-delta.nupkg",
+      "SHA1": "C7B0E4D0B1F4A4A3A1A2B3C4D5E6F708192A3B4C",
+      "Size": 10
+    }
+  ]
+}"#;
+    let feed = VelopackAssetFeed::from_json(json).unwrap();
+    assert_eq!(feed.assets.len(), 3);
+    assert_eq!(feed.assets[2].asset_type, VelopackAssetType::Delta);
+    assert_eq!(feed.assets[1].notes_markdown.as_deref(), Some("Fixed things"));
+
+    let latest = feed.get_latest_full_release().unwrap();
+    assert_eq!(latest.version, Version::parse("1.1.0-beta.2").unwrap());
+    assert_eq!(latest.size, 2048);
+}
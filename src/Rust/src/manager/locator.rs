@@ -0,0 +1,64 @@
+use crate::shared::{
+    self,
+    bundle::{self, EntryNameInfo, Manifest},
+};
+use anyhow::{bail, Result};
+use std::path::PathBuf;
+
+/// The locations of an installed application and its updater.
+#[derive(Debug, Clone)]
+pub struct VelopackLocator {
+    /// The root of the installation: the install dir on Windows, the .AppImage on Linux, or the .app bundle on macOS.
+    pub root_app_dir: PathBuf,
+    /// The path to the Update binary which ships with the application.
+    pub update_exe_path: PathBuf,
+    /// The directory where downloaded packages are stored until they are applied.
+    pub packages_dir: PathBuf,
+    /// The directory where per-installation state, such as the staging id, is kept.
+    pub state_dir: PathBuf,
+    /// The manifest of the currently installed version.
+    pub manifest: Manifest,
+}
+
+impl VelopackLocator {
+    /// Detects the installed application from the location of the currently running executable.
+    pub fn auto_locate() -> Result<VelopackLocator> {
+        let me = std::env::current_exe()?;
+        let my_dir = me.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+
+        #[cfg(target_os = "windows")]
+        let (update_exe_path, (root_app_dir, manifest)) = {
+            // the app runs from root/current, and Update.exe lives in the root
+            let update_exe = my_dir.parent().map(|p| p.join("Update.exe")).unwrap_or_default();
+            let detected = shared::detect_manifest_from_update_path(&update_exe)?;
+            (update_exe, detected)
+        };
+        #[cfg(target_os = "linux")]
+        let (update_exe_path, (root_app_dir, manifest)) = (my_dir.join("UpdateNix"), shared::detect_current_manifest()?);
+        #[cfg(target_os = "macos")]
+        let (update_exe_path, (root_app_dir, manifest)) = {
+            let update_exe = my_dir.join("UpdateMac");
+            let detected = shared::detect_manifest_from_update_path(&update_exe)?;
+            (update_exe, detected)
+        };
+
+        if !update_exe_path.exists() {
+            bail!("Unable to locate the Update binary at {}. Is this a properly installed application?", update_exe_path.to_string_lossy());
+        }
+
+        let packages_dir = PathBuf::from(crate::commands::get_packages_dir(&manifest, &root_app_dir));
+        let state_dir = shared::get_app_state_dir(&manifest.id, &root_app_dir);
+        Ok(VelopackLocator { root_app_dir, update_exe_path, packages_dir, state_dir, manifest })
+    }
+
+    /// Returns the newest full package which has been downloaded to the packages directory, ignoring
+    /// versions which previously failed their health check.
+    pub fn get_latest_local_full_package(&self) -> Option<EntryNameInfo> {
+        std::fs::read_dir(&self.packages_dir)
+            .ok()?
+            .flatten()
+            .filter_map(|e| bundle::parse_package_file_path(e.path()))
+            .filter(|p| !p.is_delta && !crate::commands::is_failed_version(&self.packages_dir, &p.version))
+            .max_by(|a, b| a.version.cmp(&b.version))
+    }
+}
@@ -0,0 +1,17 @@
+//! A high-level API for Rust applications to check for, download and apply their own updates.
+//! This mirrors the `UpdateManager` in the .NET library, and uses the same release feeds.
+
+mod assets;
+pub use assets::*;
+
+mod locator;
+pub use locator::*;
+
+mod sources;
+pub use sources::*;
+
+mod update_manager;
+pub use update_manager::*;
+
+mod velopack_app;
+pub use velopack_app::*;
@@ -0,0 +1,172 @@
+use super::{VelopackAsset, VelopackAssetFeed, VelopackAssetType};
+use crate::shared::{self, bundle, bundle::Manifest, download};
+use anyhow::{bail, Result};
+use std::{
+    fs,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// A location which release feeds and packages can be retrieved from.
+pub trait UpdateSource {
+    /// Retrieves the list of available releases for the specified channel.
+    fn get_release_feed(&self, channel: &str, app: &Manifest) -> Result<VelopackAssetFeed>;
+    /// Downloads a release to a local file, reporting progress (0-100) along the way.
+    fn download_release_entry(&self, asset: &VelopackAsset, local_file: &str, progress: &mut dyn FnMut(i16)) -> Result<()>;
+}
+
+/// Creates a `HttpSource` for http(s) urls, or a `FileSource` for anything else.
+pub fn create_source(url_or_path: &str) -> Box<dyn UpdateSource> {
+    if shared::is_http_url(url_or_path) {
+        Box::new(HttpSource::new(url_or_path))
+    } else {
+        Box::new(FileSource::new(url_or_path))
+    }
+}
+
+pub fn get_release_feed_name(channel: &str) -> String {
+    format!("releases.{}.json", channel)
+}
+
+/// Retrieves updates from a local or network directory.
+#[derive(Debug, Clone)]
+pub struct FileSource {
+    base_dir: PathBuf,
+}
+
+impl FileSource {
+    pub fn new<P: AsRef<Path>>(base_dir: P) -> FileSource {
+        FileSource { base_dir: base_dir.as_ref().to_path_buf() }
+    }
+}
+
+impl UpdateSource for FileSource {
+    fn get_release_feed(&self, channel: &str, _app: &Manifest) -> Result<VelopackAssetFeed> {
+        if !self.base_dir.exists() {
+            bail!("The local update directory '{}' does not exist.", self.base_dir.to_string_lossy());
+        }
+
+        // if a feed exists in the folder, let's use that.
+        let feed_path = self.base_dir.join(get_release_feed_name(channel));
+        if feed_path.exists() {
+            debug!("Found local file feed at '{}'.", feed_path.to_string_lossy());
+            let json = shared::retry_io(|| fs::read_to_string(&feed_path))?;
+            return VelopackAssetFeed::from_json(&json);
+        }
+
+        warn!("No local feed found at '{}', will search for *.nupkg in '{}'.", feed_path.to_string_lossy(), self.base_dir.to_string_lossy());
+        let mut assets = Vec::new();
+        for entry in fs::read_dir(&self.base_dir)?.flatten() {
+            let path = entry.path();
+            if !path.to_string_lossy().to_ascii_lowercase().ends_with(".nupkg") {
+                continue;
+            }
+            match read_asset_from_package(&path) {
+                Ok((asset, package_channel)) => {
+                    if channel.is_empty() || package_channel.is_empty() || package_channel == channel {
+                        assets.push(asset);
+                    } else {
+                        debug!("Skipping '{}' because it is not in the '{}' channel.", path.to_string_lossy(), channel);
+                    }
+                }
+                Err(e) => warn!("Error while reading local package '{}' ({}).", path.to_string_lossy(), e),
+            }
+        }
+        Ok(VelopackAssetFeed { assets })
+    }
+
+    fn download_release_entry(&self, asset: &VelopackAsset, local_file: &str, progress: &mut dyn FnMut(i16)) -> Result<()> {
+        let release_path = self.base_dir.join(&asset.file_name);
+        if !release_path.exists() {
+            bail!("The file '{}' does not exist. The packages directory is invalid.", release_path.to_string_lossy());
+        }
+        let total = fs::metadata(&release_path)?.len();
+        let mut reader = shared::retry_io(|| fs::File::open(&release_path))?;
+        let mut writer = shared::retry_io(|| fs::File::create(local_file))?;
+        let mut buffer = vec![0; 1024 * 1024];
+        let mut copied = 0u64;
+        progress(0);
+        loop {
+            let read = reader.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            writer.write_all(&buffer[..read])?;
+            copied += read as u64;
+            if let Some(percent) = (copied.min(total) * 100).checked_div(total) {
+                progress(percent as i16);
+            }
+        }
+        writer.flush()?;
+        progress(100);
+        Ok(())
+    }
+}
+
+/// Retrieves updates from a static http(s) server.
+#[derive(Debug, Clone)]
+pub struct HttpSource {
+    base_url: String,
+}
+
+impl HttpSource {
+    pub fn new(base_url: &str) -> HttpSource {
+        HttpSource { base_url: base_url.to_owned() }
+    }
+
+    fn get_url_for(&self, file_name: &str) -> Result<url::Url> {
+        let mut base = self.base_url.clone();
+        if !base.ends_with('/') {
+            base.push('/');
+        }
+        Ok(url::Url::parse(&base)?.join(file_name)?)
+    }
+}
+
+impl UpdateSource for HttpSource {
+    fn get_release_feed(&self, channel: &str, app: &Manifest) -> Result<VelopackAssetFeed> {
+        let mut url = self.get_url_for(&get_release_feed_name(channel))?;
+        url.query_pairs_mut().append_pair("id", &app.id).append_pair("localVersion", &app.version.to_string());
+        if !app.os.is_empty() {
+            url.query_pairs_mut().append_pair("os", &app.os);
+        }
+
+        info!("Downloading release feed from '{}'.", url);
+        let json = download::download_url_as_string(url.as_str())?;
+        VelopackAssetFeed::from_json(&json)
+    }
+
+    fn download_release_entry(&self, asset: &VelopackAsset, local_file: &str, progress: &mut dyn FnMut(i16)) -> Result<()> {
+        // the file name can be relative to the feed, or an absolute url
+        let url = if shared::is_http_url(&asset.file_name) { url::Url::parse(&asset.file_name)? } else { self.get_url_for(&asset.file_name)? };
+        info!("Downloading '{}' from '{}'.", asset.file_name, url);
+        download::download_url_to_file(url.as_str(), local_file, progress)
+    }
+}
+
+fn read_asset_from_package(path: &PathBuf) -> Result<(VelopackAsset, String)> {
+    let manifest = bundle::load_bundle_from_file(path)?.read_manifest()?;
+    let bytes = fs::read(path)?;
+    let file_name = path.file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or_default();
+    let is_delta = bundle::parse_package_file_path(path.clone()).map(|p| p.is_delta).unwrap_or(false);
+    let asset = VelopackAsset {
+        package_id: manifest.id.clone(),
+        version: manifest.version.clone(),
+        asset_type: if is_delta { VelopackAssetType::Delta } else { VelopackAssetType::Full },
+        file_name,
+        sha1: sha1_smol::Sha1::from(&bytes).digest().to_string(),
+        size: bytes.len() as u64,
+        notes_markdown: None,
+        notes_html: None,
+        staging_percentage: manifest.staging_percentage,
+    };
+    Ok((asset, manifest.channel))
+}
+
+#[test]
+fn test_http_source_builds_urls() {
+    let source = HttpSource::new("https://example.com/releases");
+    assert_eq!(source.get_url_for("releases.win.json").unwrap().as_str(), "https://example.com/releases/releases.win.json");
+    let source = HttpSource::new("https://example.com/releases/");
+    assert_eq!(source.get_url_for("MyApp-1.0.0-full.nupkg").unwrap().as_str(), "https://example.com/releases/MyApp-1.0.0-full.nupkg");
+}
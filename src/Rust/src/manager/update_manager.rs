@@ -0,0 +1,392 @@
+use super::{UpdateSource, VelopackAsset, VelopackLocator};
+use crate::shared;
+use anyhow::{bail, Result};
+use semver::Version;
+use std::{
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    process::Command as Process,
+    time::Duration,
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct UpdateOptions {
+    /// Check a different channel than the one the application was installed from.
+    pub explicit_channel: Option<String>,
+    /// Allow updating to a version which is older than the installed version.
+    pub allow_version_downgrade: bool,
+    /// If set, the restarted application must call `VelopackApp::run` within this time after an update is applied,
+    /// or the previous version is restored.
+    pub health_check_timeout: Option<Duration>,
+}
+
+#[derive(Debug, Clone)]
+pub struct UpdateInfo {
+    /// The release which should be downloaded and applied.
+    pub target_full_release: VelopackAsset,
+    /// True if the target release is older than the installed version.
+    pub is_downgrade: bool,
+}
+
+/// Checks for, downloads and applies updates for the currently running application.
+pub struct UpdateManager {
+    source: Box<dyn UpdateSource>,
+    options: UpdateOptions,
+    locator: VelopackLocator,
+}
+
+impl UpdateManager {
+    /// Creates an UpdateManager for the currently running application. Fails if the application
+    /// is not installed (eg. it is being run from a development build directory).
+    pub fn new<S: UpdateSource + 'static>(source: S, options: Option<UpdateOptions>) -> Result<UpdateManager> {
+        Self::new_with_locator(source, options, VelopackLocator::auto_locate()?)
+    }
+
+    pub fn new_with_locator<S: UpdateSource + 'static>(source: S, options: Option<UpdateOptions>, locator: VelopackLocator) -> Result<UpdateManager> {
+        Self::new_with_boxed_source(Box::new(source), options, locator)
+    }
+
+    pub fn new_with_boxed_source(source: Box<dyn UpdateSource>, options: Option<UpdateOptions>, locator: VelopackLocator) -> Result<UpdateManager> {
+        Ok(UpdateManager { source, options: options.unwrap_or_default(), locator })
+    }
+
+    pub fn get_app_id(&self) -> String {
+        self.locator.manifest.id.clone()
+    }
+
+    pub fn get_current_version(&self) -> Version {
+        self.locator.manifest.version.clone()
+    }
+
+    /// The channel which is checked for updates: the explicit channel if one was provided in the
+    /// options, otherwise the channel the application was installed from.
+    pub fn get_channel(&self) -> String {
+        if let Some(channel) = &self.options.explicit_channel {
+            return channel.clone();
+        }
+        if !self.locator.manifest.channel.is_empty() {
+            return self.locator.manifest.channel.clone();
+        }
+        get_default_channel().to_owned()
+    }
+
+    /// Returns true if a package newer than the running version has been downloaded, but not yet applied.
+    pub fn is_update_pending_restart(&self) -> bool {
+        let current = self.get_current_version();
+        self.locator.get_latest_local_full_package().is_some_and(|p| p.version > current)
+    }
+
+    /// Checks the update source for a release newer than the installed version.
+    pub fn check_for_updates(&self) -> Result<Option<UpdateInfo>> {
+        let channel = self.get_channel();
+        let installed = self.get_current_version();
+        debug!("Retrieving latest release feed (channel: '{}').", channel);
+        let feed = self.source.get_release_feed(&channel, &self.locator.manifest)?;
+
+        let staged_user_id = shared::get_or_create_staged_user_id(&self.locator.state_dir, &self.locator.packages_dir);
+        let latest = match feed.get_latest_full_release_for(staged_user_id.as_deref()) {
+            Some(l) => l,
+            None => {
+                info!("No remote full releases found.");
+                return Ok(None);
+            }
+        };
+
+        if crate::commands::is_failed_version(&self.locator.packages_dir, &latest.version) {
+            warn!("Latest remote release {} previously failed its health check, ignoring it.", latest.version);
+            return Ok(None);
+        }
+
+        if latest.version > installed {
+            info!("Found newer remote release available ({} -> {}).", installed, latest.version);
+            return Ok(Some(UpdateInfo { target_full_release: latest.clone(), is_downgrade: false }));
+        }
+
+        if self.options.allow_version_downgrade {
+            let is_other_channel = self.options.explicit_channel.as_ref().is_some_and(|c| !c.eq_ignore_ascii_case(&self.locator.manifest.channel));
+            if latest.version < installed || (latest.version == installed && is_other_channel) {
+                info!("Latest remote release is not newer than current, and downgrade is enabled ({} -> {}).", installed, latest.version);
+                return Ok(Some(UpdateInfo { target_full_release: latest.clone(), is_downgrade: true }));
+            }
+        }
+
+        info!("No updates, remote version ({}) is not newer than current version ({}).", latest.version, installed);
+        Ok(None)
+    }
+
+    /// Downloads the target release into the packages directory and verifies its checksum.
+    /// Progress is reported from 0 to 100.
+    pub fn download_updates<F: FnMut(i16)>(&self, update: &UpdateInfo, mut progress: F) -> Result<()> {
+        let target = &update.target_full_release;
+        let packages_dir = &self.locator.packages_dir;
+        crate::shared::create_private_dir(packages_dir)?;
+
+        let complete_file = packages_dir.join(&target.file_name);
+        let incomplete_file = packages_dir.join(format!("{}.partial", target.file_name));
+
+        if complete_file.exists() {
+            info!("Package already exists on disk: '{}', verifying checksum...", complete_file.to_string_lossy());
+            match verify_package_checksum(&complete_file, target) {
+                Ok(()) => {
+                    info!("Package checksum verified, skipping download.");
+                    progress(100);
+                    return Ok(());
+                }
+                Err(e) => warn!("{} Deleting and starting over.", e),
+            }
+        }
+
+        info!("Downloading full release ({})", target.file_name);
+        let _ = fs::remove_file(&incomplete_file);
+        let result: Result<()> = (|| {
+            self.source.download_release_entry(target, &incomplete_file.to_string_lossy(), &mut progress)?;
+            info!("Verifying package checksum...");
+            verify_package_checksum(&incomplete_file, target)?;
+            shared::retry_io(|| fs::rename(&incomplete_file, &complete_file))?;
+            Ok(())
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(&incomplete_file);
+        }
+        result?;
+
+        info!("Full release download complete. Package moved to: {}", complete_file.to_string_lossy());
+        progress(100);
+
+        let current = self.get_current_version();
+        if let Err(e) = crate::commands::prune_packages(packages_dir, &current, crate::commands::PACKAGES_RETAIN_COUNT, false) {
+            warn!("Failed to clean up packages directory ({}).", e);
+        }
+        Ok(())
+    }
+
+    /// Launches the updater to apply the release and restart the application, then exits this process.
+    /// Only returns if the updater could not be started.
+    pub fn apply_updates_and_restart(&self, to_apply: &VelopackAsset, restart_args: Option<Vec<&str>>) -> Result<()> {
+        self.wait_exit_then_apply_updates(to_apply, false, true, restart_args)?;
+        std::process::exit(0);
+    }
+
+    /// Launches the updater to apply the release without restarting the application, then exits this process.
+    /// Only returns if the updater could not be started.
+    pub fn apply_updates_and_exit(&self, to_apply: &VelopackAsset) -> Result<()> {
+        self.wait_exit_then_apply_updates(to_apply, false, false, None)?;
+        std::process::exit(0);
+    }
+
+    /// Launches the updater, which waits for this process to exit and then applies the release.
+    /// The caller is responsible for exiting soon after this returns.
+    pub fn wait_exit_then_apply_updates(&self, to_apply: &VelopackAsset, silent: bool, restart: bool, restart_args: Option<Vec<&str>>) -> Result<()> {
+        let package = self.locator.packages_dir.join(&to_apply.file_name);
+        if !package.exists() {
+            bail!("Package {} has not been downloaded, call download_updates first.", package.to_string_lossy());
+        }
+
+        let health_check = if restart { self.options.health_check_timeout } else { None };
+        start_updater_apply(&self.locator.update_exe_path, &package, silent, restart, health_check, restart_args)
+    }
+}
+
+/// Starts the updater, which waits for this process to exit before applying the package.
+pub(crate) fn start_updater_apply(
+    update_exe: &Path,
+    package: &Path,
+    silent: bool,
+    restart: bool,
+    health_check: Option<Duration>,
+    restart_args: Option<Vec<&str>>,
+) -> Result<()> {
+    let args = get_apply_args(package, std::process::id(), silent, restart, health_check, restart_args);
+    info!("Starting updater: {} {:?}", update_exe.to_string_lossy(), args);
+    Process::new(update_exe).args(args).spawn().map_err(|e| anyhow::anyhow!("Failed to start updater ({}).", e))?;
+    Ok(())
+}
+
+fn get_apply_args(package: &Path, pid: u32, silent: bool, restart: bool, health_check: Option<Duration>, restart_args: Option<Vec<&str>>) -> Vec<String> {
+    let mut args = Vec::new();
+    if silent {
+        args.push("--silent".to_owned());
+    }
+    args.push("apply".to_owned());
+    args.push("--package".to_owned());
+    args.push(package.to_string_lossy().to_string());
+    args.push("--waitPid".to_owned());
+    args.push(pid.to_string());
+    if !restart {
+        args.push("--norestart".to_owned());
+    }
+    if let Some(timeout) = health_check {
+        args.push("--healthCheck".to_owned());
+        args.push(timeout.as_secs().max(1).to_string());
+    }
+    if let Some(restart_args) = restart_args {
+        if !restart_args.is_empty() {
+            args.push("--".to_owned());
+            args.extend(restart_args.iter().map(|a| a.to_string()));
+        }
+    }
+    args
+}
+
+fn get_default_channel() -> &'static str {
+    #[cfg(target_os = "windows")]
+    return "win";
+    #[cfg(target_os = "linux")]
+    return "linux";
+    #[cfg(target_os = "macos")]
+    return "osx";
+}
+
+fn verify_package_checksum(file: &PathBuf, asset: &VelopackAsset) -> Result<()> {
+    let size = fs::metadata(file)?.len();
+    if asset.size > 0 && size != asset.size {
+        bail!("File size of '{}' is {} but {} was expected.", file.to_string_lossy(), size, asset.size);
+    }
+    if !asset.sha1.is_empty() {
+        let mut hasher = sha1_smol::Sha1::new();
+        let mut reader = fs::File::open(file)?;
+        let mut buffer = vec![0; 1024 * 1024];
+        loop {
+            let read = reader.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+        let actual = hasher.digest().to_string();
+        if !actual.eq_ignore_ascii_case(&asset.sha1) {
+            bail!("SHA1 checksum of '{}' is {} but {} was expected.", file.to_string_lossy(), actual, asset.sha1);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+fn create_test_manager(source_dir: &Path, packages_dir: &Path, options: Option<UpdateOptions>) -> UpdateManager {
+    let mut manifest = shared::bundle::Manifest::default();
+    manifest.id = "MyApp".to_owned();
+    manifest.version = Version::parse("1.0.0").unwrap();
+    manifest.channel = "stable".to_owned();
+    let locator = VelopackLocator {
+        root_app_dir: packages_dir.join("root"),
+        update_exe_path: packages_dir.join("Update"),
+        packages_dir: packages_dir.to_path_buf(),
+        state_dir: packages_dir.join("state"),
+        manifest,
+    };
+    UpdateManager::new_with_locator(super::FileSource::new(source_dir), options, locator).unwrap()
+}
+
+#[cfg(test)]
+fn write_test_feed(source_dir: &Path, channel: &str, version: &str, content: &str) {
+    let file_name = format!("MyApp-{}-full.nupkg", version);
+    fs::write(source_dir.join(&file_name), content).unwrap();
+    let sha1 = sha1_smol::Sha1::from(content).digest().to_string();
+    let feed = format!(
+        r#"{{"Assets":[{{"PackageId":"MyApp","Version":"{}","Type":"Full","FileName":"{}","SHA1":"{}","Size":{}}}]}}"#,
+        version,
+        file_name,
+        sha1,
+        content.len()
+    );
+    fs::write(source_dir.join(super::get_release_feed_name(channel)), feed).unwrap();
+}
+
+#[test]
+fn test_update_manager_checks_and_downloads_updates() {
+    let source = tempfile::tempdir().unwrap();
+    let packages = tempfile::tempdir().unwrap();
+    write_test_feed(source.path(), "stable", "1.1.0", "new version");
+
+    let um = create_test_manager(source.path(), packages.path(), None);
+    assert_eq!(um.get_channel(), "stable");
+    assert!(!um.is_update_pending_restart());
+
+    let update = um.check_for_updates().unwrap().unwrap();
+    assert_eq!(update.target_full_release.version, Version::parse("1.1.0").unwrap());
+    assert!(!update.is_downgrade);
+
+    let mut last_progress = 0;
+    um.download_updates(&update, |p| last_progress = p).unwrap();
+    assert_eq!(last_progress, 100);
+    assert_eq!(fs::read_to_string(packages.path().join("MyApp-1.1.0-full.nupkg")).unwrap(), "new version");
+    assert!(!packages.path().join("MyApp-1.1.0-full.nupkg.partial").exists());
+    assert!(um.is_update_pending_restart());
+}
+
+#[test]
+fn test_update_manager_rejects_bad_checksum() {
+    let source = tempfile::tempdir().unwrap();
+    let packages = tempfile::tempdir().unwrap();
+    write_test_feed(source.path(), "stable", "1.1.0", "new version");
+    fs::write(source.path().join("MyApp-1.1.0-full.nupkg"), "tampered!!!").unwrap();
+
+    let um = create_test_manager(source.path(), packages.path(), None);
+    let update = um.check_for_updates().unwrap().unwrap();
+    assert!(um.download_updates(&update, |_| {}).is_err());
+    assert!(!packages.path().join("MyApp-1.1.0-full.nupkg").exists());
+    assert!(!packages.path().join("MyApp-1.1.0-full.nupkg.partial").exists());
+}
+
+#[test]
+fn test_update_manager_only_downgrades_when_allowed() {
+    let source = tempfile::tempdir().unwrap();
+    let packages = tempfile::tempdir().unwrap();
+    write_test_feed(source.path(), "stable", "0.9.0", "old version");
+    write_test_feed(source.path(), "beta", "1.0.0", "beta version");
+
+    let um = create_test_manager(source.path(), packages.path(), None);
+    assert!(um.check_for_updates().unwrap().is_none());
+
+    let options = UpdateOptions { allow_version_downgrade: true, ..Default::default() };
+    let um = create_test_manager(source.path(), packages.path(), Some(options));
+    assert!(um.check_for_updates().unwrap().unwrap().is_downgrade);
+
+    let options = UpdateOptions { explicit_channel: Some("beta".to_owned()), allow_version_downgrade: true, ..Default::default() };
+    let um = create_test_manager(source.path(), packages.path(), Some(options));
+    let update = um.check_for_updates().unwrap().unwrap();
+    assert_eq!(update.target_full_release.version, Version::parse("1.0.0").unwrap());
+}
+
+#[test]
+fn test_update_manager_honors_staged_rollout() {
+    let source = tempfile::tempdir().unwrap();
+    let packages = tempfile::tempdir().unwrap();
+    write_test_feed(source.path(), "stable", "1.1.0", "new version");
+    let feed_path = source.path().join(super::get_release_feed_name("stable"));
+    let feed = fs::read_to_string(&feed_path).unwrap();
+    let staged = feed.replace(r#"{"Assets":["#, r#"{"Assets":[{"PackageId":"MyApp","Version":"1.2.0","Type":"Full","FileName":"MyApp-1.2.0-full.nupkg","StagingPercentage":0},"#);
+    fs::write(&feed_path, staged).unwrap();
+
+    let um = create_test_manager(source.path(), packages.path(), None);
+    let update = um.check_for_updates().unwrap().unwrap();
+    assert_eq!(update.target_full_release.version, Version::parse("1.1.0").unwrap());
+    assert!(packages.path().join("state").join(".betaId").exists());
+
+    fs::write(&feed_path, feed.replace(r#""Size""#, r#""StagingPercentage":100,"Size""#)).unwrap();
+    assert!(um.check_for_updates().unwrap().is_some());
+}
+
+#[test]
+fn test_file_source_reports_progress() {
+    let source = tempfile::tempdir().unwrap();
+    let packages = tempfile::tempdir().unwrap();
+    let content = "x".repeat(3 * 1024 * 1024);
+    write_test_feed(source.path(), "stable", "1.1.0", &content);
+
+    let um = create_test_manager(source.path(), packages.path(), None);
+    let update = um.check_for_updates().unwrap().unwrap();
+    let mut reported = Vec::new();
+    um.download_updates(&update, |p| reported.push(p)).unwrap();
+    assert!(reported.iter().any(|p| *p > 0 && *p < 100), "{:?}", reported);
+    assert_eq!(reported.last(), Some(&100));
+}
+
+#[test]
+fn test_apply_args() {
+    let args = get_apply_args(Path::new("/tmp/MyApp-1.1.0-full.nupkg"), 42, true, false, None, Some(vec!["--hello"]));
+    assert_eq!(args, vec!["--silent", "apply", "--package", "/tmp/MyApp-1.1.0-full.nupkg", "--waitPid", "42", "--norestart", "--", "--hello"]);
+    let args = get_apply_args(Path::new("/tmp/MyApp-1.1.0-full.nupkg"), 42, false, true, Some(Duration::from_secs(30)), None);
+    assert_eq!(args, vec!["apply", "--package", "/tmp/MyApp-1.1.0-full.nupkg", "--waitPid", "42", "--healthCheck", "30"]);
+}
@@ -0,0 +1,342 @@
+use super::VelopackLocator;
+use semver::Version;
+use std::panic::{self, AssertUnwindSafe};
+
+type VelopackHook<'a> = Box<dyn FnOnce(Version) + 'a>;
+
+/// Handles the lifecycle hooks and environment which the updater uses to communicate with the
+/// application. Should be run as early as possible in `main`, because some hooks exit the process.
+///
+/// ```ignore
+/// VelopackApp::build()
+///     .on_after_install_fast_callback(|v| create_shortcuts(v))
+///     .on_first_run(|v| show_welcome(v))
+///     .run();
+/// ```
+pub struct VelopackApp<'a> {
+    install_hook: Option<VelopackHook<'a>>,
+    update_hook: Option<VelopackHook<'a>>,
+    obsolete_hook: Option<VelopackHook<'a>>,
+    uninstall_hook: Option<VelopackHook<'a>>,
+    firstrun_hook: Option<VelopackHook<'a>>,
+    restarted_hook: Option<VelopackHook<'a>>,
+    args: Option<Vec<String>>,
+    auto_apply: bool,
+    locator: Option<VelopackLocator>,
+}
+
+/// What the application should do after the hooks have been handled.
+#[derive(Debug, Clone, PartialEq)]
+enum RunOutcome {
+    Continue,
+    Exit(i32),
+}
+
+impl<'a> VelopackApp<'a> {
+    pub fn build() -> VelopackApp<'a> {
+        VelopackApp {
+            install_hook: None,
+            update_hook: None,
+            obsolete_hook: None,
+            uninstall_hook: None,
+            firstrun_hook: None,
+            restarted_hook: None,
+            args: None,
+            auto_apply: true,
+            locator: None,
+        }
+    }
+
+    /// Override the command line arguments, which are read from the environment by default.
+    pub fn set_args(mut self, args: Vec<String>) -> Self {
+        self.args = Some(args);
+        self
+    }
+
+    /// Whether a downloaded but not yet applied update is applied when the application starts (default true).
+    pub fn set_auto_apply_on_startup(mut self, auto_apply: bool) -> Self {
+        self.auto_apply = auto_apply;
+        self
+    }
+
+    /// Override the application locations, which are detected from the running executable by default.
+    pub fn set_locator(mut self, locator: VelopackLocator) -> Self {
+        self.locator = Some(locator);
+        self
+    }
+
+    /// Runs the first time the application is started after being installed.
+    pub fn on_first_run<F: FnOnce(Version) + 'a>(mut self, hook: F) -> Self {
+        self.firstrun_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Runs when the application is restarted by the updater after an update.
+    pub fn on_restarted<F: FnOnce(Version) + 'a>(mut self, hook: F) -> Self {
+        self.restarted_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Runs after the application is installed (`--veloapp-install`). The process then exits.
+    pub fn on_after_install_fast_callback<F: FnOnce(Version) + 'a>(mut self, hook: F) -> Self {
+        self.install_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Runs in the new version after an update is applied (`--veloapp-updated`). The process then exits.
+    pub fn on_after_update_fast_callback<F: FnOnce(Version) + 'a>(mut self, hook: F) -> Self {
+        self.update_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Runs in the old version before an update is applied (`--veloapp-obsolete`). The process then exits.
+    pub fn on_before_update_fast_callback<F: FnOnce(Version) + 'a>(mut self, hook: F) -> Self {
+        self.obsolete_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Runs before the application is uninstalled (`--veloapp-uninstall`). The process then exits.
+    pub fn on_before_uninstall_fast_callback<F: FnOnce(Version) + 'a>(mut self, hook: F) -> Self {
+        self.uninstall_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Handles the hooks. If the application was started by the updater to run a fast callback,
+    /// or an update needs to be applied first, this exits the process and does not return. Otherwise,
+    /// an updater waiting on a health check (`apply --healthCheck`) is told the application started successfully.
+    pub fn run(self) {
+        let args = self.args.clone().unwrap_or_else(|| std::env::args().skip(1).collect());
+        let firstrun = is_env_set("VELOPACK_FIRSTRUN");
+        let restarted = is_env_set("VELOPACK_RESTART");
+        // these must not be inherited by any process the application starts
+        std::env::remove_var("VELOPACK_FIRSTRUN");
+        std::env::remove_var("VELOPACK_RESTART");
+
+        if let RunOutcome::Exit(code) = self.handle(&args, firstrun, restarted) {
+            std::process::exit(code);
+        }
+        signal_healthy();
+    }
+
+    fn handle(mut self, args: &[String], firstrun: bool, restarted: bool) -> RunOutcome {
+        // internal hook run by the Velopack tooling to check everything is working
+        if args.first().is_some_and(|a| a.eq_ignore_ascii_case("--veloapp-version")) {
+            println!("{}", env!("NGBV_VERSION"));
+            return RunOutcome::Exit(0);
+        }
+
+        if args.len() >= 2 {
+            let hook_name = args[0].to_ascii_lowercase();
+            let hook = match hook_name.as_str() {
+                "--veloapp-install" => Some(self.install_hook.take()),
+                "--veloapp-updated" => Some(self.update_hook.take()),
+                "--veloapp-obsolete" => Some(self.obsolete_hook.take()),
+                "--veloapp-uninstall" => Some(self.uninstall_hook.take()),
+                // legacy hooks are accepted, but ignored
+                "--squirrel-install" | "--squirrel-updated" | "--squirrel-obsolete" | "--squirrel-uninstall" => Some(None),
+                _ => None,
+            };
+            if let Some(hook) = hook {
+                info!("Found fast exit hook: {}", args[0]);
+                return match Version::parse(&args[1]) {
+                    Ok(version) => {
+                        if run_hook(hook, version) {
+                            info!("Completed hook, exiting...");
+                            RunOutcome::Exit(0)
+                        } else {
+                            error!("Error occurred executing user defined Velopack hook. ({})", args[0]);
+                            RunOutcome::Exit(-1)
+                        }
+                    }
+                    Err(e) => {
+                        error!("Invalid version passed to hook {} ({}).", args[0], e);
+                        RunOutcome::Exit(-1)
+                    }
+                };
+            }
+        }
+
+        let locator = match self.locator.take().map_or_else(VelopackLocator::auto_locate, Ok) {
+            Ok(l) => l,
+            Err(e) => {
+                debug!("Application is not installed, skipping startup hooks ({}).", e);
+                return RunOutcome::Continue;
+            }
+        };
+        let my_version = locator.manifest.version.clone();
+
+        // an apply which a hook postponed is retried here too, since an AppImage is usually launched without `update start`
+        if !restarted && self.auto_apply {
+            if let Some(outcome) = retry_pending_apply(&locator, args) {
+                return outcome;
+            }
+        }
+
+        // if we've not just been restarted by the updater, and there is a newer package available, apply it first
+        if let Some(latest) = locator.get_latest_local_full_package() {
+            if latest.version > my_version {
+                info!("Launching app is out-dated. Current: {}, Newest Local Available: {}", my_version, latest.version);
+                if !restarted && self.auto_apply {
+                    info!("Auto apply is true, so restarting to apply update...");
+                    let exe_args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+                    match super::start_updater_apply(&locator.update_exe_path, latest.file_path.as_ref(), false, true, None, Some(exe_args)) {
+                        Ok(()) => return RunOutcome::Exit(0),
+                        Err(e) => error!("Failed to apply update on startup ({}).", e),
+                    }
+                } else {
+                    info!("Pre-condition failed, we will not restart to apply updates. (restarted: {}, autoApply: {})", restarted, self.auto_apply);
+                }
+            }
+        }
+
+        // an AppImage is usually run straight after it was downloaded, so it gets its menu entry here rather than on install
+        #[cfg(target_os = "linux")]
+        if locator.root_app_dir.is_file() {
+            if let Err(e) = crate::shared::ensure_desktop_entry(&locator.root_app_dir, &locator.manifest) {
+                warn!("Failed to create desktop entry ({}).", e);
+            }
+        }
+
+        if firstrun && !run_hook(self.firstrun_hook.take(), my_version.clone()) {
+            error!("Error occurred executing user defined Velopack hook. (firstrun)");
+        }
+        if restarted && !run_hook(self.restarted_hook.take(), my_version) {
+            error!("Error occurred executing user defined Velopack hook. (restarted)");
+        }
+        RunOutcome::Continue
+    }
+}
+
+/// Starts the updater to retry an apply which was postponed by a hook. Any other pending operation is left for `update start`.
+fn retry_pending_apply(locator: &VelopackLocator, args: &[String]) -> Option<RunOutcome> {
+    use crate::commands::PendingRetry;
+    let pending = crate::commands::take_pending_retry(&locator.packages_dir)?;
+    let file_name = match &pending {
+        PendingRetry::Apply(file_name) => file_name,
+        _ => {
+            let _ = crate::commands::record_pending_retry(&locator.packages_dir, &pending);
+            return None;
+        }
+    };
+
+    let package = locator.packages_dir.join(file_name);
+    let is_newer = crate::shared::bundle::parse_package_file_path(package.clone()).is_some_and(|p| p.version > locator.manifest.version);
+    if !package.exists() || !is_newer {
+        info!("Discarding postponed apply of {}, it is missing or not newer than the running version.", package.to_string_lossy());
+        return None;
+    }
+
+    info!("Restarting to retry the postponed apply of {}...", package.to_string_lossy());
+    let exe_args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+    match super::start_updater_apply(&locator.update_exe_path, &package, false, true, None, Some(exe_args)) {
+        Ok(()) => Some(RunOutcome::Exit(0)),
+        Err(e) => {
+            error!("Failed to retry the postponed apply ({}).", e);
+            let _ = crate::commands::record_pending_retry(&locator.packages_dir, &pending);
+            None
+        }
+    }
+}
+
+fn signal_healthy() {
+    if let Ok(marker_path) = std::env::var(crate::commands::HEALTH_FILE_ENV) {
+        std::env::remove_var(crate::commands::HEALTH_FILE_ENV);
+        if let Err(e) = std::fs::write(&marker_path, "") {
+            warn!("Failed to signal a successful start to the updater ({}).", e);
+        }
+    }
+}
+
+fn is_env_set(name: &str) -> bool {
+    std::env::var(name).map(|v| !v.trim().is_empty()).unwrap_or(false)
+}
+
+fn run_hook(hook: Option<VelopackHook>, version: Version) -> bool {
+    match hook {
+        Some(hook) => panic::catch_unwind(AssertUnwindSafe(|| hook(version))).is_ok(),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+fn test_args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|a| a.to_string()).collect()
+}
+
+#[cfg(test)]
+fn create_test_locator(packages_dir: &std::path::Path) -> VelopackLocator {
+    let mut manifest = crate::shared::bundle::Manifest::default();
+    manifest.id = "MyApp".to_owned();
+    manifest.version = Version::parse("1.0.0").unwrap();
+    VelopackLocator {
+        root_app_dir: packages_dir.join("root"),
+        update_exe_path: packages_dir.join("Update"),
+        packages_dir: packages_dir.to_path_buf(),
+        state_dir: packages_dir.join("state"),
+        manifest,
+    }
+}
+
+#[test]
+fn test_fast_hooks_run_callback_and_exit() {
+    let mut installed = None;
+    let outcome = VelopackApp::build().on_after_install_fast_callback(|v| installed = Some(v)).handle(&test_args(&["--veloapp-install", "1.2.3"]), false, false);
+    assert_eq!(outcome, RunOutcome::Exit(0));
+    assert_eq!(installed, Some(Version::new(1, 2, 3)));
+
+    // hooks without a callback, and legacy hooks, still exit successfully
+    let outcome = VelopackApp::build().handle(&test_args(&["--VELOAPP-UNINSTALL", "1.2.3"]), false, false);
+    assert_eq!(outcome, RunOutcome::Exit(0));
+    let outcome = VelopackApp::build().handle(&test_args(&["--squirrel-updated", "1.2.3"]), false, false);
+    assert_eq!(outcome, RunOutcome::Exit(0));
+
+    let outcome = VelopackApp::build().on_after_update_fast_callback(|_| panic!("hook failed")).handle(&test_args(&["--veloapp-updated", "1.2.3"]), false, false);
+    assert_eq!(outcome, RunOutcome::Exit(-1));
+    let outcome = VelopackApp::build().handle(&test_args(&["--veloapp-obsolete", "not-a-version"]), false, false);
+    assert_eq!(outcome, RunOutcome::Exit(-1));
+}
+
+#[test]
+fn test_startup_hooks_follow_environment() {
+    let tmp = tempfile::tempdir().unwrap();
+    let mut firstrun = None;
+    let mut restarted = None;
+    let outcome = VelopackApp::build()
+        .set_locator(create_test_locator(tmp.path()))
+        .on_first_run(|v| firstrun = Some(v))
+        .on_restarted(|v| restarted = Some(v))
+        .handle(&test_args(&["--some-arg"]), true, false);
+    assert_eq!(outcome, RunOutcome::Continue);
+    assert_eq!(firstrun, Some(Version::new(1, 0, 0)));
+    assert_eq!(restarted, None);
+}
+
+#[test]
+fn test_pending_update_is_not_applied_after_restart() {
+    let tmp = tempfile::tempdir().unwrap();
+    std::fs::write(tmp.path().join("MyApp-2.0.0-full.nupkg"), "x").unwrap();
+    let mut restarted = false;
+    let outcome = VelopackApp::build().set_locator(create_test_locator(tmp.path())).on_restarted(|_| restarted = true).handle(&[], false, true);
+    assert_eq!(outcome, RunOutcome::Continue);
+    assert!(restarted);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_postponed_apply_is_retried_on_startup() {
+    let tmp = tempfile::tempdir().unwrap();
+    let package = tmp.path().join("MyApp-2.0.0-full.nupkg");
+    std::fs::write(&package, "x").unwrap();
+    crate::commands::record_pending_apply(tmp.path(), &package).unwrap();
+
+    // not retried after a restart by the updater, so a postponed apply can not loop
+    let outcome = VelopackApp::build().set_locator(create_test_locator(tmp.path())).handle(&[], false, true);
+    assert_eq!(outcome, RunOutcome::Continue);
+    assert!(crate::commands::has_pending_apply(tmp.path(), &package));
+
+    let mut locator = create_test_locator(tmp.path());
+    locator.update_exe_path = "/bin/true".into();
+    let outcome = VelopackApp::build().set_locator(locator).handle(&[], false, false);
+    assert_eq!(outcome, RunOutcome::Exit(0));
+    assert_eq!(crate::commands::take_pending_retry(tmp.path()), None);
+}
@@ -19,6 +19,7 @@ fn main() -> Result<()> {
         .arg(arg!(-v --verbose "Print debug messages to console"))
         .arg(arg!(-l --log <FILE> "Enable file logging and set location").required(false).value_parser(value_parser!(PathBuf)))
         .arg(arg!(-t --installto <DIR> "Installation directory to install the application").required(false).value_parser(value_parser!(PathBuf)))
+        .arg(arg!(--dryRun "Validate the package and report the planned changes, without installing it").alias("dry-run"))
         .arg(arg!(--nocolor "Disable colored output").hide(true));
 
     if cfg!(debug_assertions) {
@@ -32,9 +33,10 @@ fn main() -> Result<()> {
     let logfile = matches.get_one::<PathBuf>("log");
     let installto = matches.get_one::<PathBuf>("installto");
     let nocolor = matches.get_flag("nocolor");
+    let dry_run = matches.get_flag("dryRun");
 
     shared::dialogs::set_silent(silent);
-    logging::setup_logging("setup", logfile, true, verbose, nocolor)?;
+    logging::setup_logging("setup", logfile, true, verbose, nocolor, logging::LogFormat::Text, logging::LogFormat::Text)?;
 
     info!("Starting Velopack Setup ({})", env!("NGBV_VERSION"));
     info!("    Location: {:?}", std::env::current_exe()?);
@@ -42,6 +44,7 @@ fn main() -> Result<()> {
     info!("    Verbose: {}", verbose);
     info!("    Log: {:?}", logfile);
     info!("    Install To: {:?}", installto);
+    info!("    Dry Run: {}", dry_run);
     if cfg!(debug_assertions) {
         info!("    Debug: {:?}", debug);
     }
@@ -51,7 +54,7 @@ fn main() -> Result<()> {
     containing_dir.pop();
     env::set_current_dir(containing_dir)?;
 
-    let res = commands::install(debug, installto);
+    let res = commands::install(debug, installto, dry_run);
     if let Err(e) = &res {
         error!("An error has occurred: {}", e);
         dialogs::show_error("Setup Error", None, format!("An error has occurred: {}", e).as_str());
@@ -96,6 +96,15 @@ pub fn load_bundle_from_mmap<'a>(mmap: &'a Mmap, debug_pkg: Option<&PathBuf>) ->
     bail!("Could not find embedded zip file. Please contact the application author.");
 }
 
+/// An app file in the `lib/<framework>/` dir of a package, with its path relative to the current dir.
+struct LibEntry {
+    index: usize,
+    path: String,
+    // only extracted on windows and macos, linux packages contain a single AppImage
+    #[cfg_attr(target_os = "linux", allow(dead_code))]
+    is_symlink: bool,
+}
+
 #[derive(Clone)]
 pub struct BundleInfo<'a> {
     zip: Rc<RefCell<ZipArchive<Box<dyn ReadSeek + 'a>>>>,
@@ -177,6 +186,11 @@ impl BundleInfo<'_> {
     }
 
     pub fn extract_zip_idx_to_path<T: AsRef<Path>>(&self, index: usize, path: T) -> Result<()> {
+        self.extract_zip_idx_to_path_with_progress(index, path, |_| {})
+    }
+
+    /// Extracts a single file, reporting progress (0-100) as it is written. Used for large files such as an AppImage.
+    pub fn extract_zip_idx_to_path_with_progress<T: AsRef<Path>, F: Fn(i16)>(&self, index: usize, path: T, progress: F) -> Result<()> {
         let path = path.as_ref();
         debug!("Extracting zip file to path: {}", path.to_string_lossy());
         let p = PathBuf::from(path);
@@ -192,6 +206,10 @@ impl BundleInfo<'_> {
         let mut outfile = super::retry_io(|| File::create(path))?;
         let mut buffer = [0; 64000]; // Use a 64KB buffer; good balance for large/small files.
 
+        let total = file.size();
+        let mut written = 0u64;
+        progress(0);
+
         debug!("Writing normal file to disk with 64k buffer: {:?}", path);
         loop {
             let len = file.read(&mut buffer)?;
@@ -199,8 +217,14 @@ impl BundleInfo<'_> {
                 break; // End of file
             }
             outfile.write_all(&buffer[..len])?;
+            written += len as u64;
+            if let Some(percent) = (written.min(total) * 100).checked_div(total) {
+                progress(percent as i16);
+            }
         }
 
+        progress(100);
+
         Ok(())
     }
 
@@ -248,14 +272,10 @@ impl BundleInfo<'_> {
     #[cfg(not(target_os = "linux"))]
     pub fn extract_lib_contents_to_path<P: AsRef<Path>, F: Fn(i16)>(&self, current_path: P, progress: F) -> Result<()> {
         let current_path = current_path.as_ref();
-        let files = self.get_file_names()?;
-        let num_files = files.len();
+        let entries = self.find_lib_entries()?;
+        let num_files = entries.len();
 
         info!("Extracting {} app files...", num_files);
-        let re = Regex::new(r"lib[\\\/][^\\\/]*[\\\/]").unwrap();
-        let stub_regex = Regex::new("_ExecutionStub.exe$").unwrap();
-        let symlink_regex = Regex::new(".__symlink$").unwrap();
-        let updater_idx = self.find_zip_file(|name| name.ends_with("Squirrel.exe"));
 
         // for legacy support, we still extract the nuspec file to the current dir.
         // in newer versions, the nuspec is in the current dir in the package itself.
@@ -270,36 +290,22 @@ impl BundleInfo<'_> {
         // we extract the symlinks after, because the target must exist.
         let mut symlinks: Vec<(usize, PathBuf)> = Vec::new();
 
-        for (i, key) in files.iter().enumerate() {
-            if Some(i) == updater_idx || !re.is_match(key) || key.ends_with("/") || key.ends_with("\\") {
-                debug!("    {} Skipped '{}'", i, key);
-                continue;
-            }
+        for (n, entry) in entries.iter().enumerate() {
+            let i = entry.index;
+            let file_path_on_disk = Path::new(&current_path).join(&entry.path);
 
-            let file_path_in_zip = re.replace(key, "").to_string();
-            let file_path_on_disk = Path::new(&current_path).join(&file_path_in_zip);
-
-            if symlink_regex.is_match(&file_path_in_zip) {
-                let sym_key = symlink_regex.replace(&file_path_in_zip, "").to_string();
-                let file_path_on_disk = Path::new(&current_path).join(&sym_key);
+            if entry.is_symlink {
                 symlinks.push((i, file_path_on_disk));
                 continue;
             }
 
-            if stub_regex.is_match(&file_path_in_zip) {
-                // let stub_key = stub_regex.replace(&file_path_in_zip, ".exe").to_string();
-                // file_path_on_disk = root_path.join(&stub_key);
-                debug!("    {} Skipped Stub (obsolete) '{}'", i, key);
-                continue;
-            }
-
             // on windows, the zip paths are / and should be \ instead
             #[cfg(target_os = "windows")]
             let file_path_on_disk = file_path_on_disk.normalize_virtually()?;
             #[cfg(target_os = "windows")]
             let file_path_on_disk = file_path_on_disk.as_path();
 
-            debug!("    {} Extracting '{}' to '{}'", i, key, file_path_on_disk.to_string_lossy());
+            debug!("    {} Extracting '{}' to '{}'", i, entry.path, file_path_on_disk.to_string_lossy());
             self.extract_zip_idx_to_path(i, &file_path_on_disk)?;
 
             // on macos, we need to chmod +x the executable files
@@ -324,7 +330,7 @@ impl BundleInfo<'_> {
                 }
             }
 
-            progress(((i as f32 / num_files as f32) * 100.0) as i16);
+            progress(((n as f32 / num_files as f32) * 100.0) as i16);
         }
 
         // we extract the symlinks after, because the target must exist.
@@ -351,6 +357,78 @@ impl BundleInfo<'_> {
         Ok(())
     }
 
+    /// Lists the app files which `extract_lib_contents_to_path` would write, as the zip index and the
+    /// path relative to the current dir (with `/` separators). Symlinks are listed by the path of the link.
+    pub fn get_lib_file_entries(&self) -> Result<Vec<(usize, String)>> {
+        let mut entries = Vec::new();
+
+        #[cfg(target_os = "windows")]
+        if let Some(nuspec_idx) = self.find_zip_file(|name| name.ends_with(".nuspec")) {
+            entries.push((nuspec_idx, "sq.version".to_owned()));
+        }
+
+        for entry in self.find_lib_entries()? {
+            entries.push((entry.index, entry.path.replace('\\', "/")));
+        }
+        Ok(entries)
+    }
+
+    /// Finds the entries under `lib/<framework>/` which are installed to the current dir. The updater,
+    /// directories and obsolete execution stubs are skipped.
+    fn find_lib_entries(&self) -> Result<Vec<LibEntry>> {
+        let files = self.get_file_names()?;
+        let re = Regex::new(r"lib[\\\/][^\\\/]*[\\\/]").unwrap();
+        let stub_regex = Regex::new("_ExecutionStub.exe$").unwrap();
+        let symlink_regex = Regex::new(".__symlink$").unwrap();
+        let updater_idx = self.find_zip_file(|name| name.ends_with("Squirrel.exe"));
+        let mut entries = Vec::new();
+
+        for (i, key) in files.iter().enumerate() {
+            if Some(i) == updater_idx || !re.is_match(key) || key.ends_with("/") || key.ends_with("\\") {
+                debug!("    {} Skipped '{}'", i, key);
+                continue;
+            }
+            let file_path_in_zip = re.replace(key, "").to_string();
+            if stub_regex.is_match(&file_path_in_zip) {
+                debug!("    {} Skipped Stub (obsolete) '{}'", i, key);
+                continue;
+            }
+            let is_symlink = symlink_regex.is_match(&file_path_in_zip);
+            let path = symlink_regex.replace(&file_path_in_zip, "").to_string();
+            entries.push(LibEntry { index: i, path, is_symlink });
+        }
+        Ok(entries)
+    }
+
+    /// Whether the contents of a zip entry are identical to a file on disk.
+    pub fn is_zip_idx_same_as_file<T: AsRef<Path>>(&self, index: usize, path: T) -> Result<bool> {
+        let path = path.as_ref();
+        let metadata = match fs::metadata(path) {
+            Ok(m) => m,
+            Err(_) => return Ok(false),
+        };
+
+        let mut archive = self.zip.borrow_mut();
+        let mut file = archive.by_index(index)?;
+        if metadata.len() != file.size() {
+            return Ok(false);
+        }
+
+        let mut disk_file = File::open(path)?;
+        let mut zip_buffer = [0; 64000];
+        let mut disk_buffer = [0; 64000];
+        loop {
+            let len = file.read(&mut zip_buffer)?;
+            if len == 0 {
+                return Ok(true);
+            }
+            disk_file.read_exact(&mut disk_buffer[..len])?;
+            if zip_buffer[..len] != disk_buffer[..len] {
+                return Ok(false);
+            }
+        }
+    }
+
     pub fn read_manifest(&self) -> Result<Manifest> {
         let nuspec_idx = self
             .find_zip_file(|name| name.ends_with(".nuspec"))
@@ -406,6 +484,7 @@ pub struct Manifest {
     pub os: String,
     pub os_min_version: String,
     pub channel: String,
+    pub staging_percentage: Option<f32>,
 }
 
 #[cfg(target_os = "windows")]
@@ -507,6 +586,12 @@ pub fn read_manifest_from_string(xml: &str) -> Result<Manifest> {
                     obj.os_min_version = text;
                 } else if el_name == "channel" {
                     obj.channel = text;
+                } else if el_name == "stagingPercentage" {
+                    // a bad value should not make the whole package unreadable, so it is treated as not staged
+                    match text.trim().trim_end_matches('%').parse::<f32>() {
+                        Ok(p) => obj.staging_percentage = Some(p),
+                        Err(e) => warn!("Ignoring invalid stagingPercentage '{}' ({}).", text, e),
+                    }
                 }
             }
             Ok(XmlEvent::EndElement { .. }) => {
@@ -631,3 +716,30 @@ fn test_parse_package_file_name() {
     assert!(parse_package_file_name("MyCoolApp-1.2.3.nupkg").is_none());
     assert!(parse_package_file_name("MyCoolApp-1.2-full.nupkg").is_none());
 }
+
+#[test]
+fn test_read_manifest_staging_percentage() {
+    let nuspec = "<?xml version=\"1.0\"?>\n<package>\n<metadata>\n  <id>MyApp</id>\n  <version>1.0.0</version>\n  <stagingPercentage>{}</stagingPercentage>\n</metadata>\n</package>";
+    assert_eq!(read_manifest_from_string(&nuspec.replace("{}", "25%")).unwrap().staging_percentage, Some(25.0));
+    let mani = read_manifest_from_string(&nuspec.replace("{}", "a quarter")).unwrap();
+    assert_eq!(mani.staging_percentage, None);
+    assert_eq!(mani.id, "MyApp");
+}
+
+#[test]
+fn test_find_lib_entries_applies_skip_rules() {
+    use std::io::Write;
+    let tmp = tempfile::tempdir().unwrap();
+    let package = tmp.path().join("MyApp-1.0.0-full.nupkg");
+    let mut zip = zip::ZipWriter::new(File::create(&package).unwrap());
+    let options = zip::write::FileOptions::default();
+    for name in ["MyApp.nuspec", "lib/app/Squirrel.exe", "lib/app/MyApp_ExecutionStub.exe", "lib/app/sub/", "lib/app/MyApp", "lib/app/link.__symlink"] {
+        zip.start_file(name, options).unwrap();
+        zip.write_all(b"contents").unwrap();
+    }
+    zip.finish().unwrap();
+
+    let bundle = load_bundle_from_file(&package).unwrap();
+    let entries: Vec<(String, bool)> = bundle.find_lib_entries().unwrap().into_iter().map(|e| (e.path, e.is_symlink)).collect();
+    assert_eq!(entries, vec![("MyApp".to_owned(), false), ("link".to_owned(), true)]);
+}
@@ -0,0 +1,50 @@
+use super::bundle::Manifest;
+use anyhow::Result;
+use std::{fs, path::Path};
+
+const CHANNEL_FILE: &str = ".channel";
+
+/// Reads the channel this installation was switched to, if it was ever switched away from the channel of the installed package.
+pub fn read_installed_channel<P: AsRef<Path>>(state_dir: P) -> Option<String> {
+    let path = state_dir.as_ref().join(CHANNEL_FILE);
+    let channel = fs::read_to_string(&path).ok()?;
+    let channel = channel.trim();
+    if channel.is_empty() {
+        return None;
+    }
+    Some(channel.to_owned())
+}
+
+/// Records the channel this installation follows. The installed manifest is never modified, since it is
+/// read-only inside an AppImage and covered by the code signature of a macOS bundle.
+pub fn write_installed_channel<P: AsRef<Path>>(state_dir: P, channel: &str) -> Result<()> {
+    let state_dir = state_dir.as_ref();
+    super::create_private_dir(state_dir)?;
+    let path = state_dir.join(CHANNEL_FILE);
+    info!("Recording channel '{}' in {}", channel, path.to_string_lossy());
+    super::retry_io(|| fs::write(&path, channel))?;
+    Ok(())
+}
+
+/// Replaces the channel of an installed manifest with the one recorded by `switch-channel`, if there is one.
+pub fn apply_installed_channel(root_path: &Path, manifest: &mut Manifest) {
+    if let Some(channel) = read_installed_channel(super::get_app_state_dir(&manifest.id, root_path)) {
+        if !channel.eq_ignore_ascii_case(&manifest.channel) {
+            debug!("Using recorded channel '{}' instead of '{}'.", channel, manifest.channel);
+            manifest.channel = channel;
+        }
+    }
+}
+
+#[test]
+fn test_installed_channel_is_persisted() {
+    let tmp = tempfile::tempdir().unwrap();
+    let state_dir = tmp.path().join("state");
+    assert_eq!(read_installed_channel(&state_dir), None);
+    write_installed_channel(&state_dir, "beta").unwrap();
+    assert_eq!(read_installed_channel(&state_dir), Some("beta".to_owned()));
+    write_installed_channel(&state_dir, "stable").unwrap();
+    assert_eq!(read_installed_channel(&state_dir), Some("stable".to_owned()));
+    fs::write(state_dir.join(CHANNEL_FILE), "  \n").unwrap();
+    assert_eq!(read_installed_channel(&state_dir), None);
+}
@@ -61,6 +61,17 @@ pub fn ask_user_to_elevate(app_to: &crate::bundle::Manifest) -> Result<()> {
     }
 }
 
+#[cfg(not(target_os = "windows"))]
+pub fn show_uninstall_complete_with_errors_dialog(app: &crate::bundle::Manifest, log_path: Option<&std::path::PathBuf>) {
+    let title = format!("{} Uninstall", app.title);
+    let header = format!("{} uninstall has completed with errors.", app.title);
+    let mut body = "There may be left-over files or directories on your system. You can attempt to remove these manually or re-install the application and try again.".to_owned();
+    if let Some(log_path) = log_path.filter(|p| p.exists()) {
+        body += &format!("\n\nLog file: '{}'", log_path.to_string_lossy());
+    }
+    show_warn(&title, Some(&header), &body);
+}
+
 #[test]
 #[ntest::timeout(2000)]
 fn test_no_dialogs_show_if_silent() {
@@ -0,0 +1,215 @@
+use anyhow::{bail, Result};
+use std::{
+    env, fs,
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    process::{Command as Process, Stdio},
+};
+
+/// A mechanism which can run a command as root, in the order they are tried.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ElevationMethod {
+    Pkexec,
+    SudoAskpass,
+    Doas,
+    Run0,
+}
+
+pub const ELEVATION_METHODS: [ElevationMethod; 4] = [ElevationMethod::Pkexec, ElevationMethod::SudoAskpass, ElevationMethod::Doas, ElevationMethod::Run0];
+
+/// System directories which only root can write to. Programs which end up running as root are only looked up here,
+/// never in $PATH, which the user (or anything running as the user) can change.
+const TRUSTED_PROGRAM_DIRS: [&str; 4] = ["/usr/bin", "/bin", "/usr/sbin", "/sbin"];
+
+/// Well known graphical askpass helpers, used by `sudo -A` when SUDO_ASKPASS is not set.
+const ASKPASS_PROGRAMS: [&str; 4] = ["ssh-askpass", "ksshaskpass", "lxqt-openssh-askpass", "x11-ssh-askpass"];
+
+impl ElevationMethod {
+    pub fn program(&self) -> &'static str {
+        match self {
+            ElevationMethod::Pkexec => "pkexec",
+            ElevationMethod::SudoAskpass => "sudo",
+            ElevationMethod::Doas => "doas",
+            ElevationMethod::Run0 => "run0",
+        }
+    }
+}
+
+impl std::fmt::Display for ElevationMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ElevationMethod::SudoAskpass => write!(f, "sudo -A"),
+            other => write!(f, "{}", other.program()),
+        }
+    }
+}
+
+/// What is known about the current session, which decides which elevation methods can prompt the user.
+#[derive(Debug, Clone, Default)]
+pub struct ElevationContext {
+    pub search_path: Vec<PathBuf>,
+    pub askpass: Option<PathBuf>,
+    pub has_display: bool,
+    pub has_terminal: bool,
+}
+
+impl ElevationContext {
+    pub fn from_env() -> ElevationContext {
+        let search_path: Vec<PathBuf> = TRUSTED_PROGRAM_DIRS.iter().map(PathBuf::from).collect();
+        let askpass = env::var_os("SUDO_ASKPASS")
+            .map(PathBuf::from)
+            .filter(|p| p.is_file())
+            .or_else(|| ASKPASS_PROGRAMS.iter().find_map(|p| find_program(&search_path, p)));
+        let has_display = env::var_os("DISPLAY").is_some() || env::var_os("WAYLAND_DISPLAY").is_some();
+        ElevationContext { search_path, askpass, has_display, has_terminal: std::io::stdin().is_terminal() }
+    }
+
+    pub fn find_program(&self, name: &str) -> Option<PathBuf> {
+        find_program(&self.search_path, name)
+    }
+}
+
+fn find_program(search_path: &[PathBuf], name: &str) -> Option<PathBuf> {
+    search_path.iter().map(|dir| dir.join(name)).find(|p| p.is_file())
+}
+
+/// Finds a program in a system directory, which is owned by root and can not be modified by anyone else.
+pub fn find_trusted_program(name: &str) -> Option<PathBuf> {
+    TRUSTED_PROGRAM_DIRS.iter().map(|dir| Path::new(dir).join(name)).find(|p| is_root_owned_file(p))
+}
+
+fn is_root_owned_file(path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).is_ok_and(|m| m.is_file() && m.uid() == 0 && m.mode() & 0o022 == 0)
+}
+
+/// Decides whether an elevation method can be used in this session, returning the path of its
+/// program, or the reason it can not be used.
+pub fn check_elevation_method(ctx: &ElevationContext, method: ElevationMethod) -> Result<PathBuf, String> {
+    let program = ctx.find_program(method.program()).ok_or_else(|| "not installed".to_owned())?;
+    match method {
+        ElevationMethod::Pkexec | ElevationMethod::Run0 if !ctx.has_display && !ctx.has_terminal => {
+            Err("no graphical session or terminal for the polkit agent to prompt in".to_owned())
+        }
+        ElevationMethod::SudoAskpass if ctx.askpass.is_none() => Err("SUDO_ASKPASS is not set and no askpass program was found".to_owned()),
+        _ => Ok(program),
+    }
+}
+
+/// Runs a program as root, trying each available elevation method in turn. If every method fails,
+/// the error lists why each one could not be used. Nothing is written to disk.
+pub fn run_elevated(program: &Path, args: &[&str]) -> Result<()> {
+    run_elevated_with(&ElevationContext::from_env(), program, args)
+}
+
+pub fn run_elevated_with(ctx: &ElevationContext, program: &Path, args: &[&str]) -> Result<()> {
+    let mut failures = Vec::new();
+    for method in ELEVATION_METHODS {
+        let method_path = match check_elevation_method(ctx, method) {
+            Ok(p) => p,
+            Err(reason) => {
+                info!("Elevation with {} is not available ({}).", method, reason);
+                failures.push(format!("{}: {}", method, reason));
+                continue;
+            }
+        };
+
+        let mut cmd = Process::new(&method_path);
+        match method {
+            ElevationMethod::SudoAskpass => {
+                cmd.arg("-A").env("SUDO_ASKPASS", ctx.askpass.as_ref().unwrap());
+            }
+            // without a terminal doas can not prompt, but it may still be configured with nopass
+            ElevationMethod::Doas if !ctx.has_terminal => {
+                cmd.arg("-n");
+            }
+            _ => {}
+        }
+        // pkexec takes the program as its first non-option argument, and does not understand '--'
+        if method != ElevationMethod::Pkexec {
+            cmd.arg("--");
+        }
+        cmd.arg(program).args(args).stdin(Stdio::inherit()).stdout(Stdio::piped()).stderr(Stdio::piped());
+
+        info!("Attempting to elevate: {} {} {:?}", method, program.to_string_lossy(), args);
+        let output = match cmd.output() {
+            Ok(o) => o,
+            Err(e) => {
+                failures.push(format!("{}: failed to start ({})", method, e));
+                continue;
+            }
+        };
+        if output.status.success() {
+            info!("Elevated command succeeded using {}.", method);
+            return Ok(());
+        }
+
+        let code = output.status.code().unwrap_or(-1);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let detail = stderr.lines().map(|l| l.trim()).rfind(|l| !l.is_empty()).unwrap_or_default().to_owned();
+        // pkexec and run0 exit with 126 when the user dismisses the authentication dialog, don't ask them again
+        if code == 126 && matches!(method, ElevationMethod::Pkexec | ElevationMethod::Run0) {
+            bail!("Elevation was cancelled, the authentication dialog was dismissed ({}).", method);
+        }
+        let reason = match (method, code) {
+            (ElevationMethod::Pkexec, 127) => format!("not authorized, or no polkit authentication agent is running ({})", detail),
+            _ if detail.is_empty() => format!("exited with code {}", code),
+            _ => format!("exited with code {} ({})", code, detail),
+        };
+        warn!("Elevation with {} failed: {}", method, reason);
+        failures.push(format!("{}: {}", method, reason));
+    }
+    bail!("Unable to elevate permissions. {}.", failures.join("; "))
+}
+
+#[cfg(test)]
+fn write_fake_program(dir: &Path, name: &str, script: &str) {
+    use std::os::unix::fs::PermissionsExt;
+    let path = dir.join(name);
+    std::fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+#[test]
+fn test_elevation_reports_why_each_method_failed() {
+    let tmp = tempfile::tempdir().unwrap();
+    write_fake_program(tmp.path(), "pkexec", "echo 'Error executing command as another user: No authentication agent found.' 1>&2; exit 127");
+    write_fake_program(tmp.path(), "doas", "echo 'doas: Authentication failed' 1>&2; exit 1");
+    let ctx = ElevationContext { search_path: vec![tmp.path().to_path_buf()], askpass: None, has_display: true, has_terminal: false };
+
+    let err = run_elevated_with(&ctx, Path::new("/bin/true"), &[]).unwrap_err().to_string();
+    assert!(err.contains("pkexec: not authorized, or no polkit authentication agent is running (Error executing command as another user: No authentication agent found.)"));
+    assert!(err.contains("sudo -A: not installed"));
+    assert!(err.contains("doas: exited with code 1 (doas: Authentication failed)"));
+    assert!(err.contains("run0: not installed"));
+}
+
+#[test]
+fn test_elevation_falls_back_and_stops_when_cancelled() {
+    let tmp = tempfile::tempdir().unwrap();
+    let marker = tmp.path().join("ran");
+    // the fake sudo checks it was given -A and an askpass program, and then runs the command
+    write_fake_program(tmp.path(), "sudo", "[ \"$1\" = \"-A\" ] && [ -n \"$SUDO_ASKPASS\" ] || exit 1; shift 2; exec \"$@\"");
+    write_fake_program(tmp.path(), "ssh-askpass", "echo password");
+    write_fake_program(tmp.path(), "run0", "exit 126");
+    let mut ctx = ElevationContext { search_path: vec![tmp.path().to_path_buf()], askpass: None, has_display: false, has_terminal: false };
+    ctx.askpass = ctx.find_program("ssh-askpass");
+
+    assert_eq!(check_elevation_method(&ctx, ElevationMethod::Pkexec), Err("not installed".to_owned()));
+    run_elevated_with(&ctx, Path::new("/usr/bin/touch"), &[marker.to_str().unwrap()]).unwrap();
+    assert!(marker.exists());
+
+    write_fake_program(tmp.path(), "sudo", "exit 1");
+    ctx.has_display = true;
+    let err = run_elevated_with(&ctx, Path::new("/bin/true"), &[]).unwrap_err().to_string();
+    assert!(err.contains("cancelled"));
+}
+
+#[test]
+fn test_programs_are_only_found_in_system_dirs() {
+    let ctx = ElevationContext::from_env();
+    assert!(ctx.search_path.iter().all(|p| TRUSTED_PROGRAM_DIRS.contains(&p.to_str().unwrap())));
+    let mv = find_trusted_program("mv").unwrap();
+    assert!(mv == Path::new("/usr/bin/mv") || mv == Path::new("/bin/mv"));
+    assert_eq!(find_trusted_program("velopack-not-a-real-program"), None);
+}
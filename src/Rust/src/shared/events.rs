@@ -0,0 +1,219 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::Path,
+    sync::Mutex,
+};
+
+lazy_static! {
+    static ref EVENT_SINK: Mutex<Option<EventSink>> = Mutex::new(None);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ApplyPhase {
+    Locating,
+    Verifying,
+    Extracting,
+    Swapping,
+    Hooks,
+    Restarting,
+}
+
+/// A single machine-readable event, written to the event output as one line of JSON.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ProgressEvent {
+    Phase {
+        phase: ApplyPhase,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        progress: Option<i16>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        detail: Option<String>,
+    },
+    Warning {
+        message: String,
+    },
+    Error {
+        message: String,
+    },
+    Result {
+        success: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        version: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+/// Enables the event output. The target can be `-` for stdout, the path of a unix domain socket
+/// which is already listening, a named pipe / fifo, or a regular file which will be appended to.
+pub fn set_event_output(target: &str) -> Result<()> {
+    let writer: Box<dyn Write + Send> = if target == "-" || target.eq_ignore_ascii_case("stdout") {
+        Box::new(std::io::stdout())
+    } else {
+        open_event_target(Path::new(target))?
+    };
+    *EVENT_SINK.lock().unwrap() = Some(EventSink::new(writer));
+    Ok(())
+}
+
+pub fn is_stdout_target(target: &str) -> bool {
+    target == "-" || target.eq_ignore_ascii_case("stdout")
+}
+
+pub fn get_events_enabled() -> bool {
+    EVENT_SINK.lock().map(|s| s.is_some()).unwrap_or(false)
+}
+
+#[cfg(unix)]
+fn open_event_target(path: &Path) -> Result<Box<dyn Write + Send>> {
+    use std::os::unix::fs::FileTypeExt;
+    if let Ok(meta) = std::fs::metadata(path) {
+        if meta.file_type().is_socket() {
+            return Ok(Box::new(std::os::unix::net::UnixStream::connect(path)?));
+        }
+        if meta.file_type().is_fifo() {
+            return Ok(Box::new(OpenOptions::new().write(true).open(path)?));
+        }
+    }
+    Ok(Box::new(OpenOptions::new().create(true).append(true).open(path)?))
+}
+
+#[cfg(windows)]
+fn open_event_target(path: &Path) -> Result<Box<dyn Write + Send>> {
+    let is_pipe = path.to_string_lossy().starts_with(r"\\.\pipe\");
+    if is_pipe {
+        return Ok(Box::new(OpenOptions::new().write(true).open(path)?));
+    }
+    Ok(Box::new(OpenOptions::new().create(true).append(true).open(path)?))
+}
+
+/// Writes events to the event output, skipping progress updates which have not changed since the last one.
+struct EventSink {
+    writer: Box<dyn Write + Send>,
+    last_progress: Option<i16>,
+}
+
+impl EventSink {
+    fn new(writer: Box<dyn Write + Send>) -> EventSink {
+        EventSink { writer, last_progress: None }
+    }
+
+    fn emit(&mut self, event: &ProgressEvent) -> std::io::Result<()> {
+        if let ProgressEvent::Phase { progress, .. } = event {
+            if progress.is_some() && *progress == self.last_progress {
+                return Ok(());
+            }
+            self.last_progress = *progress;
+        }
+        let line = serde_json::to_string(event)?;
+        writeln!(self.writer, "{}", line)?;
+        self.writer.flush()
+    }
+}
+
+pub fn emit(event: ProgressEvent) {
+    let mut sink = match EVENT_SINK.lock() {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+    if let Some(writer) = sink.as_mut() {
+        // if the reader has gone away there is nothing useful to do, so stop emitting.
+        // this must not log, because warnings are forwarded here by the logger.
+        if writer.emit(&event).is_err() {
+            *sink = None;
+        }
+    }
+}
+
+pub fn phase(phase: ApplyPhase) {
+    set_phase_log_field(phase);
+    emit(ProgressEvent::Phase { phase, progress: None, detail: None });
+}
+
+pub fn phase_detail(phase: ApplyPhase, detail: &str) {
+    set_phase_log_field(phase);
+    emit(ProgressEvent::Phase { phase, progress: None, detail: Some(detail.to_owned()) });
+}
+
+fn set_phase_log_field(phase: ApplyPhase) {
+    if let Ok(serde_json::Value::String(name)) = serde_json::to_value(phase) {
+        crate::logging::set_log_field("phase", &name);
+    }
+}
+
+/// Reports progress (0-100) within a phase, only emitting when the value has changed.
+pub fn phase_progress(phase: ApplyPhase, progress: i16) {
+    emit(ProgressEvent::Phase { phase, progress: Some(progress), detail: None });
+}
+
+pub fn warning(message: &str) {
+    emit(ProgressEvent::Warning { message: message.to_owned() });
+}
+
+pub fn error(message: &str) {
+    emit(ProgressEvent::Error { message: message.to_owned() });
+}
+
+pub fn result_success(version: &semver::Version) {
+    emit(ProgressEvent::Result { success: true, version: Some(version.to_string()), error: None });
+}
+
+pub fn result_failure(error: &str) {
+    emit(ProgressEvent::Result { success: false, version: None, error: Some(error.to_owned()) });
+}
+
+#[test]
+fn test_events_serialize_as_json_lines() {
+    let json = |e: ProgressEvent| serde_json::to_string(&e).unwrap();
+    assert_eq!(json(ProgressEvent::Phase { phase: ApplyPhase::Locating, progress: None, detail: None }), r#"{"type":"phase","phase":"locating"}"#);
+    assert_eq!(
+        json(ProgressEvent::Phase { phase: ApplyPhase::Extracting, progress: Some(42), detail: None }),
+        r#"{"type":"phase","phase":"extracting","progress":42}"#
+    );
+    assert_eq!(
+        json(ProgressEvent::Phase { phase: ApplyPhase::Hooks, progress: None, detail: Some("--veloapp-updated".to_owned()) }),
+        r#"{"type":"phase","phase":"hooks","detail":"--veloapp-updated"}"#
+    );
+    assert_eq!(json(ProgressEvent::Warning { message: "careful".to_owned() }), r#"{"type":"warning","message":"careful"}"#);
+    assert_eq!(
+        json(ProgressEvent::Result { success: true, version: Some("1.2.3".to_owned()), error: None }),
+        r#"{"type":"result","success":true,"version":"1.2.3"}"#
+    );
+    assert_eq!(
+        json(ProgressEvent::Result { success: false, version: None, error: Some("boom".to_owned()) }),
+        r#"{"type":"result","success":false,"error":"boom"}"#
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_events_are_written_to_unix_socket() {
+    use std::io::{BufRead, BufReader};
+    let tmp = tempfile::tempdir().unwrap();
+    let socket_path = tmp.path().join("events.sock");
+    let listener = std::os::unix::net::UnixListener::bind(&socket_path).unwrap();
+
+    let mut sink = EventSink::new(open_event_target(&socket_path).unwrap());
+    sink.emit(&ProgressEvent::Phase { phase: ApplyPhase::Extracting, progress: None, detail: None }).unwrap();
+    for progress in [10, 10, 20] {
+        sink.emit(&ProgressEvent::Phase { phase: ApplyPhase::Extracting, progress: Some(progress), detail: None }).unwrap();
+    }
+    sink.emit(&ProgressEvent::Result { success: true, version: Some("1.0.0".to_owned()), error: None }).unwrap();
+    drop(sink);
+
+    let (stream, _) = listener.accept().unwrap();
+    let lines: Vec<String> = BufReader::new(stream).lines().map(|l| l.unwrap()).collect();
+    assert_eq!(
+        lines,
+        vec![
+            r#"{"type":"phase","phase":"extracting"}"#,
+            r#"{"type":"phase","phase":"extracting","progress":10}"#,
+            r#"{"type":"phase","phase":"extracting","progress":20}"#,
+            r#"{"type":"result","success":true,"version":"1.0.0"}"#,
+        ]
+    );
+}
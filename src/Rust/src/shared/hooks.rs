@@ -0,0 +1,138 @@
+use super::bundle::Manifest;
+use anyhow::{bail, Result};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Incremented whenever a field is removed from, or changes meaning in, the hook payload.
+pub const HOOK_PROTOCOL_VERSION: u32 = 1;
+
+/// The environment variable which the hook payload is passed to the application in.
+pub const HOOK_PAYLOAD_ENV: &str = "VELOPACK_HOOK_PAYLOAD";
+
+/// A hook exits with this code to abort the update (or, from `--veloapp-updated`, to roll it back).
+pub const HOOK_EXIT_ABORT: i32 = 10;
+
+/// A hook exits with this code to ask for the update to be applied at a later time.
+pub const HOOK_EXIT_RETRY_LATER: i32 = 11;
+
+/// Describes the operation a hook is being run for. It is serialized as JSON into `VELOPACK_HOOK_PAYLOAD`,
+/// in addition to the legacy `<hook> <version>` arguments.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HookPayload {
+    pub protocol_version: u32,
+    pub hook: String,
+    pub old_version: Option<String>,
+    pub new_version: String,
+    pub root_path: String,
+    pub channel: String,
+    pub first_run: bool,
+}
+
+impl HookPayload {
+    pub fn new(hook: &str, old_version: Option<&Version>, app: &Manifest, root_path: &Path, first_run: bool) -> HookPayload {
+        HookPayload {
+            protocol_version: HOOK_PROTOCOL_VERSION,
+            hook: hook.to_owned(),
+            old_version: old_version.map(|v| v.to_string()),
+            new_version: app.version.to_string(),
+            root_path: root_path.to_string_lossy().to_string(),
+            channel: app.channel.clone(),
+            first_run,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// Returned when a hook asks for the operation to be retried later, so the caller can tell this apart from a failure.
+#[derive(Debug)]
+pub struct HookRetryLaterError {
+    pub hook: String,
+}
+
+impl std::fmt::Display for HookRetryLaterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The application's {} hook asked for the operation to be retried later.", self.hook)
+    }
+}
+
+impl std::error::Error for HookRetryLaterError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HookResult {
+    /// The hook succeeded, or was not run.
+    Ok,
+    /// The hook asked for the operation to be aborted.
+    Abort,
+    /// The hook asked for the operation to be tried again later.
+    RetryLater,
+    /// The hook crashed, timed out or exited with an unknown code. For compatibility with hooks which
+    /// pre-date the protocol, this does not stop the operation.
+    Failed,
+}
+
+impl HookResult {
+    /// Fails if a hook which runs before an operation asked for it not to go ahead.
+    pub fn ensure_continue(self, hook: &str) -> Result<()> {
+        match self {
+            HookResult::Abort => bail!("The application's {} hook aborted the operation.", hook),
+            HookResult::RetryLater => Err(HookRetryLaterError { hook: hook.to_owned() }.into()),
+            HookResult::Ok | HookResult::Failed => Ok(()),
+        }
+    }
+
+    pub fn from_process_result<T>(result: &Result<T>) -> HookResult {
+        match result {
+            Ok(_) => HookResult::Ok,
+            Err(e) => match e.downcast_ref::<super::ExitCodeError>().map(|e| e.code) {
+                Some(HOOK_EXIT_ABORT) => HookResult::Abort,
+                Some(HOOK_EXIT_RETRY_LATER) => HookResult::RetryLater,
+                _ => HookResult::Failed,
+            },
+        }
+    }
+}
+
+#[test]
+fn test_hook_payload_serializes_to_protocol_fields() {
+    let mut app = Manifest::default();
+    app.version = Version::new(2, 0, 0);
+    app.channel = "beta".to_owned();
+    let payload = HookPayload::new("--veloapp-updated", Some(&Version::new(1, 0, 0)), &app, Path::new("/opt/MyApp"), false);
+    let json: serde_json::Value = serde_json::from_str(&payload.to_json()).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "protocolVersion": 1,
+            "hook": "--veloapp-updated",
+            "oldVersion": "1.0.0",
+            "newVersion": "2.0.0",
+            "rootPath": "/opt/MyApp",
+            "channel": "beta",
+            "firstRun": false,
+        })
+    );
+}
+
+#[test]
+fn test_hook_result_maps_exit_codes() {
+    let exit = |code: i32| -> Result<()> { Err(super::ExitCodeError { code }.into()) };
+    assert_eq!(HookResult::from_process_result(&Ok(())), HookResult::Ok);
+    assert_eq!(HookResult::from_process_result(&exit(HOOK_EXIT_ABORT)), HookResult::Abort);
+    assert_eq!(HookResult::from_process_result(&exit(HOOK_EXIT_RETRY_LATER)), HookResult::RetryLater);
+    assert_eq!(HookResult::from_process_result(&exit(1)), HookResult::Failed);
+    assert_eq!(HookResult::from_process_result::<()>(&Err(anyhow::anyhow!("Process timed out"))), HookResult::Failed);
+}
+
+#[test]
+fn test_hook_result_retry_later_is_distinguishable() {
+    let err = HookResult::RetryLater.ensure_continue("--veloapp-obsolete").unwrap_err();
+    assert_eq!(err.downcast_ref::<HookRetryLaterError>().unwrap().hook, "--veloapp-obsolete");
+    let err = HookResult::Abort.ensure_continue("--veloapp-obsolete").unwrap_err();
+    assert!(err.downcast_ref::<HookRetryLaterError>().is_none());
+    assert!(HookResult::Failed.ensure_continue("--veloapp-obsolete").is_ok());
+}
@@ -0,0 +1,204 @@
+use anyhow::Result;
+#[cfg(unix)]
+use anyhow::bail;
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// A directory which velopack writes to while downloading and applying updates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocationKind {
+    /// Downloaded packages, waiting to be applied.
+    Packages,
+    /// Where new versions are extracted before being swapped in, and where the apply journal and
+    /// retained versions are kept. Should be on the same volume as the application.
+    Staging,
+    /// Short-lived scratch files.
+    Temp,
+}
+
+impl LocationKind {
+    /// The environment variable which overrides this location, it is inherited by the updater when started by the app.
+    pub fn env_var(&self) -> &'static str {
+        match self {
+            LocationKind::Packages => "VELOPACK_PACKAGES_DIR",
+            LocationKind::Staging => "VELOPACK_STAGING_DIR",
+            LocationKind::Temp => "VELOPACK_TEMP_DIR",
+        }
+    }
+}
+
+static LOCATION_OVERRIDES: Mutex<Vec<(LocationKind, PathBuf)>> = Mutex::new(Vec::new());
+
+/// Overrides a location for the rest of this process, eg. from a command line flag. Takes precedence over the environment.
+pub fn set_location_override(kind: LocationKind, path: &Path) {
+    let path = if path.is_relative() { std::env::current_dir().map(|d| d.join(path)).unwrap_or_else(|_| path.to_path_buf()) } else { path.to_path_buf() };
+    info!("Using {:?} location override: {}", kind, path.to_string_lossy());
+    let mut overrides = LOCATION_OVERRIDES.lock().unwrap();
+    overrides.retain(|(k, _)| *k != kind);
+    overrides.push((kind, path));
+}
+
+/// Resolves a location for an application, from (in order) a command line override, the environment, or the
+/// platform default: the install dir on Windows, `$XDG_CACHE_HOME/velopack/<id>` on Linux, and
+/// `~/Library/Caches/velopack/<id>` on macOS. Nothing is created or verified, see `get_location_checked`.
+pub fn get_location(kind: LocationKind, app_id: &str, root_path: &Path) -> PathBuf {
+    resolve_location(kind, app_id, root_path, &LOCATION_OVERRIDES.lock().unwrap())
+}
+
+fn resolve_location(kind: LocationKind, app_id: &str, root_path: &Path, overrides: &[(LocationKind, PathBuf)]) -> PathBuf {
+    if let Some((_, path)) = overrides.iter().find(|(k, _)| *k == kind) {
+        return path.clone();
+    }
+    if let Some(path) = std::env::var_os(kind.env_var()).map(PathBuf::from).filter(|p| p.is_absolute()) {
+        return path;
+    }
+    get_default_location(kind, app_id, root_path)
+}
+
+/// Resolves a location, creating it if needed, and verifies it can not be tampered with by other users.
+pub fn get_location_checked(kind: LocationKind, app_id: &str, root_path: &Path) -> Result<PathBuf> {
+    let path = get_location(kind, app_id, root_path);
+    create_private_dir(&path)?;
+    Ok(path)
+}
+
+#[cfg(target_os = "windows")]
+fn get_default_location(kind: LocationKind, _app_id: &str, root_path: &Path) -> PathBuf {
+    match kind {
+        LocationKind::Packages | LocationKind::Staging => root_path.join("packages"),
+        LocationKind::Temp => std::env::temp_dir(),
+    }
+}
+
+#[cfg(unix)]
+fn get_default_location(kind: LocationKind, app_id: &str, _root_path: &Path) -> PathBuf {
+    let cache_dir = get_app_cache_dir(app_id);
+    match kind {
+        LocationKind::Packages => {
+            let packages_dir = cache_dir.join("packages");
+            // packages used to be downloaded to a shared temp dir, keep finding them there until the new dir is used
+            let legacy_dir = get_legacy_cache_dir(app_id).join("packages");
+            if !packages_dir.exists() && legacy_dir.is_dir() && verify_location(&legacy_dir).is_ok() {
+                return legacy_dir;
+            }
+            packages_dir
+        }
+        LocationKind::Staging => cache_dir,
+        LocationKind::Temp => cache_dir.join("tmp"),
+    }
+}
+
+/// Where velopack keeps state which has to outlive updates and cache cleanups, such as the staging id and selected
+/// channel. This is the packages dir inside the install dir on Windows (the .NET library reads it from there),
+/// `$XDG_STATE_HOME/velopack/<id>` on Linux, and `~/Library/Application Support/velopack/<id>` on macOS.
+/// Unlike the other locations, this can not be overridden.
+#[allow(unused_variables)]
+pub fn get_app_state_dir(app_id: &str, root_path: &Path) -> PathBuf {
+    #[cfg(target_os = "windows")]
+    return root_path.join("packages");
+    #[cfg(unix)]
+    {
+        #[allow(deprecated)]
+        let home = std::env::home_dir().expect("Could not locate user home directory via $HOME or /etc/passwd");
+        #[cfg(target_os = "linux")]
+        let state_home = std::env::var_os("XDG_STATE_HOME").map(PathBuf::from).filter(|p| p.is_absolute()).unwrap_or_else(|| home.join(".local").join("state"));
+        #[cfg(target_os = "macos")]
+        let state_home = home.join("Library").join("Application Support");
+        state_home.join("velopack").join(app_id)
+    }
+}
+
+/// The per-user cache dir of an application, `$XDG_CACHE_HOME/velopack/<id>` (or `~/.cache/velopack/<id>`) on
+/// Linux, and `~/Library/Caches/velopack/<id>` on macOS.
+#[cfg(unix)]
+pub fn get_app_cache_dir(app_id: &str) -> PathBuf {
+    #[allow(deprecated)]
+    let home = std::env::home_dir().expect("Could not locate user home directory via $HOME or /etc/passwd");
+    #[cfg(target_os = "linux")]
+    let cache_home = std::env::var_os("XDG_CACHE_HOME").map(PathBuf::from).filter(|p| p.is_absolute()).unwrap_or_else(|| home.join(".cache"));
+    #[cfg(target_os = "macos")]
+    let cache_home = home.join("Library").join("Caches");
+    cache_home.join("velopack").join(app_id)
+}
+
+/// The shared temp dir which older versions of velopack used for everything.
+#[cfg(unix)]
+pub fn get_legacy_cache_dir(app_id: &str) -> PathBuf {
+    #[cfg(target_os = "linux")]
+    return Path::new("/var/tmp/velopack").join(app_id);
+    #[cfg(target_os = "macos")]
+    return Path::new("/tmp/velopack").join(app_id);
+}
+
+/// Creates a directory which only the current user can access, or verifies an existing one.
+pub fn create_private_dir(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        if !path.exists() {
+            std::fs::DirBuilder::new().recursive(true).mode(0o700).create(path)?;
+        }
+        verify_location(path)
+    }
+    #[cfg(target_os = "windows")]
+    {
+        std::fs::create_dir_all(path)?;
+        Ok(())
+    }
+}
+
+/// Verifies that a directory belongs to the current user and can not be written to by anyone else,
+/// so that packages or staged files in it can not have been planted or swapped by another local user.
+#[cfg(unix)]
+pub fn verify_location(path: &Path) -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    let uid = unsafe { libc::geteuid() };
+    let link_meta = std::fs::symlink_metadata(path)?;
+    if link_meta.file_type().is_symlink() && link_meta.uid() != uid && link_meta.uid() != 0 {
+        bail!("Refusing to use '{}', it is a symlink owned by another user (uid {}).", path.to_string_lossy(), link_meta.uid());
+    }
+    let meta = std::fs::metadata(path)?;
+    if !meta.is_dir() {
+        bail!("Refusing to use '{}', it is not a directory.", path.to_string_lossy());
+    }
+    if meta.uid() != uid {
+        bail!("Refusing to use '{}', it is owned by another user (uid {}).", path.to_string_lossy(), meta.uid());
+    }
+    if meta.mode() & 0o022 != 0 {
+        bail!("Refusing to use '{}', it is writable by other users (mode {:o}).", path.to_string_lossy(), meta.mode() & 0o777);
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+pub fn verify_location(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_location_resolution_and_verification() {
+    use std::os::unix::fs::PermissionsExt;
+    let tmp = tempfile::tempdir().unwrap();
+    let root_path = tmp.path().join("root");
+
+    assert_eq!(resolve_location(LocationKind::Staging, "MyApp", &root_path, &[]), get_app_cache_dir("MyApp"));
+    let override_dir = tmp.path().join("temp-override");
+    let overrides = vec![(LocationKind::Temp, override_dir.clone())];
+    assert_eq!(resolve_location(LocationKind::Temp, "MyApp", &root_path, &overrides), override_dir);
+    assert_eq!(resolve_location(LocationKind::Staging, "MyApp", &root_path, &overrides), get_app_cache_dir("MyApp"));
+
+    let checked = resolve_location(LocationKind::Temp, "MyApp", &root_path, &overrides);
+    create_private_dir(&checked).unwrap();
+    assert_eq!(std::fs::metadata(&checked).unwrap().permissions().mode() & 0o777, 0o700);
+
+    std::fs::set_permissions(&checked, std::fs::Permissions::from_mode(0o777)).unwrap();
+    let err = create_private_dir(&checked).unwrap_err().to_string();
+    assert!(err.contains("writable by other users"), "{}", err);
+    // "/" belongs to root, so it is only rejected when the tests are not run as root
+    if unsafe { libc::geteuid() } != 0 {
+        assert!(verify_location(Path::new("/")).unwrap_err().to_string().contains("owned by another user"));
+    }
+}
@@ -0,0 +1,143 @@
+use super::dialogs;
+use std::path::{Path, PathBuf};
+
+/// A process which has files under a path open, or mapped into memory (eg. shared libraries).
+#[derive(Debug, Clone, PartialEq)]
+pub struct LockingProcess {
+    pub pid: u32,
+    pub name: String,
+    pub paths: Vec<PathBuf>,
+}
+
+impl std::fmt::Display for LockingProcess {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (pid {})", self.name, self.pid)
+    }
+}
+
+/// Lists the processes which are using files under `path`. The updater and the processes it was started
+/// from (eg. the AppImage runtime which mounted it) are not included.
+pub fn find_processes_locking_path(path: &Path) -> Vec<LockingProcess> {
+    let proc_root = Path::new("/proc");
+    let excluded = get_process_ancestors(proc_root, std::process::id());
+    find_processes_locking_path_in(proc_root, path, &excluded)
+}
+
+/// Asks the user to close the processes which are using files under `path`. Returns false if the user
+/// cancelled. When running silent, the processes are only reported, as Linux allows the files to be replaced anyway.
+pub fn close_processes_locking_path(app_name: &str, path: &Path) -> bool {
+    let processes = find_processes_locking_path(path);
+    if processes.is_empty() {
+        return true;
+    }
+
+    let list = processes.iter().map(|p| format!("    {}", p)).collect::<Vec<_>>().join("\n");
+    for process in &processes {
+        let files = process.paths.iter().map(|p| p.to_string_lossy()).collect::<Vec<_>>().join(", ");
+        warn!("Process {} is using files in '{}': {}", process, path.to_string_lossy(), files);
+    }
+    if dialogs::get_silent() {
+        warn!("Continuing while {} process(es) are using '{}', they may need to be restarted.", processes.len(), path.to_string_lossy());
+        return true;
+    }
+
+    let title = format!("{} Update", app_name);
+    let body = format!("The following programs are using files from {}, and should be closed before it is updated:\n\n{}", app_name, list);
+    if !dialogs::show_ok_cancel(&title, Some("Close programs to continue"), &body, Some("Close Programs")) {
+        info!("User cancelled closing the processes using '{}'.", path.to_string_lossy());
+        return false;
+    }
+    super::stop_processes(processes.into_iter().map(|p| p.pid).collect());
+    true
+}
+
+fn find_processes_locking_path_in(proc_root: &Path, path: &Path, excluded: &[u32]) -> Vec<LockingProcess> {
+    let Ok(entries) = std::fs::read_dir(proc_root) else {
+        warn!("Unable to list processes in '{}'.", proc_root.to_string_lossy());
+        return Vec::new();
+    };
+    let mut pids: Vec<u32> = entries.flatten().filter_map(|e| e.file_name().to_str().and_then(|n| n.parse().ok())).filter(|p| !excluded.contains(p)).collect();
+    pids.sort();
+
+    let mut processes = Vec::new();
+    for pid in pids {
+        let proc_dir = proc_root.join(pid.to_string());
+        let mut paths: Vec<PathBuf> = Vec::new();
+        // processes of other users can't be inspected, and are skipped
+        if let Ok(fds) = std::fs::read_dir(proc_dir.join("fd")) {
+            paths.extend(fds.flatten().filter_map(|fd| std::fs::read_link(fd.path()).ok()));
+        }
+        if let Ok(maps) = std::fs::read_to_string(proc_dir.join("maps")) {
+            paths.extend(maps.lines().filter_map(parse_maps_path));
+        }
+        paths.retain(|p| p.starts_with(path));
+        paths.sort();
+        paths.dedup();
+        if !paths.is_empty() {
+            let name = std::fs::read_to_string(proc_dir.join("comm")).map(|c| c.trim().to_owned()).unwrap_or_else(|_| pid.to_string());
+            processes.push(LockingProcess { pid, name, paths });
+        }
+    }
+    processes
+}
+
+/// The file of a line in /proc/<pid>/maps, which comes after the address, perms, offset, device and inode columns.
+fn parse_maps_path(line: &str) -> Option<PathBuf> {
+    let mut rest = line;
+    for _ in 0..5 {
+        rest = rest.trim_start().split_once(char::is_whitespace)?.1;
+    }
+    let file = rest.trim();
+    if !file.starts_with('/') {
+        return None;
+    }
+    Some(PathBuf::from(file.strip_suffix(" (deleted)").unwrap_or(file)))
+}
+
+/// The process and each of its parents, up to (but not including) init.
+pub(crate) fn get_process_ancestors(proc_root: &Path, pid: u32) -> Vec<u32> {
+    let mut ancestors = vec![pid];
+    let mut current = pid;
+    while let Some(parent) = get_parent_pid(proc_root, current).filter(|p| *p > 1 && !ancestors.contains(p)) {
+        ancestors.push(parent);
+        current = parent;
+    }
+    ancestors
+}
+
+fn get_parent_pid(proc_root: &Path, pid: u32) -> Option<u32> {
+    // the name in /proc/<pid>/stat may contain spaces or brackets, so the fields are read after the last ')'
+    let stat = std::fs::read_to_string(proc_root.join(pid.to_string()).join("stat")).ok()?;
+    stat.rsplit_once(')')?.1.split_whitespace().nth(1)?.parse().ok()
+}
+
+#[test]
+fn test_find_processes_locking_path() {
+    use std::os::unix::fs::symlink;
+    let tmp = tempfile::tempdir().unwrap();
+    let proc_root = tmp.path().join("proc");
+    let fake_process = |pid: u32, ppid: u32, name: &str, fds: &[&str], maps: &str| {
+        let dir = proc_root.join(pid.to_string());
+        std::fs::create_dir_all(dir.join("fd")).unwrap();
+        for (i, fd) in fds.iter().enumerate() {
+            symlink(fd, dir.join("fd").join(i.to_string())).unwrap();
+        }
+        std::fs::write(dir.join("maps"), maps).unwrap();
+        std::fs::write(dir.join("comm"), format!("{}\n", name)).unwrap();
+        std::fs::write(dir.join("stat"), format!("{} ({}) S {} 1 1", pid, name, ppid)).unwrap();
+    };
+
+    let maps = "7f0000000000-7f0000001000 r-xp 00000000 08:01 1234                       /opt/My App/lib/libfoo.so (deleted)\n\
+                7f0000002000-7f0000003000 rw-p 00000000 00:00 0                          [heap]\n";
+    fake_process(1, 0, "systemd", &["/dev/null"], "");
+    fake_process(10, 1, "runtime", &["/opt/My App/MyApp.AppImage"], "");
+    fake_process(11, 10, "UpdateNix", &["/opt/My App/MyApp.AppImage"], "");
+    fake_process(20, 1, "my app", &["/opt/My App/data.db", "/home/user/notes.txt"], maps);
+    fake_process(21, 1, "bash", &["/opt/My App Other/file"], "");
+
+    assert_eq!(get_process_ancestors(&proc_root, 11), vec![11, 10]);
+    let processes = find_processes_locking_path_in(&proc_root, Path::new("/opt/My App"), &get_process_ancestors(&proc_root, 11));
+    assert_eq!(processes.len(), 1);
+    assert_eq!(processes[0].to_string(), "my app (pid 20)");
+    assert_eq!(processes[0].paths, vec![PathBuf::from("/opt/My App/data.db"), PathBuf::from("/opt/My App/lib/libfoo.so")]);
+}
@@ -1,5 +1,10 @@
 pub mod bundle;
 pub mod download;
+pub mod events;
+pub mod hooks;
+
+#[cfg(target_os = "linux")]
+pub mod elevation;
 
 mod dialogs_const;
 mod dialogs_common;
@@ -24,11 +29,28 @@ pub mod dialogs {
 mod util_common;
 pub use util_common::*;
 
+mod staging;
+pub use staging::*;
+
+mod channel;
+pub use channel::*;
+
+mod operation_lock;
+pub use operation_lock::*;
+
+mod locations;
+pub use locations::*;
+
 #[cfg(target_os = "windows")]
 mod util_windows;
 #[cfg(target_os = "windows")]
 pub use util_windows::*;
 
+#[cfg(unix)]
+mod util_unix;
+#[cfg(unix)]
+pub use util_unix::*;
+
 #[cfg(target_os = "macos")]
 mod util_osx;
 #[cfg(target_os = "macos")]
@@ -38,3 +60,13 @@ pub use util_osx::*;
 mod util_linux;
 #[cfg(target_os = "linux")]
 pub use util_linux::*;
+
+#[cfg(target_os = "linux")]
+mod locksmith_linux;
+#[cfg(target_os = "linux")]
+pub use locksmith_linux::*;
+
+#[cfg(target_os = "linux")]
+mod shortcuts_linux;
+#[cfg(target_os = "linux")]
+pub use shortcuts_linux::*;
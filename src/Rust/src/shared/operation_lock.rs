@@ -0,0 +1,110 @@
+use super::bundle::Manifest;
+use anyhow::Result;
+#[cfg(unix)]
+use anyhow::{anyhow, bail};
+#[cfg(unix)]
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+#[cfg(unix)]
+const LOCKED_MESSAGE: &str = "Another installer or updater for this application is running, quit that process and try again.";
+
+/// Held for the duration of an operation which modifies an installed application. Released when dropped,
+/// or by the OS if the process exits.
+pub struct OperationLock {
+    #[cfg(target_os = "windows")]
+    _mutex: crate::windows::MutexDropGuard,
+    #[cfg(unix)]
+    _file: File,
+}
+
+/// Prevents two installers or updaters from modifying the same application at the same time.
+#[cfg(target_os = "windows")]
+pub fn acquire_operation_lock(app: &Manifest) -> Result<OperationLock> {
+    Ok(OperationLock { _mutex: crate::windows::create_global_mutex(app)? })
+}
+
+/// Prevents two installers or updaters from modifying the same application at the same time.
+#[cfg(unix)]
+pub fn acquire_operation_lock(app: &Manifest) -> Result<OperationLock> {
+    acquire_lock_file(&get_lock_file_path(&app.id)?)
+}
+
+/// The per-app lock file, in the user runtime dir (`$XDG_RUNTIME_DIR` on Linux) or the velopack cache dir. Both
+/// are private to the user, so nobody else can create the lock file first and block (or hijack) the operation.
+#[cfg(unix)]
+pub fn get_lock_file_path(app_id: &str) -> Result<PathBuf> {
+    #[cfg(target_os = "linux")]
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from);
+    #[cfg(not(target_os = "linux"))]
+    let runtime_dir = None;
+    // the lock is kept next to the app cache dir rather than in it, so it survives the cache being removed
+    let cache_root = super::get_app_cache_dir(app_id).parent().map(Path::to_path_buf).ok_or_else(|| anyhow!("Unable to locate the cache dir."))?;
+    resolve_lock_file_path(app_id, runtime_dir, &cache_root)
+}
+
+#[cfg(unix)]
+fn resolve_lock_file_path(app_id: &str, runtime_dir: Option<PathBuf>, cache_root: &Path) -> Result<PathBuf> {
+    let file_name = format!("velopack-{}.lock", app_id);
+    if let Some(runtime_dir) = runtime_dir.filter(|p| p.is_absolute() && p.is_dir()) {
+        match super::verify_location(&runtime_dir) {
+            Ok(()) => return Ok(runtime_dir.join(file_name)),
+            Err(e) => warn!("Not using the runtime dir for the lock file ({}).", e),
+        }
+    }
+    super::create_private_dir(cache_root)?;
+    Ok(cache_root.join(file_name))
+}
+
+#[cfg(unix)]
+fn acquire_lock_file(path: &Path) -> Result<OperationLock> {
+    use std::os::unix::{fs::OpenOptionsExt, io::AsRawFd};
+    info!("Attempting to lock: '{}'", path.to_string_lossy());
+    // the lock file is never written to, it is left behind so it can't be deleted while another process holds it
+    let file = std::fs::OpenOptions::new().read(true).write(true).create(true).mode(0o600).custom_flags(libc::O_NOFOLLOW).open(path)?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        let err = std::io::Error::last_os_error();
+        if err.kind() == std::io::ErrorKind::WouldBlock {
+            bail!(LOCKED_MESSAGE);
+        }
+        bail!("Unable to lock '{}': {}", path.to_string_lossy(), err);
+    }
+    Ok(OperationLock { _file: file })
+}
+
+#[test]
+#[cfg(unix)]
+fn test_operation_lock_is_exclusive_until_dropped() {
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("velopack-MyApp.lock");
+    let lock = acquire_lock_file(&path).unwrap();
+    let err = acquire_lock_file(&path).err().unwrap();
+    assert_eq!(err.to_string(), LOCKED_MESSAGE);
+    drop(lock);
+    acquire_lock_file(&path).unwrap();
+}
+
+#[test]
+#[cfg(unix)]
+fn test_lock_file_is_in_a_private_dir() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    let tmp = tempfile::tempdir().unwrap();
+    let runtime_dir = tmp.path().join("runtime");
+    let cache_root = tmp.path().join("cache");
+
+    let path = resolve_lock_file_path("MyApp", None, &cache_root).unwrap();
+    assert_eq!(path, cache_root.join("velopack-MyApp.lock"));
+    let metadata = std::fs::metadata(&cache_root).unwrap();
+    assert_eq!(metadata.uid(), unsafe { libc::geteuid() });
+    assert_eq!(metadata.mode() & 0o077, 0);
+
+    std::fs::create_dir(&runtime_dir).unwrap();
+    std::fs::set_permissions(&runtime_dir, std::fs::Permissions::from_mode(0o700)).unwrap();
+    assert_eq!(resolve_lock_file_path("MyApp", Some(runtime_dir.clone()), &cache_root).unwrap(), runtime_dir.join("velopack-MyApp.lock"));
+
+    // a runtime dir which others can write to is not trusted
+    std::fs::set_permissions(&runtime_dir, std::fs::Permissions::from_mode(0o777)).unwrap();
+    assert_eq!(resolve_lock_file_path("MyApp", Some(runtime_dir), &cache_root).unwrap(), path);
+}
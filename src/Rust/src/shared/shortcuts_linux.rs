@@ -0,0 +1,261 @@
+use super::bundle::Manifest;
+use anyhow::Result;
+use std::{
+    fs,
+    os::unix::fs::DirBuilderExt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// The hicolor sizes which are listed in the theme index, icons in other size dirs are ignored by desktops.
+const HICOLOR_SIZES: [u32; 9] = [16, 22, 24, 32, 48, 64, 128, 256, 512];
+
+/// An application icon, and the extension (png or svg) it should be installed with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DesktopIcon {
+    pub bytes: Vec<u8>,
+    pub extension: String,
+}
+
+/// The freedesktop user data dir, `$XDG_DATA_HOME` or `~/.local/share`.
+pub fn get_xdg_data_home() -> PathBuf {
+    if let Some(dir) = std::env::var_os("XDG_DATA_HOME").map(PathBuf::from).filter(|p| p.is_absolute()) {
+        return dir;
+    }
+    #[allow(deprecated)]
+    let home = std::env::home_dir().expect("Could not locate user home directory via $HOME or /etc/passwd");
+    home.join(".local").join("share")
+}
+
+fn get_desktop_entry_name(app_id: &str) -> String {
+    format!("velopack-{}", app_id)
+}
+
+/// Creates (or replaces) the menu entry and icon for an installed application. The icon and categories
+/// are read from the AppImage, or from the install dir if the application is not an AppImage.
+pub fn create_desktop_entry(root_path: &Path, app: &Manifest) -> Result<PathBuf> {
+    let (icon, categories) = read_desktop_metadata(root_path, app);
+    create_desktop_entry_in(&get_xdg_data_home(), root_path, app, icon.as_ref(), categories.as_deref())
+}
+
+/// Removes any existing menu entries for the application (eg. pointing to where the AppImage used to be)
+/// and creates a new one for the current location.
+pub fn refresh_desktop_entry(root_path: &Path, app: &Manifest) -> Result<PathBuf> {
+    remove_desktop_entries_in(&get_xdg_data_home(), &app.id)?;
+    create_desktop_entry(root_path, app)
+}
+
+/// Creates the menu entry for an application if it has none, or if it points somewhere else. An AppImage is usually
+/// downloaded and run directly, so this gives it a menu entry without waiting for its first update.
+pub fn ensure_desktop_entry(root_path: &Path, app: &Manifest) -> Result<Option<PathBuf>> {
+    ensure_desktop_entry_in(&get_xdg_data_home(), root_path, app)
+}
+
+pub fn ensure_desktop_entry_in(data_home: &Path, root_path: &Path, app: &Manifest) -> Result<Option<PathBuf>> {
+    let entry_path = data_home.join("applications").join(format!("{}.desktop", get_desktop_entry_name(&app.id)));
+    let exe_path = if root_path.is_dir() { root_path.join(&app.main_exe) } else { root_path.to_path_buf() };
+    let exec_line = format!("Exec={} %U", escape_value(&quote_exec_arg(&exe_path.to_string_lossy())));
+    if fs::read_to_string(&entry_path).is_ok_and(|s| s.lines().any(|l| l == exec_line)) {
+        return Ok(None);
+    }
+    remove_desktop_entries_in(data_home, &app.id)?;
+    let (icon, categories) = read_desktop_metadata(root_path, app);
+    create_desktop_entry_in(data_home, root_path, app, icon.as_ref(), categories.as_deref()).map(Some)
+}
+
+pub fn remove_desktop_entries(app_id: &str) -> Result<()> {
+    remove_desktop_entries_in(&get_xdg_data_home(), app_id)
+}
+
+pub fn create_desktop_entry_in(data_home: &Path, root_path: &Path, app: &Manifest, icon: Option<&DesktopIcon>, categories: Option<&str>) -> Result<PathBuf> {
+    let entry_name = get_desktop_entry_name(&app.id);
+    let exe_path = if root_path.is_dir() { root_path.join(&app.main_exe) } else { root_path.to_path_buf() };
+    let title = if app.title.is_empty() { &app.id } else { &app.title };
+
+    let mut entry = String::from("[Desktop Entry]\nType=Application\n");
+    entry += &format!("Name={}\n", escape_value(title));
+    if !app.description.is_empty() {
+        entry += &format!("Comment={}\n", escape_value(&app.description));
+    }
+    entry += &format!("Exec={} %U\n", escape_value(&quote_exec_arg(&exe_path.to_string_lossy())));
+    if let Some(icon) = icon {
+        let icon_path = get_icon_path(data_home, &entry_name, icon);
+        info!("Writing application icon: {}", icon_path.to_string_lossy());
+        fs::create_dir_all(icon_path.parent().unwrap())?;
+        fs::write(&icon_path, &icon.bytes)?;
+        entry += &format!("Icon={}\n", entry_name);
+    }
+    entry += "Terminal=false\n";
+    entry += &format!("Categories={};\n", categories.unwrap_or("Utility").trim_end_matches(';'));
+    entry += &format!("StartupWMClass={}\n", app.id);
+    entry += &format!("X-Velopack-Id={}\n", app.id);
+
+    let entry_path = data_home.join("applications").join(format!("{}.desktop", entry_name));
+    info!("Writing desktop entry: {}", entry_path.to_string_lossy());
+    fs::create_dir_all(entry_path.parent().unwrap())?;
+    fs::write(&entry_path, entry)?;
+    Ok(entry_path)
+}
+
+/// Removes the menu entries and icons which were created for an application.
+pub fn remove_desktop_entries_in(data_home: &Path, app_id: &str) -> Result<()> {
+    let id_line = format!("X-Velopack-Id={}", app_id);
+    let applications_dir = data_home.join("applications");
+    if let Ok(entries) = fs::read_dir(&applications_dir) {
+        for path in entries.flatten().map(|e| e.path()).filter(|p| p.extension().is_some_and(|e| e == "desktop")) {
+            let is_ours = fs::read_to_string(&path).map(|s| s.lines().any(|l| l.trim() == id_line)).unwrap_or(false);
+            if is_ours {
+                info!("Removing desktop entry: {}", path.to_string_lossy());
+                fs::remove_file(&path)?;
+            }
+        }
+    }
+
+    let icon_glob = format!("{}/icons/hicolor/*/apps/{}.*", data_home.to_string_lossy(), get_desktop_entry_name(app_id));
+    for icon_path in glob::glob(&icon_glob)?.flatten() {
+        info!("Removing application icon: {}", icon_path.to_string_lossy());
+        fs::remove_file(&icon_path)?;
+    }
+    Ok(())
+}
+
+fn get_icon_path(data_home: &Path, entry_name: &str, icon: &DesktopIcon) -> PathBuf {
+    let size_dir = match icon.extension.as_str() {
+        "svg" => "scalable".to_owned(),
+        _ => {
+            let size = get_png_width(&icon.bytes).unwrap_or(256);
+            // desktops only look in the sizes listed by the theme, so use the closest one which is not larger
+            let size = HICOLOR_SIZES.iter().rev().find(|s| **s <= size).copied().unwrap_or(HICOLOR_SIZES[0]);
+            format!("{}x{}", size, size)
+        }
+    };
+    data_home.join("icons").join("hicolor").join(size_dir).join("apps").join(format!("{}.{}", entry_name, icon.extension))
+}
+
+fn get_png_width(bytes: &[u8]) -> Option<u32> {
+    const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+    if bytes.len() < 24 || bytes[..8] != PNG_SIGNATURE {
+        return None;
+    }
+    Some(u32::from_be_bytes([bytes[16], bytes[17], bytes[18], bytes[19]]))
+}
+
+/// Quotes an Exec argument, as described in the desktop entry spec.
+fn quote_exec_arg(arg: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        match c {
+            '"' | '`' | '$' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '%' => quoted.push_str("%%"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Escapes a value in a desktop entry. This is applied after `quote_exec_arg`, so backslashes end up doubled.
+fn escape_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\n', "\\n").replace('\t', "\\t")
+}
+
+/// Reads the icon and categories which were packed into the application. An AppImage is not mounted, the
+/// files are extracted with its own `--appimage-extract`, which does not run any application code.
+fn read_desktop_metadata(root_path: &Path, app: &Manifest) -> (Option<DesktopIcon>, Option<String>) {
+    if root_path.is_dir() {
+        return read_desktop_metadata_from_dir(root_path, app);
+    }
+
+    let temp_dir = super::get_location(super::LocationKind::Temp, &app.id, root_path);
+    let extract_dir = temp_dir.join(format!("velopack_{}_{}", app.id, super::random_string(8)));
+    // the dir is private, so the extracted files can't be swapped out by other users of a shared temp dir
+    if let Err(e) = super::create_private_dir(&temp_dir).and_then(|_| fs::DirBuilder::new().mode(0o700).create(&extract_dir).map_err(Into::into)) {
+        warn!("Failed to create temp dir to extract the application icon ({}).", e);
+        return (None, None);
+    }
+    let pattern = format!("{}.*", app.id);
+    let result = super::run_process_and_wait(root_path, vec!["--appimage-extract", &pattern], &extract_dir, &[], Some(Duration::from_secs(30)));
+    let metadata = match result {
+        Ok(_) => read_desktop_metadata_from_dir(&extract_dir.join("squashfs-root"), app),
+        Err(e) => {
+            warn!("Failed to extract the application icon from the AppImage ({}).", e);
+            (None, None)
+        }
+    };
+    let _ = remove_dir_all::remove_dir_all(&extract_dir);
+    metadata
+}
+
+fn read_desktop_metadata_from_dir(dir: &Path, app: &Manifest) -> (Option<DesktopIcon>, Option<String>) {
+    let icon = ["png", "svg"]
+        .iter()
+        .find_map(|ext| fs::read(dir.join(format!("{}.{}", app.id, ext))).ok().map(|bytes| DesktopIcon { bytes, extension: ext.to_string() }));
+    let categories = fs::read_to_string(dir.join(format!("{}.desktop", app.id)))
+        .ok()
+        .and_then(|s| s.lines().find_map(|l| l.strip_prefix("Categories=").map(|c| c.trim().to_owned())));
+    (icon, categories)
+}
+
+#[test]
+fn test_desktop_entry_is_created_refreshed_and_removed() {
+    let tmp = tempfile::tempdir().unwrap();
+    let data_home = tmp.path().join("share");
+    let mut app = Manifest::default();
+    app.id = "MyApp".to_owned();
+    app.title = "My App".to_owned();
+
+    let mut png = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 13, b'I', b'H', b'D', b'R'];
+    png.extend(300u32.to_be_bytes());
+    png.extend(300u32.to_be_bytes());
+    let icon = DesktopIcon { bytes: png, extension: "png".to_owned() };
+
+    let old_path = tmp.path().join("Old Dir").join("MyApp.AppImage");
+    let entry_path = create_desktop_entry_in(&data_home, &old_path, &app, Some(&icon), Some("Development;")).unwrap();
+    assert_eq!(entry_path, data_home.join("applications").join("velopack-MyApp.desktop"));
+    let entry = fs::read_to_string(&entry_path).unwrap();
+    assert!(entry.contains("Name=My App\n"));
+    assert!(entry.contains(&format!("Exec=\"{}\" %U\n", old_path.to_string_lossy())));
+    assert!(entry.contains("Icon=velopack-MyApp\n"));
+    assert!(entry.contains("Categories=Development;\n"));
+    let icon_path = data_home.join("icons/hicolor/256x256/apps/velopack-MyApp.png");
+    assert_eq!(fs::read(&icon_path).unwrap(), icon.bytes);
+
+    // an entry which is not ours is left alone
+    fs::write(data_home.join("applications").join("other.desktop"), "[Desktop Entry]\nX-Velopack-Id=OtherApp\n").unwrap();
+
+    let new_path = tmp.path().join("Apps").join("My$App.AppImage");
+    remove_desktop_entries_in(&data_home, &app.id).unwrap();
+    create_desktop_entry_in(&data_home, &new_path, &app, None, None).unwrap();
+    let entry = fs::read_to_string(&entry_path).unwrap();
+    assert!(entry.contains(&format!("Exec=\"{}/My\\\\$App.AppImage\" %U\n", tmp.path().join("Apps").to_string_lossy())));
+    assert!(entry.contains("Categories=Utility;\n"));
+    assert!(!icon_path.exists());
+
+    remove_desktop_entries_in(&data_home, &app.id).unwrap();
+    assert!(!entry_path.exists());
+    assert!(data_home.join("applications").join("other.desktop").exists());
+}
+
+#[test]
+fn test_desktop_entry_is_ensured_when_missing_or_moved() {
+    let tmp = tempfile::tempdir().unwrap();
+    let data_home = tmp.path().join("share");
+    let install_dir = tmp.path().join("MyApp");
+    fs::create_dir_all(&install_dir).unwrap();
+    fs::write(install_dir.join("MyApp.desktop"), "[Desktop Entry]\nCategories=Game;\n").unwrap();
+    let mut app = Manifest::default();
+    app.id = "MyApp".to_owned();
+    app.main_exe = "MyApp".to_owned();
+
+    let entry_path = ensure_desktop_entry_in(&data_home, &install_dir, &app).unwrap().unwrap();
+    assert!(fs::read_to_string(&entry_path).unwrap().contains("Categories=Game;\n"));
+    assert_eq!(ensure_desktop_entry_in(&data_home, &install_dir, &app).unwrap(), None);
+
+    let moved_dir = tmp.path().join("Moved");
+    fs::rename(&install_dir, &moved_dir).unwrap();
+    assert_eq!(ensure_desktop_entry_in(&data_home, &moved_dir, &app).unwrap(), Some(entry_path.clone()));
+    assert!(fs::read_to_string(&entry_path).unwrap().contains(&moved_dir.join("MyApp").to_string_lossy().to_string()));
+}
@@ -0,0 +1,173 @@
+use anyhow::{anyhow, Result};
+use rand::RngCore;
+use std::{fs, path::Path};
+
+const STAGED_USER_ID_FILE: &str = ".betaId";
+
+/// Reads the persistent staging id for this installation from the app's state directory, creating a new random
+/// one if it does not exist yet. An id left in the packages directory by an older version is carried over, so the
+/// install stays in the same bucket. The file format is shared with the .NET library.
+pub fn get_or_create_staged_user_id<P: AsRef<Path>, L: AsRef<Path>>(state_dir: P, legacy_dir: L) -> Option<String> {
+    let state_dir = state_dir.as_ref();
+    let id_path = state_dir.join(STAGED_USER_ID_FILE);
+    let legacy_path = legacy_dir.as_ref().join(STAGED_USER_ID_FILE);
+
+    let existing_path = if id_path.exists() { Some(&id_path) } else if legacy_path.exists() { Some(&legacy_path) } else { None };
+    let mut id = None;
+    if let Some(existing_path) = existing_path {
+        match fs::read_to_string(existing_path).map_err(|e| anyhow!(e)).and_then(|s| parse_staged_user_id(&s)) {
+            Ok(existing) if existing_path == &id_path => {
+                info!("Loaded existing staging userId: {}", existing);
+                return Some(existing);
+            }
+            Ok(existing) => {
+                info!("Moving staging userId {} from '{}'.", existing, legacy_path.to_string_lossy());
+                id = Some(existing);
+            }
+            Err(e) => debug!("Couldn't read staging userId, creating a new one ({}).", e),
+        }
+    } else {
+        warn!("No userId could be found at '{}', creating a new one.", id_path.to_string_lossy());
+    }
+
+    let is_new = id.is_none();
+    let id = id.unwrap_or_else(generate_staged_user_id);
+    let write_result: Result<()> = (|| {
+        super::create_private_dir(state_dir)?;
+        fs::write(&id_path, &id)?;
+        Ok(())
+    })();
+
+    match write_result {
+        Ok(()) => {
+            if is_new {
+                info!("Generated new staging userId: {}", id);
+            }
+            Some(id)
+        }
+        Err(e) => {
+            warn!("Couldn't write out staging userId ({}).", e);
+            // an id which could not be persisted would put this install in a different bucket on every check
+            if is_new {
+                None
+            } else {
+                Some(id)
+            }
+        }
+    }
+}
+
+/// Returns true if a user falls inside the staged rollout group of a release. A release
+/// without a staging percentage (or staged to 100%) is available to everyone, but a partially
+/// staged release is never offered to an installation which has no staging id.
+pub fn is_staging_match(staging_percentage: Option<f32>, user_id: Option<&str>) -> bool {
+    let percentage = match staging_percentage {
+        Some(p) if p >= 100.0 => return true,
+        Some(p) => p,
+        None => return true,
+    };
+    let bucket = match user_id.and_then(|id| get_staging_bucket(id).ok()) {
+        Some(b) => b,
+        None => return false,
+    };
+    bucket < (percentage as f64 / 100.0)
+}
+
+/// Maps a staging id onto the range [0, 1). This matches `ReleaseEntry.IsStagingMatch` in the
+/// .NET library, which reads the last four bytes of the guid as a little-endian u32.
+pub fn get_staging_bucket(user_id: &str) -> Result<f64> {
+    let id = parse_staged_user_id(user_id)?;
+    let hex = id.replace('-', "");
+    let tail = &hex[24..32];
+    let mut bytes = [0u8; 4];
+    for i in 0..4 {
+        bytes[i] = u8::from_str_radix(&tail[i * 2..i * 2 + 2], 16)?;
+    }
+    let val = u32::from_le_bytes(bytes);
+    Ok(val as f64 / u32::MAX as f64)
+}
+
+fn parse_staged_user_id(contents: &str) -> Result<String> {
+    let id = contents.trim().trim_start_matches('\u{feff}').trim_matches(|c| c == '{' || c == '}').to_ascii_lowercase();
+    let is_valid = id.len() == 36
+        && id.char_indices().all(|(i, c)| if i == 8 || i == 13 || i == 18 || i == 23 { c == '-' } else { c.is_ascii_hexdigit() });
+    if !is_valid {
+        return Err(anyhow!("Invalid staging userId: '{}'", contents.trim()));
+    }
+    Ok(id)
+}
+
+fn generate_staged_user_id() -> String {
+    let mut b = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut b);
+    b[6] = (b[6] & 0x0f) | 0x40; // version 4
+    b[8] = (b[8] & 0x3f) | 0x80; // RFC 4122 variant
+    format!(
+        "{:02x}{:02x}{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+        b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7], b[8], b[9], b[10], b[11], b[12], b[13], b[14], b[15]
+    )
+}
+
+#[test]
+fn test_staging_bucket_is_deterministic() {
+    // the last 4 bytes are read as a little-endian u32, like System.Guid.ToByteArray() in .NET
+    assert_eq!(get_staging_bucket("00000000-0000-0000-0000-000000000000").unwrap(), 0.0);
+    assert_eq!(get_staging_bucket("00000000-0000-0000-0000-0000ffffffff").unwrap(), 1.0);
+    assert_eq!(get_staging_bucket("ffffffff-ffff-ffff-ffff-ffff00000000").unwrap(), 0.0);
+    let bucket = get_staging_bucket("6f0b3e2a-1c4d-4e5f-8a9b-0c1d00000080").unwrap();
+    assert!((bucket - 0.5).abs() < 0.0001);
+    assert_eq!(get_staging_bucket("{6F0B3E2A-1C4D-4E5F-8A9B-0C1D00000080}").unwrap(), bucket);
+    assert!(get_staging_bucket("not-a-guid").is_err());
+}
+
+#[test]
+fn test_staging_match_honors_percentage() {
+    let low = Some("00000000-0000-0000-0000-00000000000a"); // bucket ~0.04
+    let mid = Some("00000000-0000-0000-0000-000000000080"); // bucket ~0.5
+    let high = Some("00000000-0000-0000-0000-0000fffffffe"); // bucket ~0.99
+
+    assert!(is_staging_match(None, None));
+    assert!(is_staging_match(None, high));
+    assert!(is_staging_match(Some(100.0), None));
+    assert!(is_staging_match(Some(150.0), Some("garbage")));
+    assert!(!is_staging_match(Some(99.0), None));
+    assert!(!is_staging_match(Some(10.0), Some("garbage")));
+
+    assert!(is_staging_match(Some(10.0), low));
+    assert!(!is_staging_match(Some(10.0), mid));
+    assert!(!is_staging_match(Some(10.0), high));
+
+    assert!(is_staging_match(Some(60.0), mid));
+    assert!(!is_staging_match(Some(40.0), mid));
+    assert!(is_staging_match(Some(100.0), high));
+    assert!(!is_staging_match(Some(0.0), low));
+}
+
+#[test]
+fn test_staged_user_id_is_persisted() {
+    let tmp = tempfile::tempdir().unwrap();
+    let state_dir = tmp.path().join("state");
+    let packages_dir = tmp.path().join("packages");
+    let first = get_or_create_staged_user_id(&state_dir, &packages_dir).unwrap();
+    assert!(parse_staged_user_id(&first).is_ok());
+    let second = get_or_create_staged_user_id(&state_dir, &packages_dir).unwrap();
+    assert_eq!(first, second);
+    assert_eq!(fs::read_to_string(state_dir.join(STAGED_USER_ID_FILE)).unwrap(), first);
+    assert!(!packages_dir.join(STAGED_USER_ID_FILE).exists());
+
+    fs::write(state_dir.join(STAGED_USER_ID_FILE), "corrupt").unwrap();
+    let third = get_or_create_staged_user_id(&state_dir, &packages_dir).unwrap();
+    assert_ne!(first, third);
+}
+
+#[test]
+fn test_staged_user_id_is_moved_from_packages_dir() {
+    let tmp = tempfile::tempdir().unwrap();
+    let state_dir = tmp.path().join("state");
+    let packages_dir = tmp.path().join("packages");
+    fs::create_dir_all(&packages_dir).unwrap();
+    fs::write(packages_dir.join(STAGED_USER_ID_FILE), "{6F0B3E2A-1C4D-4E5F-8A9B-0C1D00000080}").unwrap();
+    let id = get_or_create_staged_user_id(&state_dir, &packages_dir).unwrap();
+    assert_eq!(id, "6f0b3e2a-1c4d-4e5f-8a9b-0c1d00000080");
+    assert_eq!(fs::read_to_string(state_dir.join(STAGED_USER_ID_FILE)).unwrap(), id);
+}
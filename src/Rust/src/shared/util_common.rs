@@ -75,6 +75,20 @@ pub fn random_string(len: usize) -> String {
     Alphanumeric.sample_string(&mut rand::thread_rng(), len)
 }
 
+/// Returned by the process helpers when a process exits with a non-zero exit code.
+#[derive(Debug)]
+pub struct ExitCodeError {
+    pub code: i32,
+}
+
+impl std::fmt::Display for ExitCodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Process exited with non-zero exit code: {}", self.code)
+    }
+}
+
+impl std::error::Error for ExitCodeError {}
+
 pub fn is_error_permission_denied(e: &anyhow::Error) -> bool {
     if let Some(io_err) = e.downcast_ref::<std::io::Error>() {
         return io_err.kind() == std::io::ErrorKind::PermissionDenied;
@@ -1,7 +1,12 @@
 use super::bundle::Manifest;
 use crate::shared::bundle;
 use anyhow::{anyhow, bail, Result};
-use std::{path::Path, path::PathBuf, process::Command as Process, time::Duration};
+use std::{
+    path::Path,
+    path::PathBuf,
+    process::{Child, Command as Process},
+    time::Duration,
+};
 
 pub fn wait_for_pid_to_exit(pid: u32, ms_to_wait: u32) -> Result<()> {
     info!("Waiting {}ms for process ({}) to exit.", ms_to_wait, pid);
@@ -24,29 +29,137 @@ pub fn wait_for_parent_to_exit(ms_to_wait: u32) -> Result<()> {
     Ok(())
 }
 
-pub fn start_package<P: AsRef<Path>>(_app: &Manifest, root_dir: P, exe_args: Option<Vec<&str>>, set_env: Option<&str>) -> Result<()> {
-    let mut cmd = Process::new(root_dir.as_ref());
+/// Stops every process which belongs to the application: processes running an executable from (or with a
+/// working dir inside) the install dir, and processes running from the mount point of the AppImage.
+/// Each process is sent SIGTERM, and any which are still running after a few seconds are sent SIGKILL. The updater
+/// and the processes it was started from (eg. the AppImage runtime, or the application itself) are never stopped.
+pub fn force_stop_package<P: AsRef<Path>>(root_dir: P) -> Result<()> {
+    let root_dir = root_dir.as_ref();
+    let proc_root = Path::new("/proc");
+    let excluded = super::get_process_ancestors(proc_root, std::process::id());
+    let own_mount_dir = std::env::current_exe().ok().and_then(|exe| get_mount_dir(&exe));
+    let pids = find_package_processes(proc_root, root_dir, &excluded, own_mount_dir.as_deref());
+    if pids.is_empty() {
+        info!("No running processes found for '{}'.", root_dir.to_string_lossy());
+        return Ok(());
+    }
+
+    info!("Stopping processes {:?} belonging to '{}'.", pids, root_dir.to_string_lossy());
+    stop_processes(pids);
+    Ok(())
+}
+
+/// Sends SIGTERM to each process, and SIGKILL to any which are still running a few seconds later.
+pub fn stop_processes(pids: Vec<u32>) {
+    for pid in &pids {
+        unsafe { libc::kill(*pid as libc::pid_t, libc::SIGTERM) };
+    }
+
+    let sw = simple_stopwatch::Stopwatch::start_new();
+    let mut running: Vec<u32> = pids;
+    while !running.is_empty() && sw.ms() < 3000.0 {
+        std::thread::sleep(Duration::from_millis(100));
+        running.retain(|pid| is_process_running(*pid));
+    }
+
+    for pid in &running {
+        warn!("Process {} did not exit after SIGTERM, sending SIGKILL.", pid);
+        unsafe { libc::kill(*pid as libc::pid_t, libc::SIGKILL) };
+    }
+}
+
+/// Finds the processes in `proc_root` which belong to the application installed at `root_dir`. The `excluded`
+/// processes are skipped, and processes running from `own_mount_dir` (the mount the updater runs from) are
+/// only matched by their APPIMAGE or working dir.
+fn find_package_processes(proc_root: &Path, root_dir: &Path, excluded: &[u32], own_mount_dir: Option<&Path>) -> Vec<u32> {
+    let pids: Vec<u32> = match std::fs::read_dir(proc_root) {
+        Ok(entries) => entries.flatten().filter_map(|e| e.file_name().to_str().and_then(|n| n.parse().ok())).filter(|pid| !excluded.contains(pid)).collect(),
+        Err(e) => {
+            warn!("Unable to list processes in '{}' ({}).", proc_root.to_string_lossy(), e);
+            return Vec::new();
+        }
+    };
+
+    // the AppImage runtime mounts the image at /tmp/.mount_*, and tells the app where with APPIMAGE / APPDIR
+    let mut mount_dirs = Vec::new();
+    let mut matched = Vec::new();
+    for pid in &pids {
+        let proc_dir = proc_root.join(pid.to_string());
+        let environ = std::fs::read(proc_dir.join("environ")).unwrap_or_default();
+        let env_var = |name: &str| {
+            let prefix = format!("{}=", name);
+            environ.split(|b| *b == 0).find_map(|v| v.strip_prefix(prefix.as_bytes())).map(|v| PathBuf::from(String::from_utf8_lossy(v).to_string()))
+        };
+        let is_our_appimage = env_var("APPIMAGE").is_some_and(|p| p == root_dir);
+        if is_our_appimage {
+            if let Some(mount_dir) = env_var("APPDIR").filter(|d| d.file_name().is_some_and(|n| n.to_string_lossy().starts_with(".mount_"))) {
+                if !mount_dirs.contains(&mount_dir) && own_mount_dir != Some(mount_dir.as_path()) {
+                    mount_dirs.push(mount_dir);
+                }
+            }
+        }
+        if is_our_appimage || is_process_in_dir(&proc_dir, root_dir) {
+            matched.push(*pid);
+        }
+    }
+
+    // child processes may have cleared their environment, but still run from the mount point
+    for pid in &pids {
+        let proc_dir = proc_root.join(pid.to_string());
+        if !matched.contains(pid) && mount_dirs.iter().any(|d| is_process_in_dir(&proc_dir, d)) {
+            matched.push(*pid);
+        }
+    }
+    matched.sort();
+    matched
+}
+
+/// The AppImage mount point (`/tmp/.mount_*`) which a path is inside of, if any.
+fn get_mount_dir(path: &Path) -> Option<PathBuf> {
+    path.ancestors().find(|p| p.file_name().is_some_and(|n| n.to_string_lossy().starts_with(".mount_"))).map(Path::to_path_buf)
+}
+
+fn is_process_in_dir(proc_dir: &Path, dir: &Path) -> bool {
+    ["exe", "cwd"].iter().any(|link| std::fs::read_link(proc_dir.join(link)).is_ok_and(|target| target.starts_with(dir)))
+}
+
+fn is_process_running(pid: u32) -> bool {
+    // a zombie has already exited, it is only waiting for its parent to collect the exit code
+    match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        Ok(stat) => stat.rsplit_once(')').is_some_and(|(_, rest)| !rest.trim_start().starts_with('Z')),
+        Err(_) => false,
+    }
+}
+
+pub fn start_package<P: AsRef<Path>>(app: &Manifest, root_dir: P, exe_args: Option<Vec<&str>>, set_env: Option<&str>) -> Result<Child> {
+    let envs: Vec<(&str, &str)> = set_env.map(|env| (env, "true")).into_iter().collect();
+    start_package_with_env(app, root_dir, exe_args, &envs)
+}
+
+pub fn start_package_with_env<P: AsRef<Path>>(app: &Manifest, root_dir: P, exe_args: Option<Vec<&str>>, envs: &[(&str, &str)]) -> Result<Child> {
+    let (exe_path, work_dir) = super::get_main_exe_path(app, root_dir.as_ref());
+    info!("Starting application: {}", exe_path.to_string_lossy());
+    let mut cmd = Process::new(&exe_path);
+    cmd.current_dir(work_dir);
     if let Some(args) = exe_args {
         cmd.args(args);
     }
-    if let Some(env) = set_env {
-        cmd.env(env, "true");
-    }
-    cmd.spawn().map_err(|z| anyhow!("Failed to start_package ({}).", z))?;
-    Ok(())
+    cmd.envs(envs.iter().copied());
+    cmd.spawn().map_err(|z| anyhow!("Failed to start_package ({}).", z))
 }
 
 pub fn detect_current_manifest() -> Result<(PathBuf, Manifest)> {
     let mut manifest_path = std::env::current_exe()?;
     manifest_path.pop();
     manifest_path.push("sq.version");
-    let manifest = load_manifest(&manifest_path)?;
+    let mut manifest = load_manifest(&manifest_path)?;
 
     let path = std::env::var("APPIMAGE")?;
     let path = Path::new(&path).to_path_buf();
     if !path.exists() {
         bail!("Unable to find AppImage at: {}", path.to_string_lossy());
     }
+    super::apply_installed_channel(&path, &mut manifest);
     Ok((path, manifest))
 }
 
@@ -58,3 +171,154 @@ fn load_manifest(nuspec_path: &PathBuf) -> Result<Manifest> {
     }
     bail!("Unable to read nuspec file in current directory.")
 }
+
+/// The identifying fields of an os-release file (see `man os-release`).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OsRelease {
+    pub id: String,
+    pub id_like: Vec<String>,
+    pub version_id: String,
+    pub pretty_name: String,
+}
+
+impl OsRelease {
+    pub fn parse(contents: &str) -> OsRelease {
+        let mut release = OsRelease::default();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.starts_with('#') {
+                continue;
+            }
+            if let Some((key, value)) = line.split_once('=') {
+                let value = value.trim().trim_matches(|c| c == '"' || c == '\'').to_owned();
+                match key.trim() {
+                    "ID" => release.id = value.to_ascii_lowercase(),
+                    "ID_LIKE" => release.id_like = value.split_whitespace().map(|v| v.to_ascii_lowercase()).collect(),
+                    "VERSION_ID" => release.version_id = value,
+                    "PRETTY_NAME" => release.pretty_name = value,
+                    _ => {}
+                }
+            }
+        }
+        release
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<OsRelease> {
+        Ok(OsRelease::parse(&std::fs::read_to_string(path)?))
+    }
+
+    /// Reads the os-release file of this system, or returns an empty release if there is none.
+    pub fn detect() -> OsRelease {
+        ["/etc/os-release", "/usr/lib/os-release"].iter().find_map(|p| OsRelease::from_file(p).ok()).unwrap_or_default()
+    }
+
+    pub fn is_distro(&self, distro: &str) -> bool {
+        self.id.eq_ignore_ascii_case(distro) || self.id_like.iter().any(|d| d.eq_ignore_ascii_case(distro))
+    }
+}
+
+/// The parts of a Linux system which an application can declare a minimum version of.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LinuxSystemInfo {
+    pub kernel_version: String,
+    pub glibc_version: Option<String>,
+    pub os_release: OsRelease,
+}
+
+impl LinuxSystemInfo {
+    pub fn detect() -> Result<LinuxSystemInfo> {
+        let (kernel_version, _) = super::get_uname()?;
+        Ok(LinuxSystemInfo { kernel_version, glibc_version: get_glibc_version(), os_release: OsRelease::detect() })
+    }
+}
+
+impl std::fmt::Display for LinuxSystemInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let distro = if self.os_release.pretty_name.is_empty() { "Unknown distribution" } else { &self.os_release.pretty_name };
+        write!(f, "{}, kernel {}, glibc {}", distro, self.kernel_version, self.glibc_version.as_deref().unwrap_or("unavailable"))
+    }
+}
+
+#[cfg(target_env = "gnu")]
+fn get_glibc_version() -> Option<String> {
+    let version = unsafe { std::ffi::CStr::from_ptr(libc::gnu_get_libc_version()) };
+    Some(version.to_string_lossy().to_string())
+}
+
+#[cfg(not(target_env = "gnu"))]
+fn get_glibc_version() -> Option<String> {
+    None
+}
+
+/// Checks a Linux `os_min_version`, which is a comma separated list of requirements. Each requirement is
+/// `kernel <version>`, `glibc <version>` or `<distro id> <version>` (eg. `ubuntu 20.04`). A bare version is a
+/// kernel requirement. Distro requirements are compared with VERSION_ID, and apply to that distro and to derivatives
+/// which list it in ID_LIKE. A malformed requirement is an error.
+pub fn is_os_requirement_met(requirements: &str, system: &LinuxSystemInfo) -> Result<bool> {
+    for requirement in requirements.split(',').map(|r| r.trim()).filter(|r| !r.is_empty()) {
+        let (key, version) = match requirement.split_once(|c: char| c.is_whitespace() || c == ':' || c == '=') {
+            Some((key, version)) => (key.trim().to_ascii_lowercase(), version.trim()),
+            None => ("kernel".to_owned(), requirement),
+        };
+        if super::parse_version(version).is_err() {
+            bail!("Invalid OS requirement: '{}'", requirement);
+        }
+        let met = match key.as_str() {
+            "kernel" | "linux" => super::is_version_at_least(&system.kernel_version, version),
+            "glibc" => system.glibc_version.as_ref().is_some_and(|v| super::is_version_at_least(v, version)),
+            // rolling releases (eg. arch) have no VERSION_ID, and are always up to date
+            distro => !system.os_release.is_distro(distro) || system.os_release.version_id.is_empty() || super::is_version_at_least(&system.os_release.version_id, version),
+        };
+        if !met {
+            info!("OS requirement '{}' is not met by this system ({}).", requirement, system);
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+#[test]
+fn test_find_package_processes() {
+    use std::os::unix::fs::symlink;
+    let tmp = tempfile::tempdir().unwrap();
+    let proc_root = tmp.path().join("proc");
+    let fake_process = |pid: u32, exe: &str, cwd: &str, environ: &str| {
+        let dir = proc_root.join(pid.to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        symlink(exe, dir.join("exe")).unwrap();
+        symlink(cwd, dir.join("cwd")).unwrap();
+        std::fs::write(dir.join("environ"), environ.replace(';', "\0")).unwrap();
+    };
+
+    let appimage = Path::new("/home/user/Apps/MyApp.AppImage");
+    fake_process(10, "/home/user/Apps/MyApp.AppImage", "/home/user", "");
+    fake_process(11, "/usr/bin/bash", "/home/user", "APPIMAGE=/home/user/Apps/MyApp.AppImage;APPDIR=/tmp/.mount_MyAppX;");
+    fake_process(12, "/tmp/.mount_MyAppX/usr/bin/helper", "/", "");
+    fake_process(13, "/tmp/.mount_Other/usr/bin/other", "/", "APPIMAGE=/home/user/Apps/Other.AppImage;APPDIR=/tmp/.mount_Other;");
+    fake_process(14, "/usr/bin/bash", "/home/user/Apps", "");
+    fake_process(15, "/home/user/Apps/MyApp.AppImage", "/", "");
+    std::fs::create_dir_all(proc_root.join("self")).unwrap();
+    assert_eq!(find_package_processes(&proc_root, appimage, &[15], None), vec![10, 11, 12]);
+    // the runtime which the updater was started from is its ancestor, and the updater's own mount is not swept
+    assert_eq!(find_package_processes(&proc_root, appimage, &[10, 15], None), vec![11, 12]);
+    assert_eq!(find_package_processes(&proc_root, appimage, &[10, 15], Some(Path::new("/tmp/.mount_MyAppX"))), vec![11]);
+    assert_eq!(get_mount_dir(Path::new("/tmp/.mount_MyAppX/usr/bin/UpdateNix")), Some(PathBuf::from("/tmp/.mount_MyAppX")));
+    assert_eq!(get_mount_dir(Path::new("/opt/MyApp/UpdateNix")), None);
+
+    let install_dir = Path::new("/opt/MyApp");
+    fake_process(20, "/opt/MyApp/MyApp", "/", "");
+    fake_process(21, "/usr/bin/python3", "/opt/MyApp/data", "");
+    fake_process(22, "/opt/MyAppOther/MyApp", "/opt/MyAppOther", "");
+    assert_eq!(find_package_processes(&proc_root, install_dir, &[1], None), vec![20, 21]);
+}
+
+#[test]
+fn test_force_stop_package_stops_processes_in_install_dir() {
+    use wait_timeout::ChildExt;
+    let tmp = tempfile::tempdir().unwrap();
+    let mut child = Process::new("sleep").arg("30").current_dir(tmp.path()).spawn().unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+    force_stop_package(tmp.path()).unwrap();
+    let status = child.wait_timeout(Duration::from_secs(5)).unwrap().expect("process should have been stopped");
+    assert!(!status.success());
+}
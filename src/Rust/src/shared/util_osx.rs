@@ -2,7 +2,12 @@ use crate::shared::bundle;
 
 use super::bundle::Manifest;
 use anyhow::{anyhow, bail, Result};
-use std::{path::Path, path::PathBuf, process::Command as Process, time::Duration};
+use std::{
+    path::Path,
+    path::PathBuf,
+    process::{Child, Command as Process},
+    time::Duration,
+};
 
 pub fn wait_for_pid_to_exit(pid: u32, ms_to_wait: u32) -> Result<()> {
     info!("Waiting {}ms for process ({}) to exit.", ms_to_wait, pid);
@@ -32,7 +37,14 @@ pub fn force_stop_package<P: AsRef<Path>>(root_dir: P) -> Result<()> {
     Ok(())
 }
 
-pub fn start_package<P: AsRef<Path>>(_app: &Manifest, root_dir: P, exe_args: Option<Vec<&str>>, set_env: Option<&str>) -> Result<()> {
+pub fn start_package<P: AsRef<Path>>(app: &Manifest, root_dir: P, exe_args: Option<Vec<&str>>, set_env: Option<&str>) -> Result<Child> {
+    let envs: Vec<(&str, &str)> = set_env.map(|env| (env, "true")).into_iter().collect();
+    start_package_with_env(app, root_dir, exe_args, &envs)
+}
+
+/// Starts the bundle with `open`. The returned child is the `open` process, which exits as soon as the
+/// application has been launched.
+pub fn start_package_with_env<P: AsRef<Path>>(_app: &Manifest, root_dir: P, exe_args: Option<Vec<&str>>, envs: &[(&str, &str)]) -> Result<Child> {
     let root_dir = root_dir.as_ref().to_string_lossy().to_string();
     let mut args = vec!["-n", &root_dir];
     if let Some(a) = exe_args {
@@ -42,18 +54,15 @@ pub fn start_package<P: AsRef<Path>>(_app: &Manifest, root_dir: P, exe_args: Opt
     info!("Starting application: open {:?}", args);
     let mut psi = Process::new("/usr/bin/open");
     psi.args(args);
-    if let Some(env) = set_env {
-        psi.env(env, "true");
-    }
-    psi.spawn().map_err(|z| anyhow!("Failed to start application ({}).", z))?;
-    Ok(())
+    psi.envs(envs.iter().copied());
+    psi.spawn().map_err(|z| anyhow!("Failed to start application ({}).", z))
 }
 
 pub fn detect_manifest_from_update_path(update_exe: &PathBuf) -> Result<(PathBuf, Manifest)> {
     let mut manifest_path = update_exe.clone();
     manifest_path.pop();
     manifest_path.push("sq.version");
-    let manifest = load_manifest(&manifest_path)?;
+    let mut manifest = load_manifest(&manifest_path)?;
 
     let my_path = std::env::current_exe()?;
     let my_path = my_path.to_string_lossy();
@@ -67,7 +76,9 @@ pub fn detect_manifest_from_update_path(update_exe: &PathBuf) -> Result<(PathBuf
 
     debug!("Detected Root: {}", root_dir);
     debug!("Detected AppId: {}", manifest.id);
-    Ok((Path::new(&root_dir).to_path_buf(), manifest))
+    let root_dir = Path::new(&root_dir).to_path_buf();
+    super::apply_installed_channel(&root_dir, &mut manifest);
+    Ok((root_dir, manifest))
 }
 
 pub fn detect_current_manifest() -> Result<(PathBuf, Manifest)> {
@@ -84,6 +95,17 @@ fn load_manifest(nuspec_path: &PathBuf) -> Result<Manifest> {
     bail!("Unable to read nuspec file in current directory.")
 }
 
+/// The product version of macOS (eg. 14.1.2).
+pub fn get_macos_version() -> Result<String> {
+    let name = std::ffi::CString::new("kern.osproductversion")?;
+    let mut buf = [0u8; 64];
+    let mut len = buf.len();
+    if unsafe { libc::sysctlbyname(name.as_ptr(), buf.as_mut_ptr() as *mut libc::c_void, &mut len, std::ptr::null_mut(), 0) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(String::from_utf8_lossy(&buf[..len]).trim_end_matches('\0').trim().to_owned())
+}
+
 #[test]
 #[ignore]
 fn test_start_and_stop_package() {
@@ -0,0 +1,245 @@
+use super::{
+    bundle::Manifest,
+    hooks::{HookPayload, HookResult, HOOK_PAYLOAD_ENV},
+    ExitCodeError,
+};
+use anyhow::{anyhow, Result};
+use std::{
+    ffi::OsStr,
+    io::Read,
+    os::unix::{ffi::OsStrExt, process::CommandExt},
+    path::{Path, PathBuf},
+    process::{Command as Process, Stdio},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+use wait_timeout::ChildExt;
+
+pub fn run_hook(app: &Manifest, root_path: &Path, payload: &HookPayload, timeout_secs: u64) -> HookResult {
+    let sw = simple_stopwatch::Stopwatch::start_new();
+    let hook_name = payload.hook.as_str();
+    let (main_exe_path, work_dir) = get_main_exe_path(app, root_path);
+    info!("Running {} hook ({})...", hook_name, main_exe_path.to_string_lossy());
+    let ver_string = app.version.to_string();
+    let args = vec![hook_name, &ver_string];
+    let payload_json = payload.to_json();
+    let result = run_process_and_wait(&main_exe_path, args, &work_dir, &[(HOOK_PAYLOAD_ENV, &payload_json)], Some(Duration::from_secs(timeout_secs)));
+    match &result {
+        Ok(output) => {
+            log_hook_output(output);
+            info!("Hook executed successfully (took {}ms)", sw.ms());
+        }
+        Err(e) => warn!("Error running hook {}: {} (took {}ms)", hook_name, e, sw.ms()),
+    }
+    HookResult::from_process_result(&result)
+}
+
+/// The executable of an installed application, and the dir it should be started in: the AppImage itself
+/// (or the main exe of a directory install) on Linux, and the main executable inside the .app bundle on macOS.
+pub fn get_main_exe_path(app: &Manifest, root_path: &Path) -> (PathBuf, PathBuf) {
+    #[cfg(target_os = "linux")]
+    let exe_path = if root_path.is_dir() { root_path.join(&app.main_exe) } else { root_path.to_path_buf() };
+    #[cfg(target_os = "macos")]
+    let exe_path = root_path.join("Contents").join("MacOS").join(&app.main_exe);
+    let work_dir = exe_path.parent().map(|p| p.to_path_buf()).unwrap_or_else(|| root_path.to_path_buf());
+    (exe_path, work_dir)
+}
+
+fn log_hook_output(output: &str) {
+    for line in output.lines().filter(|l| !l.trim().is_empty()) {
+        info!("    [hook] {}", line);
+    }
+}
+
+/// The space available to unprivileged users on the volume containing `path`.
+pub fn get_free_space<P: AsRef<Path>>(path: P) -> Result<u64> {
+    let c_path = std::ffi::CString::new(path.as_ref().as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    // the field widths differ between linux and macos
+    #[allow(clippy::useless_conversion)]
+    Ok(u64::from(stat.f_bavail) * u64::from(stat.f_frsize))
+}
+
+/// The kernel release and machine hardware name, as reported by `uname -r` and `uname -m`.
+pub fn get_uname() -> Result<(String, String)> {
+    let mut uts: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let field = |f: &[libc::c_char]| unsafe { std::ffi::CStr::from_ptr(f.as_ptr()) }.to_string_lossy().to_string();
+    Ok((field(&uts.release), field(&uts.machine)))
+}
+
+/// The architecture of this machine, using the names written to package manifests (eg. x64, arm64).
+pub fn get_machine_architecture() -> String {
+    let machine = get_uname().map(|(_, m)| m).unwrap_or_else(|_| std::env::consts::ARCH.to_owned());
+    match machine.as_str() {
+        "x86_64" | "amd64" => "x64".to_owned(),
+        "aarch64" | "arm64" => "arm64".to_owned(),
+        "i386" | "i486" | "i586" | "i686" => "x86".to_owned(),
+        _ => machine,
+    }
+}
+
+/// Whether a package built for `architecture` (eg. x64, arm64) can run on this machine.
+pub fn is_cpu_architecture_supported(architecture: &str) -> bool {
+    let machine = get_machine_architecture();
+    let architecture = architecture.to_ascii_lowercase();
+    // apple silicon runs x64 apps via rosetta
+    architecture.is_empty() || architecture == machine || (cfg!(target_os = "macos") && machine == "arm64" && architecture == "x64")
+}
+
+/// Compares the leading numeric components of two versions (eg. `5.15.0-91-generic` or `2.35`),
+/// treating missing components as zero.
+pub fn is_version_at_least(actual: &str, required: &str) -> bool {
+    fn parts(version: &str) -> Vec<u32> {
+        let numeric = version.trim().split(|c: char| !c.is_ascii_digit() && c != '.').next().unwrap_or_default();
+        numeric.split('.').map_while(|p| p.parse::<u32>().ok()).collect()
+    }
+    let (actual, required) = (parts(actual), parts(required));
+    for i in 0..actual.len().max(required.len()) {
+        let (a, r) = (actual.get(i).copied().unwrap_or(0), required.get(i).copied().unwrap_or(0));
+        if a != r {
+            return a > r;
+        }
+    }
+    true
+}
+
+/// Runs a process and waits for it to exit, returning its combined stdout and stderr.
+/// The process is killed if it does not exit within the timeout.
+pub fn run_process_and_wait<S, P>(exe: S, args: Vec<&str>, work_dir: P, envs: &[(&str, &str)], timeout: Option<Duration>) -> Result<String>
+where
+    S: AsRef<OsStr>,
+    P: AsRef<Path>,
+{
+    // the process gets its own group, so anything it starts (eg. the app inside an AppImage) is killed with it on timeout
+    let mut cmd = Process::new(exe)
+        .args(args)
+        .current_dir(work_dir)
+        .envs(envs.iter().copied())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()?;
+
+    // the pipes are drained on separate threads, so a chatty process can't fill them and block forever
+    fn drain<R: Read + Send + 'static>(pipe: Option<R>) -> mpsc::Receiver<Vec<u8>> {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut buf);
+            }
+            let _ = tx.send(buf);
+        });
+        rx
+    }
+    let stdout = drain(cmd.stdout.take());
+    let stderr = drain(cmd.stderr.take());
+    let started = Instant::now();
+
+    let status = match timeout {
+        Some(t) => match cmd.wait_timeout(t)? {
+            Some(status) => status,
+            None => {
+                unsafe { libc::killpg(cmd.id() as libc::pid_t, libc::SIGKILL) };
+                let _ = cmd.kill();
+                let _ = cmd.wait();
+                // the output is not collected, a detached descendant may still be holding the pipes open
+                return Err(anyhow!("Process timed out after {:?}", t));
+            }
+        },
+        None => cmd.wait()?,
+    };
+
+    // anything the process left running in the background would hold the pipes open, and is not allowed to outlive it
+    unsafe { libc::killpg(cmd.id() as libc::pid_t, libc::SIGKILL) };
+    let remaining = timeout.map(|t| t.saturating_sub(started.elapsed())).unwrap_or(OUTPUT_GRACE_PERIOD).max(OUTPUT_GRACE_PERIOD);
+    let output = collect_output(stdout, stderr, remaining);
+    if !status.success() {
+        warn!("Process exited with non-zero exit code: {}", status.code().unwrap_or(0));
+        if !output.is_empty() {
+            warn!("    Output:\n{}", output);
+        }
+        return Err(ExitCodeError { code: status.code().unwrap_or(-1) }.into());
+    }
+    Ok(output)
+}
+
+/// How long to wait for the output of a process after it has exited, if its timeout has already run out.
+const OUTPUT_GRACE_PERIOD: Duration = Duration::from_millis(500);
+
+/// Collects whatever the process wrote. A descendant which escaped its process group may still hold the pipes open,
+/// so this gives up at the deadline rather than waiting for them to close.
+fn collect_output(stdout: mpsc::Receiver<Vec<u8>>, stderr: mpsc::Receiver<Vec<u8>>, timeout: Duration) -> String {
+    let deadline = Instant::now() + timeout;
+    let mut buf = stdout.recv_timeout(deadline.saturating_duration_since(Instant::now())).unwrap_or_default();
+    buf.extend(stderr.recv_timeout(deadline.saturating_duration_since(Instant::now())).unwrap_or_default());
+    String::from_utf8_lossy(&buf).to_string()
+}
+
+#[test]
+fn test_is_version_at_least() {
+    assert!(is_version_at_least("5.15.0-91-generic", "5.4"));
+    assert!(is_version_at_least("2.35", "2.35.0"));
+    assert!(is_version_at_least("14.1.2", "14"));
+    assert!(!is_version_at_least("4.19.0", "5.4"));
+    assert!(!is_version_at_least("2.31", "2.35"));
+    assert!(!is_version_at_least("11.7", "12.0"));
+}
+
+#[test]
+fn test_run_process_and_wait_captures_output_and_times_out() {
+    let tmp = std::env::temp_dir();
+    let output = run_process_and_wait("/bin/sh", vec!["-c", "echo out; echo err 1>&2"], &tmp, &[], Some(Duration::from_secs(10))).unwrap();
+    assert_eq!(output, "out\nerr\n");
+
+    assert!(run_process_and_wait("/bin/sh", vec!["-c", "exit 3"], &tmp, &[], None).is_err());
+
+    let started = std::time::Instant::now();
+    let result = run_process_and_wait("/bin/sh", vec!["-c", "sleep 10"], &tmp, &[], Some(Duration::from_millis(200)));
+    assert!(result.unwrap_err().to_string().contains("timed out"));
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn test_run_process_and_wait_does_not_wait_for_background_children() {
+    let tmp = std::env::temp_dir();
+    // the background child inherits the pipes, and would keep them open for 30 seconds
+    let started = std::time::Instant::now();
+    let output = run_process_and_wait("/bin/sh", vec!["-c", "echo started; sleep 30 &"], &tmp, &[], Some(Duration::from_secs(10))).unwrap();
+    assert_eq!(output, "started\n");
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    // a child which escapes the process group is not killed, but is not waited on past the deadline either
+    #[cfg(target_os = "linux")]
+    {
+        let started = std::time::Instant::now();
+        let _ = run_process_and_wait("/bin/sh", vec!["-c", "setsid sleep 10 &"], &tmp, &[], Some(Duration::from_secs(2)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_run_hook_passes_payload_and_maps_exit_code() {
+    use std::os::unix::fs::PermissionsExt;
+    let tmp = tempfile::tempdir().unwrap();
+    let exe = tmp.path().join("MyApp.AppImage");
+    let out = tmp.path().join("payload.json");
+    std::fs::write(&exe, format!("#!/bin/sh\nprintf '%s' \"$VELOPACK_HOOK_PAYLOAD\" > '{}'\nexit 10\n", out.to_string_lossy())).unwrap();
+    std::fs::set_permissions(&exe, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let mut app = Manifest::default();
+    app.version = semver::Version::new(2, 0, 0);
+    let payload = HookPayload::new("--veloapp-updated", Some(&semver::Version::new(1, 0, 0)), &app, &exe, false);
+    assert_eq!(run_hook(&app, &exe, &payload, 10), HookResult::Abort);
+    let received: HookPayload = serde_json::from_str(&std::fs::read_to_string(&out).unwrap()).unwrap();
+    assert_eq!(received, payload);
+}
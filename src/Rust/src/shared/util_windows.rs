@@ -5,7 +5,7 @@ use std::{
     collections::HashMap,
     fs,
     path::{Path, PathBuf},
-    process::Command as Process,
+    process::{Child, Command as Process},
 };
 use windows::Win32::System::ProcessStatus::EnumProcesses;
 use windows::Win32::UI::WindowsAndMessaging::AllowSetForegroundWindow;
@@ -15,6 +15,13 @@ use winsafe::{self as w, co, prelude::*};
 
 use super::bundle::{self, EntryNameInfo, Manifest};
 
+/// The space available to the current user on the volume containing `path`.
+pub fn get_free_space<P: AsRef<Path>>(path: P) -> Result<u64> {
+    let mut free_space: u64 = 0;
+    w::GetDiskFreeSpaceEx(Some(&path.as_ref().to_string_lossy()), None, None, Some(&mut free_space))?;
+    Ok(free_space)
+}
+
 pub fn wait_for_pid_to_exit(pid: u32, ms_to_wait: u32) -> Result<()> {
     info!("Waiting {}ms for process ({}) to exit.", ms_to_wait, pid);
     let handle = w::HPROCESS::OpenProcess(co::PROCESS::SYNCHRONIZE, false, pid)?;
@@ -170,7 +177,12 @@ fn _force_stop_package<P: AsRef<Path>>(root_dir: P) -> Result<()> {
     Ok(())
 }
 
-pub fn start_package<P: AsRef<Path>>(app: &Manifest, root_dir: P, exe_args: Option<Vec<&str>>, set_env: Option<&str>) -> Result<()> {
+pub fn start_package<P: AsRef<Path>>(app: &Manifest, root_dir: P, exe_args: Option<Vec<&str>>, set_env: Option<&str>) -> Result<Child> {
+    let envs: Vec<(&str, &str)> = set_env.map(|env| (env, "true")).into_iter().collect();
+    start_package_with_env(app, root_dir, exe_args, &envs)
+}
+
+pub fn start_package_with_env<P: AsRef<Path>>(app: &Manifest, root_dir: P, exe_args: Option<Vec<&str>>, envs: &[(&str, &str)]) -> Result<Child> {
     let root_dir = root_dir.as_ref().to_path_buf();
     let current = app.get_current_path(&root_dir);
     let exe = app.get_main_exe_path(&root_dir);
@@ -185,9 +197,9 @@ pub fn start_package<P: AsRef<Path>>(app: &Manifest, root_dir: P, exe_args: Opti
     if let Some(args) = exe_args {
         psi.args(args);
     }
-    if let Some(env) = set_env {
-        debug!("Setting environment variable: {}={}", env, "true");
-        psi.env(env, "true");
+    for (key, value) in envs {
+        debug!("Setting environment variable: {}={}", key, value);
+        psi.env(key, value);
     }
 
     info!("About to launch: '{}' in dir '{}'", exe_to_execute.to_string_lossy(), current);
@@ -195,13 +207,14 @@ pub fn start_package<P: AsRef<Path>>(app: &Manifest, root_dir: P, exe_args: Opti
     let child = psi.spawn().map_err(|z| anyhow!("Failed to start application ({}).", z))?;
     let _ = unsafe { AllowSetForegroundWindow(child.id()) };
 
-    Ok(())
+    Ok(child)
 }
 
 pub fn detect_manifest_from_update_path(update_exe: &PathBuf) -> Result<(PathBuf, Manifest)> {
     let root_path = update_exe.parent().unwrap().to_path_buf();
-    let app = find_manifest_from_root_dir(&root_path)
+    let mut app = find_manifest_from_root_dir(&root_path)
         .map_err(|m| anyhow!("Unable to read application manifest ({}). Is this a properly installed application?", m))?;
+    super::apply_installed_channel(&root_path, &mut app);
     info!("Loaded manifest for application: {}", app.id);
     info!("Root Directory: {}", root_path.to_string_lossy());
     Ok((root_path, app))
@@ -6,7 +6,7 @@ extern crate log;
 
 use anyhow::{anyhow, bail, Result};
 use clap::{arg, value_parser, ArgMatches, Command};
-use std::{env, path::PathBuf};
+use std::{env, path::PathBuf, time::Duration};
 use velopack::*;
 
 #[rustfmt::skip]
@@ -20,6 +20,10 @@ fn root_command() -> Command {
         .arg(arg!(-w --wait "Wait for the parent process to terminate before applying the update"))
         .arg(arg!(--waitPid <PID> "Wait for the specified process to terminate before applying the update").value_parser(value_parser!(u32)))
         .arg(arg!(-p --package <FILE> "Update package to apply").value_parser(value_parser!(PathBuf)))
+        .arg(arg!(--events <TARGET> "Write progress events as JSON lines to stdout ('-'), a file, a named pipe or a unix socket"))
+        .arg(arg!(--dryRun "Validate the package and report the planned changes, without applying it").alias("dry-run"))
+        .arg(arg!(--allowDowngrade "Allow applying a package which is older than the installed version").alias("allow-downgrade"))
+        .arg(arg!(--healthCheck <SECONDS> "Wait for the restarted application to signal it started successfully, and roll back if it does not").alias("health-check").value_parser(value_parser!(u64)))
         .arg(arg!([EXE_ARGS] "Arguments to pass to the started executable. Must be preceeded by '--'.").required(false).last(true).num_args(0..))
     )
     .subcommand(Command::new("patch")
@@ -28,20 +32,51 @@ fn root_command() -> Command {
         .arg(arg!(--patch <FILE> "The Zstd patch to apply to the old file").required(true).value_parser(value_parser!(PathBuf)))
         .arg(arg!(--output <FILE> "The file to create with the patch applied").required(true).value_parser(value_parser!(PathBuf)))
     )
+    .subcommand(Command::new("switch-channel")
+        .about("Switches the application to a different release channel and applies the newest package on that channel")
+        .arg(arg!(<CHANNEL> "The name of the channel to switch to"))
+        .arg(arg!(--allowDowngrade "Allow switching to a channel whose newest package is older than the installed version").alias("allow-downgrade"))
+        .arg(arg!(--norestart "Do not restart the application after switching"))
+        .arg(arg!(-w --wait "Wait for the parent process to terminate before switching"))
+        .arg(arg!(--waitPid <PID> "Wait for the specified process to terminate before switching").value_parser(value_parser!(u32)))
+        .arg(arg!([EXE_ARGS] "Arguments to pass to the started executable. Must be preceeded by '--'.").required(false).last(true).num_args(0..))
+    )
+    .subcommand(Command::new("prune")
+        .about("Removes old packages, applied deltas and incomplete downloads from the packages directory")
+        .arg(arg!(--dryRun "Only report what would be removed").alias("dry-run"))
+        .arg(arg!(--keep <COUNT> "The number of full packages to keep").value_parser(value_parser!(usize)))
+    )
+    .subcommand(Command::new("rollback")
+        .about("Restores the previously installed version of the application")
+        .arg(arg!(--norestart "Do not restart the application after the rollback"))
+        .arg(arg!(-w --wait "Wait for the parent process to terminate before rolling back"))
+        .arg(arg!(--waitPid <PID> "Wait for the specified process to terminate before rolling back").value_parser(value_parser!(u32)))
+        .arg(arg!([EXE_ARGS] "Arguments to pass to the started executable. Must be preceeded by '--'.").required(false).last(true).num_args(0..))
+    )
     .subcommand(Command::new("get-version")
         .about("Prints the current version of the application")
     )
+    .subcommand(Command::new("uninstall")
+        .about("Remove all app shortcuts, files, and registry entries.")
+        .long_flag_alias("uninstall")
+        .arg(arg!(--keepUserData "Keep the update state (eg. the selected channel), only remove the application itself").alias("keep-user-data").hide(cfg!(target_os = "windows")))
+    )
     .arg(arg!(--verbose "Print debug messages to console / log").global(true))
     .arg(arg!(--nocolor "Disable colored output").hide(true).global(true))
     .arg(arg!(-s --silent "Don't show any prompts / dialogs").global(true))
     .arg(arg!(-l --log <PATH> "Override the default log file location").global(true).value_parser(value_parser!(PathBuf)))
+    .arg(arg!(--logFormat <FORMAT> "Format of the log file, 'text' or 'json'").alias("log-format").global(true).value_parser(value_parser!(logging::LogFormat)))
+    .arg(arg!(--consoleLogFormat <FORMAT> "Format of the console output, 'text' or 'json'").alias("console-log-format").global(true).value_parser(value_parser!(logging::LogFormat)))
+    .arg(arg!(--packagesDir <DIR> "Override where downloaded packages are located").alias("packages-dir").global(true).value_parser(value_parser!(PathBuf)))
+    .arg(arg!(--stagingDir <DIR> "Override where updates are staged before being applied").alias("staging-dir").global(true).value_parser(value_parser!(PathBuf)))
+    .arg(arg!(--tempDir <DIR> "Override where temporary files are written").alias("temp-dir").global(true).value_parser(value_parser!(PathBuf)))
+    .arg(arg!(--retainVersions <COUNT> "The number of previous versions to keep for rollback").alias("retain-versions").global(true).value_parser(value_parser!(usize)))
     .arg(arg!(--forceLatest "Legacy argument").hide(true).global(true))
     .arg(arg!(-r --restart "Legacy argument").hide(true).global(true))
     .ignore_errors(true)
     .disable_help_subcommand(true)
     .flatten_help(true);
 
-    #[cfg(target_os = "windows")]
     let cmd = cmd.subcommand(Command::new("start")
         .about("Starts the currently installed version of the application")
         .arg(arg!(-a --args <ARGS> "Legacy args format").aliases(vec!["processStartArgs", "process-start-args"]).hide(true).allow_hyphen_values(true).num_args(1))
@@ -52,11 +87,6 @@ fn root_command() -> Command {
         .long_flag_aliases(vec!["processStart", "processStartAndWait"])
     );
 
-    #[cfg(target_os = "windows")]
-    let cmd = cmd.subcommand(Command::new("uninstall")
-        .about("Remove all app shortcuts, files, and registry entries.")
-        .long_flag_alias("uninstall")
-    );
     cmd
 }
 
@@ -125,14 +155,29 @@ fn main() -> Result<()> {
     let verbose = get_flag_or_false(&matches, "verbose");
     let silent = get_flag_or_false(&matches, "silent");
     let nocolor = get_flag_or_false(&matches, "nocolor");
-    let log_file = matches.get_one("log");
+    let log_file = matches.get_one::<PathBuf>("log").cloned().unwrap_or_else(logging::default_log_location);
+
+    // when progress events are written to stdout, console logging is disabled so the stream stays parseable
+    let events_target = if subcommand == "apply" { subcommand_matches.try_get_one::<String>("events").unwrap_or(None) } else { None };
+    let console = !events_target.map(|t| shared::events::is_stdout_target(t)).unwrap_or(false);
 
     dialogs::set_silent(silent);
-    if let Some(log_file) = log_file {
-        logging::setup_logging("update", Some(&log_file), true, verbose, nocolor)?;
-    } else {
-        let default_log_file = logging::default_log_location();
-        logging::setup_logging("update", Some(&default_log_file), true, verbose, nocolor)?;
+    let file_format = matches.get_one::<logging::LogFormat>("logFormat").copied().unwrap_or_default();
+    let console_format = matches.get_one::<logging::LogFormat>("consoleLogFormat").copied().unwrap_or_default();
+    logging::setup_logging("update", Some(&log_file), console, verbose, nocolor, console_format, file_format)?;
+
+    if let Some(target) = events_target {
+        shared::events::set_event_output(target).map_err(|e| anyhow!("Unable to open event output '{}' ({}).", target, e))?;
+    }
+
+    // relative paths are resolved before the working directory is changed
+    for (id, kind) in [("packagesDir", shared::LocationKind::Packages), ("stagingDir", shared::LocationKind::Staging), ("tempDir", shared::LocationKind::Temp)] {
+        if let Some(dir) = matches.get_one::<PathBuf>(id) {
+            shared::set_location_override(kind, dir);
+        }
+    }
+    if let Some(count) = matches.get_one::<usize>("retainVersions") {
+        commands::set_rollback_retain_count(*count);
     }
 
     // change working directory to the parent directory of the exe
@@ -146,20 +191,27 @@ fn main() -> Result<()> {
     info!("    CWD: {}", env::current_dir()?.to_string_lossy());
     info!("    Verbose: {}", verbose);
     info!("    Silent: {}", silent);
-    info!("    Log File: {:?}", log_file);
+    info!("    Log File: {}", log_file.to_string_lossy());
+
+    if let Ok((_, app)) = shared::detect_current_manifest() {
+        logging::set_log_field("app_id", &app.id);
+        logging::set_log_field("from_version", &app.version.to_string());
+    }
 
     let result = match subcommand {
-        #[cfg(target_os = "windows")]
         "uninstall" => uninstall(subcommand_matches).map_err(|e| anyhow!("Uninstall error: {}", e)),
-        #[cfg(target_os = "windows")]
         "start" => start(subcommand_matches).map_err(|e| anyhow!("Start error: {}", e)),
         "apply" => apply(subcommand_matches).map_err(|e| anyhow!("Apply error: {}", e)),
         "patch" => patch(subcommand_matches).map_err(|e| anyhow!("Patch error: {}", e)),
+        "prune" => prune(subcommand_matches).map_err(|e| anyhow!("Prune error: {}", e)),
+        "rollback" => rollback(subcommand_matches).map_err(|e| anyhow!("Rollback error: {}", e)),
+        "switch-channel" => switch_channel(subcommand_matches).map_err(|e| anyhow!("Switch channel error: {}", e)),
         _ => bail!("Unknown subcommand. Try `--help` for more information."),
     };
 
     if let Err(e) = result {
         error!("{}", e);
+        info!("The full log can be found at: {}", log_file.to_string_lossy());
         return Err(e.into());
     }
 
@@ -184,20 +236,87 @@ fn apply(matches: &ArgMatches) -> Result<()> {
     let package = matches.get_one::<PathBuf>("package");
     let exe_args: Option<Vec<&str>> = matches.get_many::<String>("EXE_ARGS").map(|v| v.map(|f| f.as_str()).collect());
     let wait = get_op_wait(&matches);
+    let health_check = matches.try_get_one::<u64>("healthCheck").unwrap_or(None).map(|s| Duration::from_secs(*s));
+    let dry_run = get_flag_or_false(&matches, "dryRun");
+    let allow_downgrade = get_flag_or_false(&matches, "allowDowngrade");
 
     info!("Command: Apply");
     info!("    Restart: {:?}", restart);
     info!("    Wait: {:?}", wait);
     info!("    Package: {:?}", package);
+    info!("    Events: {:?}", matches.try_get_one::<String>("events").unwrap_or(None));
+    info!("    Health Check: {:?}", health_check);
+    info!("    Dry Run: {:?}", dry_run);
+    info!("    Allow Downgrade: {:?}", allow_downgrade);
     info!("    Exe Args: {:?}", exe_args);
 
+    if dry_run {
+        let (root_path, app) = shared::detect_current_manifest()?;
+        return commands::apply_dry_run(&root_path, &app, package, allow_downgrade).map(|_| ());
+    }
+    let (root_path, app, _lock) = lock_and_recover()?;
+    commands::apply(&root_path, &app, restart, wait, package, exe_args, health_check, allow_downgrade, true)
+}
+
+/// Takes the operation lock, and then finishes or undoes an apply which was interrupted (eg. by a power loss). The manifest
+/// is detected again afterwards, since the recovery may have changed the installed version.
+fn lock_and_recover() -> Result<(PathBuf, shared::bundle::Manifest, shared::OperationLock)> {
+    let (_, app) = shared::detect_current_manifest()?;
+    let lock = shared::retry_io(|| shared::acquire_operation_lock(&app))?;
+    match commands::recover_interrupted_apply() {
+        Ok(Some(outcome)) => info!("Recovered an interrupted apply ({:?}).", outcome),
+        Ok(None) => {}
+        Err(e) => warn!("Failed to recover an interrupted apply ({}).", e),
+    }
     let (root_path, app) = shared::detect_current_manifest()?;
-    #[cfg(target_os = "windows")]
-    let _mutex = shared::retry_io(|| windows::create_global_mutex(&app))?;
-    commands::apply(&root_path, &app, restart, wait, package, exe_args, true)
+    Ok((root_path, app, lock))
+}
+
+fn prune(matches: &ArgMatches) -> Result<()> {
+    let dry_run = get_flag_or_false(&matches, "dryRun");
+    let keep = matches.try_get_one::<usize>("keep").unwrap_or(None).map(|v| v.to_owned()).unwrap_or(commands::PACKAGES_RETAIN_COUNT);
+
+    info!("Command: Prune");
+    info!("    Dry Run: {:?}", dry_run);
+    info!("    Keep: {:?}", keep);
+
+    let (root_path, app) = shared::detect_current_manifest()?;
+    let _lock = shared::retry_io(|| shared::acquire_operation_lock(&app))?;
+    commands::prune(&root_path, &app, keep, dry_run)
+}
+
+fn rollback(matches: &ArgMatches) -> Result<()> {
+    let restart = !get_flag_or_false(&matches, "norestart");
+    let exe_args: Option<Vec<&str>> = matches.get_many::<String>("EXE_ARGS").map(|v| v.map(|f| f.as_str()).collect());
+    let wait = get_op_wait(&matches);
+
+    info!("Command: Rollback");
+    info!("    Restart: {:?}", restart);
+    info!("    Wait: {:?}", wait);
+    info!("    Exe Args: {:?}", exe_args);
+
+    let (root_path, app, _lock) = lock_and_recover()?;
+    commands::rollback(&root_path, &app, restart, wait, exe_args, true)
+}
+
+fn switch_channel(matches: &ArgMatches) -> Result<()> {
+    let channel = matches.get_one::<String>("CHANNEL").ok_or_else(|| anyhow!("A channel name is required."))?;
+    let allow_downgrade = get_flag_or_false(&matches, "allowDowngrade");
+    let restart = !get_flag_or_false(&matches, "norestart");
+    let exe_args: Option<Vec<&str>> = matches.get_many::<String>("EXE_ARGS").map(|v| v.map(|f| f.as_str()).collect());
+    let wait = get_op_wait(&matches);
+
+    info!("Command: Switch Channel");
+    info!("    Channel: {:?}", channel);
+    info!("    Allow Downgrade: {:?}", allow_downgrade);
+    info!("    Restart: {:?}", restart);
+    info!("    Wait: {:?}", wait);
+    info!("    Exe Args: {:?}", exe_args);
+
+    let (root_path, app, _lock) = lock_and_recover()?;
+    commands::switch_channel(&root_path, &app, channel, allow_downgrade, restart, wait, exe_args, true)
 }
 
-#[cfg(target_os = "windows")]
 fn start(matches: &ArgMatches) -> Result<()> {
     let legacy_args = matches.get_one::<String>("args");
     let exe_name = matches.get_one::<String>("EXE_NAME");
@@ -214,15 +333,17 @@ fn start(matches: &ArgMatches) -> Result<()> {
     }
 
     let (_root_path, app) = shared::detect_current_manifest()?;
-    let _mutex = shared::retry_io(|| windows::create_global_mutex(&app))?;
+    let _lock = shared::retry_io(|| shared::acquire_operation_lock(&app))?;
     commands::start(wait, exe_name, exe_args, legacy_args)
 }
 
-#[cfg(target_os = "windows")]
-fn uninstall(_matches: &ArgMatches) -> Result<()> {
+fn uninstall(matches: &ArgMatches) -> Result<()> {
+    let keep_user_data = get_flag_or_false(&matches, "keepUserData");
     info!("Command: Uninstall");
+    info!("    Keep User Data: {:?}", keep_user_data);
     let (root_path, app) = shared::detect_current_manifest()?;
-    commands::uninstall(&root_path, &app, true)
+    let _lock = shared::retry_io(|| shared::acquire_operation_lock(&app))?;
+    commands::uninstall(&root_path, &app, true, keep_user_data)
 }
 
 #[cfg(target_os = "windows")]
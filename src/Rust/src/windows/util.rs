@@ -1,4 +1,7 @@
-use crate::shared;
+use crate::shared::{
+    self,
+    hooks::{HookPayload, HookResult, HOOK_PAYLOAD_ENV},
+};
 use anyhow::{anyhow, bail, Result};
 use normpath::PathExt;
 use std::{
@@ -20,20 +23,24 @@ use windows::{
 };
 use winsafe::{self as w, co};
 
-pub fn run_hook(app: &shared::bundle::Manifest, root_path: &PathBuf, hook_name: &str, timeout_secs: u64) {
+pub fn run_hook(app: &shared::bundle::Manifest, root_path: &PathBuf, payload: &HookPayload, timeout_secs: u64) -> HookResult {
     let sw = simple_stopwatch::Stopwatch::start_new();
+    let hook_name = payload.hook.as_str();
     let current_path = app.get_current_path(&root_path);
     let main_exe_path = app.get_main_exe_path(&root_path);
     info!("Running {} hook...", hook_name);
     let ver_string = app.version.to_string();
     let args = vec![hook_name, &ver_string];
-    if let Err(e) = run_process_no_console_and_wait(&main_exe_path, args, &current_path, Some(Duration::from_secs(timeout_secs))) {
+    let payload_json = payload.to_json();
+    let result = run_process_no_console_and_wait(&main_exe_path, args, &current_path, &[(HOOK_PAYLOAD_ENV, &payload_json)], Some(Duration::from_secs(timeout_secs)));
+    if let Err(e) = &result {
         warn!("Error running hook {}: {} (took {}ms)", hook_name, e, sw.ms());
     } else {
         info!("Hook executed successfully (took {}ms)", sw.ms());
     }
     // in case the hook left running processes
     let _ = shared::force_stop_package(&root_path);
+    HookResult::from_process_result(&result)
 }
 
 pub struct MutexDropGuard {
@@ -232,7 +239,7 @@ pub fn test_os_returns_true_for_everything_on_windows_11_and_below() {
 }
 
 const CREATE_NO_WINDOW: u32 = 0x08000000;
-pub fn run_process_no_console_and_wait<S, P>(exe: S, args: Vec<&str>, work_dir: P, timeout: Option<Duration>) -> Result<String>
+pub fn run_process_no_console_and_wait<S, P>(exe: S, args: Vec<&str>, work_dir: P, envs: &[(&str, &str)], timeout: Option<Duration>) -> Result<String>
 where
     S: AsRef<OsStr>,
     P: AsRef<Path>,
@@ -240,6 +247,7 @@ where
     let mut cmd = Process::new(exe)
         .args(args)
         .current_dir(work_dir)
+        .envs(envs.iter().copied())
         .stdout(std::process::Stdio::piped())
         .stderr(std::process::Stdio::piped())
         .creation_flags(CREATE_NO_WINDOW)
@@ -259,7 +267,7 @@ where
             if stdout_buf.len() > 0 {
                 warn!("    Output:\n{}", String::from_utf8_lossy(&stdout_buf));
             }
-            return Err(anyhow!("Process exited with non-zero exit code: {}", status.code().unwrap_or(0)));
+            return Err(shared::ExitCodeError { code: status.code().unwrap_or(0) }.into());
         }
 
         Ok(String::from_utf8_lossy(&stdout_buf).to_string())
@@ -0,0 +1,58 @@
+#![cfg(target_os = "linux")]
+
+use std::{fs, path::PathBuf, process::Command};
+
+fn find_cdylib() -> PathBuf {
+    // integration tests run from target/<profile>/deps, next to the library (which may also be uplifted to target/<profile>)
+    let mut path = std::env::current_exe().unwrap();
+    path.pop();
+    let lib = path.join("libvelopack.so");
+    if lib.exists() {
+        return lib;
+    }
+    path.pop();
+    path.join("libvelopack.so")
+}
+
+#[test]
+pub fn test_c_api_checks_and_downloads_updates() {
+    let crate_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let lib = find_cdylib();
+    assert!(lib.exists(), "cdylib not found at {}", lib.to_string_lossy());
+
+    let tmp = tempfile::tempdir().unwrap();
+    let source_dir = tmp.path().join("releases");
+    let packages_dir = tmp.path().join("packages");
+    fs::create_dir_all(&source_dir).unwrap();
+
+    let manifest_path = tmp.path().join("sq.version");
+    fs::write(&manifest_path, "<?xml version=\"1.0\"?><package><metadata><id>CApiApp</id><version>1.0.0</version><channel>linux</channel></metadata></package>").unwrap();
+
+    let content = "package contents";
+    fs::write(source_dir.join("CApiApp-2.0.0-full.nupkg"), content).unwrap();
+    let feed = format!(
+        r#"{{"Assets":[{{"PackageId":"CApiApp","Version":"2.0.0","Type":"Full","FileName":"CApiApp-2.0.0-full.nupkg","SHA1":"{}","Size":{}}}]}}"#,
+        sha1_smol::Sha1::from(content).digest(),
+        content.len()
+    );
+    fs::write(source_dir.join("releases.linux.json"), feed).unwrap();
+
+    let exe = tmp.path().join("test_capi");
+    let status = Command::new("cc")
+        .arg(crate_dir.join("tests").join("capi").join("test_capi.c"))
+        .arg("-I")
+        .arg(crate_dir.join("include"))
+        .arg(&lib)
+        .arg(format!("-Wl,-rpath,{}", lib.parent().unwrap().to_string_lossy()))
+        .arg("-o")
+        .arg(&exe)
+        .status()
+        .expect("Failed to run the C compiler (cc)");
+    assert!(status.success());
+
+    let output = Command::new(&exe).arg(&source_dir).arg(&packages_dir).arg(&manifest_path).output().unwrap();
+    println!("{}", String::from_utf8_lossy(&output.stdout));
+    eprintln!("{}", String::from_utf8_lossy(&output.stderr));
+    assert!(output.status.success());
+    assert_eq!(fs::read_to_string(packages_dir.join("CApiApp-2.0.0-full.nupkg")).unwrap(), content);
+}
@@ -0,0 +1,89 @@
+/*
+ * Exercises the C API against a local release feed.
+ * usage: test_capi <source_dir> <packages_dir> <manifest_path>
+ */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "velopack.h"
+
+#define CHECK(expr)                                                                                 \
+    do {                                                                                            \
+        vpkc_error_t _err = (expr);                                                                 \
+        if (_err != VPKC_OK) {                                                                      \
+            fprintf(stderr, "%s failed with %d: %s\n", #expr, (int)_err, vpkc_get_last_error());    \
+            return 1;                                                                               \
+        }                                                                                           \
+    } while (0)
+
+#define EXPECT(cond)                                                                                \
+    do {                                                                                            \
+        if (!(cond)) {                                                                              \
+            fprintf(stderr, "expectation failed: %s\n", #cond);                                     \
+            return 1;                                                                               \
+        }                                                                                           \
+    } while (0)
+
+static void on_progress(void *user_data, int16_t progress)
+{
+    *(int16_t *)user_data = progress;
+}
+
+int main(int argc, char **argv)
+{
+    if (argc != 4) {
+        fprintf(stderr, "usage: test_capi <source_dir> <packages_dir> <manifest_path>\n");
+        return 2;
+    }
+
+    vpkc_locator_config_t locator = {
+        .root_app_dir = argv[2],
+        .update_exe_path = "/nonexistent/UpdateNix",
+        .packages_dir = argv[2],
+        .manifest_path = argv[3],
+    };
+
+    vpkc_update_manager_t *manager = NULL;
+
+    /* invalid arguments are reported, not crashed on */
+    EXPECT(vpkc_new_update_manager_with_locator(NULL, NULL, &locator, &manager) == VPKC_ERROR_INVALID_ARGUMENT);
+    EXPECT(strlen(vpkc_get_last_error()) > 0);
+    EXPECT(manager == NULL);
+
+    CHECK(vpkc_new_update_manager_with_locator(argv[1], NULL, &locator, &manager));
+    EXPECT(strlen(vpkc_get_last_error()) == 0);
+
+    char *app_id = NULL;
+    CHECK(vpkc_get_app_id(manager, &app_id));
+    EXPECT(strcmp(app_id, "CApiApp") == 0);
+    vpkc_free_string(app_id);
+
+    char *version = NULL;
+    CHECK(vpkc_get_current_version(manager, &version));
+    EXPECT(strcmp(version, "1.0.0") == 0);
+    vpkc_free_string(version);
+
+    vpkc_update_info_t *update = NULL;
+    CHECK(vpkc_check_for_updates(manager, &update));
+    EXPECT(update != NULL);
+    EXPECT(!vpkc_update_info_is_downgrade(update));
+
+    char *update_version = NULL;
+    CHECK(vpkc_update_info_get_version(update, &update_version));
+    EXPECT(strcmp(update_version, "2.0.0") == 0);
+    vpkc_free_string(update_version);
+
+    int16_t progress = -1;
+    CHECK(vpkc_download_updates(manager, update, on_progress, &progress));
+    EXPECT(progress == 100);
+
+    /* the updater does not exist, so launching it must fail cleanly */
+    const char *args[] = { "--hello" };
+    EXPECT(vpkc_wait_exit_then_apply_updates(manager, update, true, true, args, 1) == VPKC_ERROR_FAILED);
+
+    vpkc_free_update_info(update);
+    vpkc_free_update_manager(manager);
+    printf("ok\n");
+    return 0;
+}
@@ -28,7 +28,7 @@ pub fn test_install_apply_uninstall() {
 
     let tmp_dir = tempdir().unwrap();
     let tmp_buf = tmp_dir.path().to_path_buf();
-    commands::install(Some(&nupkg), Some(&tmp_buf)).unwrap();
+    commands::install(Some(&nupkg), Some(&tmp_buf), false).unwrap();
 
     assert!(lnk_path.exists());
     assert!(tmp_buf.join("Update.exe").exists());
@@ -41,12 +41,12 @@ pub fn test_install_apply_uninstall() {
 
     let pkg_name_apply = "AvaloniaCrossPlat-1.0.15-win-full.nupkg";
     let nupkg_apply = fixtures.join(pkg_name_apply);
-    commands::apply(&root_dir, &app, false, shared::OperationWait::NoWait, Some(&nupkg_apply), None, false).unwrap();
+    commands::apply(&root_dir, &app, false, shared::OperationWait::NoWait, Some(&nupkg_apply), None, None, false, false).unwrap();
 
     let (root_dir, app) = shared::detect_manifest_from_update_path(&tmp_buf.join("Update.exe")).unwrap();
     assert!(semver::Version::parse("1.0.15").unwrap() == app.version);
 
-    commands::uninstall(&root_dir, &app, false).unwrap();
+    commands::uninstall(&root_dir, &app, false, false).unwrap();
     assert!(!tmp_buf.join("current").exists());
     assert!(tmp_buf.join(".dead").exists());
     assert!(!lnk_path.exists());
@@ -62,7 +62,7 @@ pub fn test_install_preserve_symlinks() {
 
     let tmp_dir = tempdir().unwrap();
     let tmp_buf = tmp_dir.path().to_path_buf();
-    commands::install(Some(&nupkg), Some(&tmp_buf)).unwrap();
+    commands::install(Some(&nupkg), Some(&tmp_buf), false).unwrap();
 
     assert!(tmp_buf.join("current").join("actual").join("file.txt").exists());
     assert!(tmp_buf.join("current").join("other").join("syml").exists());
@@ -0,0 +1,51 @@
+#![cfg(target_os = "linux")]
+
+mod common;
+use common::*;
+use velopack::shared::{is_os_requirement_met, LinuxSystemInfo, OsRelease};
+
+fn system(os_release: &str, kernel: &str, glibc: Option<&str>) -> LinuxSystemInfo {
+    let os_release = OsRelease::from_file(find_fixtures().join("os-release").join(os_release)).unwrap();
+    LinuxSystemInfo { kernel_version: kernel.to_owned(), glibc_version: glibc.map(|g| g.to_owned()), os_release }
+}
+
+#[test]
+pub fn test_os_release_fixtures_are_parsed() {
+    let ubuntu = system("ubuntu-22.04", "5.15.0-91-generic", Some("2.35"));
+    assert_eq!(ubuntu.os_release.id, "ubuntu");
+    assert_eq!(ubuntu.os_release.id_like, vec!["debian"]);
+    assert_eq!(ubuntu.os_release.version_id, "22.04");
+    assert_eq!(ubuntu.os_release.pretty_name, "Ubuntu 22.04.3 LTS");
+    assert!(ubuntu.os_release.is_distro("debian"));
+
+    let arch = system("arch", "6.6.1-arch1-1", Some("2.38"));
+    assert_eq!(arch.os_release.id, "arch");
+    assert!(arch.os_release.version_id.is_empty());
+}
+
+#[test]
+pub fn test_linux_os_requirements() {
+    let ubuntu = system("ubuntu-22.04", "5.15.0-91-generic", Some("2.35"));
+    let fedora = system("fedora-39", "6.5.6-300.fc39.x86_64", Some("2.38"));
+    let arch = system("arch", "6.6.1-arch1-1", None);
+
+    assert!(is_os_requirement_met("5.4", &ubuntu).unwrap());
+    assert!(!is_os_requirement_met("6.1", &ubuntu).unwrap());
+    assert!(is_os_requirement_met("kernel 6.1, glibc 2.38", &fedora).unwrap());
+    assert!(!is_os_requirement_met("glibc 2.36", &ubuntu).unwrap());
+    assert!(!is_os_requirement_met("glibc 2.17", &arch).unwrap());
+
+    // distro requirements apply to that distro and its derivatives
+    assert!(is_os_requirement_met("ubuntu 20.04", &ubuntu).unwrap());
+    assert!(!is_os_requirement_met("ubuntu 24.04", &ubuntu).unwrap());
+    assert!(is_os_requirement_met("debian 13", &ubuntu).unwrap());
+    assert!(!is_os_requirement_met("debian 23", &ubuntu).unwrap());
+    assert!(is_os_requirement_met("debian 23", &fedora).unwrap());
+    assert!(is_os_requirement_met("ubuntu 24.04, fedora 38", &fedora).unwrap());
+    assert!(!is_os_requirement_met("ubuntu 24.04, fedora 40", &fedora).unwrap());
+    assert!(is_os_requirement_met("arch 2024", &arch).unwrap());
+
+    assert!(is_os_requirement_met("glibc", &ubuntu).is_err());
+    assert!(is_os_requirement_met("ubuntu 22.04-lts", &ubuntu).is_err());
+    assert!(is_os_requirement_met("kernel 5.4, glibc two", &ubuntu).is_err());
+}